@@ -1,9 +1,15 @@
-use std::{cell::RefCell, rc::Weak, sync::mpsc::Sender};
+use std::{
+    cell::{Cell, RefCell},
+    ops::Range,
+    sync::mpsc::Sender,
+};
 
 use crate::{
+    cpu::decode::InstructionCache,
+    memcheck::MemCheck,
     memory::{create_memory, Memory, MEMORY_END_ADDR, MEMORY_START_ADDR},
     modules::ModuleManager,
-    registers::DRCRA, // socket::send_addr_value_u8,
+    registers::{ABWCR, DRCRA, WCRL}, // socket::send_addr_value_u8,
 };
 use anyhow::{bail, Result};
 
@@ -11,6 +17,11 @@ pub const VENCTOR_START_ADDR: u32 = 0;
 pub const VENCTOR_END_ADDR: u32 = 0xff;
 pub const VENCTOR_SIZE: usize = (VENCTOR_END_ADDR - VENCTOR_START_ADDR + 1) as usize;
 
+// On-chip flash ROM following the vector table
+pub const ROM_START_ADDR: u32 = 0x100;
+pub const ROM_END_ADDR: u32 = 0x7ffff;
+pub const ROM_SIZE: usize = (ROM_END_ADDR - ROM_START_ADDR + 1) as usize;
+
 pub const AREA0_START_ADDR: u32 = 0;
 pub const AREA0_END_ADDR: u32 = 0x1fffff;
 pub const AREA0_SIZE: usize = (AREA0_END_ADDR - AREA0_START_ADDR + 1) as usize;
@@ -58,47 +69,196 @@ pub const IO_REGISTERS2_EMC1_SIZE: usize = (IO_REGISTERS2_EMC1_END_ADDR - IO_REG
 
 pub const IO_PORT_SIZE: usize = 11;
 
+// A write of the cpu to a watched range, the bytes of a word or a longword in one value
+#[derive(Clone)]
+pub struct WatchHit {
+    pub addr: u32,
+    pub size: usize,
+    pub old: u32,
+    pub new: u32,
+}
+
+// The DMA controller and the 16 bit timer are on the 16 bit bus, the other modules on the 8 bit bus
+pub fn is_16bit_io_register(addr: u32) -> bool {
+    matches!(addr, 0xffff20..=0xffff3f | 0xffff60..=0xffff7f)
+}
+
+pub fn is_io_register(addr: u32) -> bool {
+    matches!(
+        addr,
+        IO_REGISTERS1_START_ADDR..=IO_REGISTERS1_END_ADDR | IO_REGISTERS2_EMC1_START_ADDR..=IO_REGISTERS2_EMC1_END_ADDR
+    )
+}
+
 #[derive(Clone)]
 pub struct Bus {
     pub message_tx: Option<Sender<String>>,
-    pub module_manager: Weak<RefCell<ModuleManager>>,
+    // registers of the peripherals are read and written through its register map
+    pub module_manager: RefCell<ModuleManager>,
     pub cpu_state_sum: usize,
     pub memory: Memory,
     pub exception_handling_vector: Box<[u8]>,
+    pub rom: Box<[u8]>,
     pub dram: Box<[u8]>,
     pub io_registrs1: Box<[u8]>,
     pub io_registrs2: Box<[u8]>,
     pub io_port_in: [u8; IO_PORT_SIZE],
+    pub watch_ranges: Vec<Range<u32>>,
+    pub watch_hits: Vec<WatchHit>,
+    // (addr, value, is_write) of each access while tracing
+    pub access_log: Option<RefCell<Vec<(u32, u8, bool)>>>,
+    pub memcheck: Option<RefCell<MemCheck>>,
+    // bus cycles of the accesses, a word takes two of them on an 8 bit bus
+    pub bus_cycles: Cell<usize>,
+    pub instruction_cache: InstructionCache,
+    // print the sent messages, set by the config of the cpu
+    pub print_messages: bool,
+    // report accesses to the DRAM before its refresh is configured, set by the config of the cpu
+    pub refresh_check: bool,
 }
 
 impl Bus {
-    pub fn new(module_manager: Weak<RefCell<ModuleManager>>) -> Self {
-        Bus {
+    pub fn new() -> Self {
+        let mut bus = Bus {
             message_tx: None,
-            module_manager,
+            module_manager: RefCell::new(ModuleManager::new()),
             cpu_state_sum: 0,
             memory: create_memory(),
             exception_handling_vector: vec![0; VENCTOR_SIZE].into_boxed_slice(),
+            rom: vec![0; ROM_SIZE].into_boxed_slice(),
             dram: vec![0; AREA2_SIZE].into_boxed_slice(),
             io_registrs1: vec![0; IO_REGISTERS1_SIZE].into_boxed_slice(),
             io_registrs2: vec![0; IO_REGISTERS2_EMC1_SIZE].into_boxed_slice(),
             io_port_in: [0; IO_PORT_SIZE],
-        }
+            watch_ranges: Vec::new(),
+            watch_hits: Vec::new(),
+            access_log: None,
+            memcheck: None,
+            bus_cycles: Cell::new(0),
+            instruction_cache: InstructionCache::new(),
+            print_messages: false,
+            refresh_check: false,
+        };
+        bus.reset_system_registers();
+        bus
     }
 
     pub fn write(&mut self, addr: u32, value: u8) -> Result<()> {
+        self.write_access(addr, [value])
+    }
+
+    /// Writes a word. Bit 0 of the address is ignored as the cpu does.
+    pub fn write_w(&mut self, addr: u32, value: u16) -> Result<()> {
+        self.write_access(addr & !1, value.to_be_bytes())
+    }
+
+    /// Writes a longword, the upper word first. Bit 0 of the address is ignored as the cpu does.
+    pub fn write_l(&mut self, addr: u32, value: u32) -> Result<()> {
+        self.write_access(addr & !1, value.to_be_bytes())
+    }
+
+    pub fn read(&self, addr: u32) -> Result<u8> {
+        Ok(self.read_access::<1>(addr)?[0])
+    }
+
+    /// Reads a word. Bit 0 of the address is ignored as the cpu does.
+    pub fn read_w(&self, addr: u32) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_access(addr & !1)?))
+    }
+
+    /// Reads a longword, the upper word first. Bit 0 of the address is ignored as the cpu does.
+    pub fn read_l(&self, addr: u32) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_access(addr & !1)?))
+    }
+
+    // One access of the cpu, the watchpoints and the modules see it once
+    fn write_access<const N: usize>(&mut self, addr: u32, values: [u8; N]) -> Result<()> {
+        let watched = !self.watch_ranges.is_empty() && self.is_watched(addr, N);
+        let old = if watched { self.peek_value(addr, N) } else { 0 };
+        // the modules see the bytes of their registers in one write
+        let claimed = if addr >= IO_REGISTERS1_START_ADDR {
+            self.module_manager.get_mut().write_registers(addr, &values, self.cpu_state_sum)
+        } else {
+            0
+        };
+        for (i, value) in values.iter().enumerate() {
+            self.write_byte(addr + i as u32, *value, claimed & (1 << i) != 0)?;
+        }
+        if is_io_register(addr) {
+            self.module_manager.get_mut().finish_access();
+        }
+        self.bus_cycles.set(self.bus_cycles.get() + self.access_cycles(addr, N));
+        if watched {
+            let new = values.iter().fold(0, |value, byte| (value << 8) | *byte as u32);
+            self.watch_hits.push(WatchHit { addr, size: N, old, new });
+        }
+        Ok(())
+    }
+
+    fn read_access<const N: usize>(&self, addr: u32) -> Result<[u8; N]> {
+        let mut values = [0; N];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.read_byte(addr + i as u32)?;
+        }
+        if is_io_register(addr) {
+            self.module_manager.borrow_mut().finish_access();
+        }
+        self.bus_cycles.set(self.bus_cycles.get() + self.access_cycles(addr, N));
+        Ok(values)
+    }
+
+    /// Bus cycles of an access of the size in bytes, which the states of the accesses of the cpu are counted by.
+    pub fn access_cycles(&self, addr: u32, size: usize) -> usize {
+        if size == 1 || self.is_8bit_bus(addr) {
+            size
+        } else {
+            size / 2
+        }
+    }
+
+    /// Whether the data bus of the address is 8 bits wide, which splits a word access into two byte accesses.
+    /// The width of the external areas is set by ABWCR.
+    pub fn is_8bit_bus(&self, addr: u32) -> bool {
+        match addr {
+            // the on-chip RAM is in area 7 while it is disabled
+            MEMORY_START_ADDR..=MEMORY_END_ADDR | VENCTOR_START_ADDR..=ROM_END_ADDR if addr <= ROM_END_ADDR || self.is_ram_enabled() => {
+                false
+            }
+            _ if is_io_register(addr) => !is_16bit_io_register(addr),
+            _ => match Bus::get_area_index(addr) {
+                Ok(area_index) => (self.peek(ABWCR).unwrap_or(0xff) >> area_index) & 1 == 1,
+                Err(_) => true,
+            },
+        }
+    }
+
+    // A byte of an access, `claimed` if a module took it
+    fn write_byte(&mut self, addr: u32, value: u8, claimed: bool) -> Result<()> {
+        let addr = self.decode_access(addr)?;
+        self.instruction_cache.invalidate(addr);
+        if let Some(log) = &self.access_log {
+            log.borrow_mut().push((addr, value, true));
+        }
+        if let Some(memcheck) = &self.memcheck {
+            memcheck.borrow_mut().on_write(addr);
+        }
         match addr {
             VENCTOR_START_ADDR..=VENCTOR_END_ADDR => self.exception_handling_vector[addr as usize] = value,
             IO_REGISTERS1_START_ADDR..=IO_REGISTERS1_END_ADDR => {
+                // the bus width, the wait states and the DRAM areas the cached instructions are fetched with
+                if matches!(addr, ABWCR..=WCRL | DRCRA) {
+                    self.instruction_cache.clear();
+                }
                 // I/O Port DDR value if changed
                 if addr >= 0xfee000 && addr <= 0xfee00a {
                     let previous = self.io_registrs1[(addr - IO_REGISTERS1_START_ADDR) as usize];
                     if value != previous {
+                        // the address lines and the chip selects are output by the DDR
+                        self.instruction_cache.clear();
                         self.on_write_ddr(addr, value)?;
                     }
-                } else {
+                } else if !claimed && !self.write_system_register(addr, value) {
                     self.io_registrs1[(addr - IO_REGISTERS1_START_ADDR) as usize] = value;
-                    (*self.module_manager.upgrade().unwrap()).borrow_mut().write_registers(addr, value);
                 }
             }
             AREA2_START_ADDR..=AREA2_END_ADDR => self.dram[(addr - AREA2_START_ADDR) as usize] = value,
@@ -110,9 +270,8 @@ impl Bus {
                     if value != previous {
                         self.on_write_dr(addr, value)?;
                     }
-                } else {
+                } else if !claimed {
                     self.io_registrs2[(addr - IO_REGISTERS2_EMC1_START_ADDR) as usize] = value;
-                    (*self.module_manager.upgrade().unwrap()).borrow_mut().write_registers(addr, value);
                 }
             }
             _ => bail!("Invalid address [0x{:x}]", addr),
@@ -120,21 +279,90 @@ impl Bus {
         Ok(())
     }
 
-    pub fn read(&self, addr: u32) -> Result<u8> {
+    fn read_byte(&self, addr: u32) -> Result<u8> {
+        let addr = self.decode_access(addr)?;
+        // reads of the registers of the peripherals can have side effects
+        let value = match self.read_module_register(addr) {
+            Some(value) => value,
+            None => self.peek(addr)?,
+        };
+        if let Some(log) = &self.access_log {
+            log.borrow_mut().push((addr, value, false));
+        }
+        if let Some(memcheck) = &self.memcheck {
+            memcheck.borrow_mut().on_read(addr);
+        }
+        Ok(value)
+    }
+
+    /// Reads without recording the access. Used by the emulator itself (e.g. wait state calculation).
+    /// The memories are seen as they are, without the chip selects and the RAM enable of the bus controller.
+    pub fn peek(&self, addr: u32) -> Result<u8> {
         match addr {
             VENCTOR_START_ADDR..=VENCTOR_END_ADDR => return Ok(self.exception_handling_vector[addr as usize]),
+            ROM_START_ADDR..=ROM_END_ADDR => return Ok(self.rom[(addr - ROM_START_ADDR) as usize]),
             IO_REGISTERS1_START_ADDR..=IO_REGISTERS1_END_ADDR => {
+                if let Some(value) = self.peek_module_register(addr) {
+                    return Ok(value);
+                }
                 return Ok(self.io_registrs1[(addr - IO_REGISTERS1_START_ADDR) as usize]);
             }
             AREA2_START_ADDR..=AREA2_END_ADDR => return Ok(self.dram[(addr - AREA2_START_ADDR) as usize]),
             MEMORY_START_ADDR..=MEMORY_END_ADDR => return Ok(self.memory[(addr - MEMORY_START_ADDR) as usize]),
             IO_REGISTERS2_EMC1_START_ADDR..=IO_REGISTERS2_EMC1_END_ADDR => {
-                return Ok(self.io_registrs2[(addr - IO_REGISTERS2_EMC1_START_ADDR) as usize])
+                if let Some(value) = self.peek_module_register(addr) {
+                    return Ok(value);
+                }
+                return Ok(self.io_registrs2[(addr - IO_REGISTERS2_EMC1_START_ADDR) as usize]);
             }
             _ => bail!("Invalid address [0x{:x}]", addr),
         }
     }
 
+    // Registers of the modules are counted up to the current state count when accessed
+    fn read_module_register(&self, addr: u32) -> Option<u8> {
+        if addr < IO_REGISTERS1_START_ADDR {
+            return None;
+        }
+        self.module_manager.borrow_mut().read_registers(addr, self.cpu_state_sum)
+    }
+
+    fn peek_module_register(&self, addr: u32) -> Option<u8> {
+        self.module_manager.borrow().peek_registers(addr, self.cpu_state_sum)
+    }
+
+    /// Writes a program image to the memories, including the ROM which can not be written by the program.
+    pub fn load(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let Some(end) = u32::try_from(data.len()).ok().and_then(|len| addr.checked_add(len)) else {
+            bail!("Can not load {} bytes to the address [0x{:x}]", data.len(), addr);
+        };
+        for (i, value) in data.iter().enumerate() {
+            let addr = addr + i as u32;
+            self.instruction_cache.invalidate(addr);
+            match addr {
+                VENCTOR_START_ADDR..=VENCTOR_END_ADDR => self.exception_handling_vector[addr as usize] = *value,
+                ROM_START_ADDR..=ROM_END_ADDR => self.rom[(addr - ROM_START_ADDR) as usize] = *value,
+                AREA2_START_ADDR..=AREA2_END_ADDR => self.dram[(addr - AREA2_START_ADDR) as usize] = *value,
+                MEMORY_START_ADDR..=MEMORY_END_ADDR => self.memory[(addr - MEMORY_START_ADDR) as usize] = *value,
+                _ => bail!("Can not load to the address [0x{:x}]", addr),
+            }
+        }
+        if let Some(memcheck) = &self.memcheck {
+            memcheck.borrow_mut().mark_initialized(addr..end);
+        }
+        Ok(())
+    }
+
+    fn is_watched(&self, addr: u32, size: usize) -> bool {
+        self.watch_ranges
+            .iter()
+            .any(|range| range.start < addr + size as u32 && addr < range.end)
+    }
+
+    fn peek_value(&self, addr: u32, size: usize) -> u32 {
+        (addr..addr + size as u32).fold(0, |value, addr| (value << 8) | self.peek(addr).unwrap_or(0) as u32)
+    }
+
     pub fn get_area_index(target_addr: u32) -> Result<u8> {
         match target_addr {
             AREA0_START_ADDR..=AREA0_END_ADDR => {
@@ -166,7 +394,7 @@ impl Bus {
     }
 
     pub fn check_dram_area(&self, area_index: u8) -> Result<bool> {
-        let register = self.read(DRCRA)? >> 5;
+        let register = self.peek(DRCRA)? >> 5;
 
         match area_index {
             2 => {
@@ -197,23 +425,20 @@ impl Bus {
 
 #[cfg(test)]
 mod tests {
-    use std::{cell::RefCell, rc::Rc};
-
     use crate::{
         bus::{
-            IO_REGISTERS1_END_ADDR, IO_REGISTERS1_SIZE, IO_REGISTERS1_START_ADDR, IO_REGISTERS2_EMC1_END_ADDR, IO_REGISTERS2_EMC1_SIZE,
-            IO_REGISTERS2_EMC1_START_ADDR,
+            AREA2_START_ADDR, IO_REGISTERS1_END_ADDR, IO_REGISTERS1_SIZE, IO_REGISTERS1_START_ADDR, IO_REGISTERS2_EMC1_END_ADDR,
+            IO_REGISTERS2_EMC1_SIZE, IO_REGISTERS2_EMC1_START_ADDR,
         },
         cpu::Cpu,
         memory::{MEMORY_END_ADDR, MEMORY_SIZE, MEMORY_START_ADDR},
-        modules::ModuleManager,
+        registers::{ABWCR, P8DDR},
     };
 
     use super::Bus;
 
     fn create_bus() -> Bus {
-        let module_manager = Rc::new(RefCell::new(ModuleManager::new()));
-        Bus::new(Rc::downgrade(&module_manager))
+        Bus::new()
     }
 
     #[test]
@@ -279,4 +504,67 @@ mod tests {
         bus.io_registrs2[IO_REGISTERS2_EMC1_SIZE - 1] = 0xff;
         assert_eq!(bus.read(IO_REGISTERS2_EMC1_END_ADDR).unwrap(), 0xff)
     }
+
+    #[test]
+    fn test_module_registers() {
+        // TCR0 of the 8-bit timer: overflow interrupt, φ/8
+        const TCR0: u32 = 0xffff80;
+        const TCSR0: u32 = 0xffff82;
+        let mut bus = create_bus();
+        bus.write(TCR0, 0b0010_0001).unwrap();
+        assert_eq!(bus.io_registrs2[(TCR0 - IO_REGISTERS2_EMC1_START_ADDR) as usize], 0);
+
+        bus.cpu_state_sum = 8 * 256;
+        assert_eq!(bus.peek(TCSR0).unwrap(), 0b1110_0000);
+        // the overflow flag is cleared only after it is read
+        bus.write(TCSR0, 0).unwrap();
+        assert_eq!(bus.peek(TCSR0).unwrap(), 0b1110_0000);
+        assert_eq!(bus.read(TCSR0).unwrap(), 0b1110_0000);
+        bus.write(TCSR0, 0).unwrap();
+        assert_eq!(bus.read(TCSR0).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_word_access() {
+        let mut bus = create_bus();
+        // bit 0 of the address is ignored
+        bus.write_w(MEMORY_START_ADDR + 1, 0x1234).unwrap();
+        assert_eq!(bus.memory[0..2], [0x12, 0x34]);
+        bus.write_l(MEMORY_START_ADDR + 5, 0x89abcdef).unwrap();
+        assert_eq!(bus.memory[4..8], [0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(bus.read_w(MEMORY_START_ADDR + 1).unwrap(), 0x1234);
+        assert_eq!(bus.read_l(MEMORY_START_ADDR + 4).unwrap(), 0x89abcdef);
+        assert_eq!(bus.read(MEMORY_START_ADDR + 7).unwrap(), 0xef);
+    }
+
+    #[test]
+    fn test_bus_cycles() {
+        let mut bus = create_bus();
+        // the on-chip memory is on the 16 bit bus
+        bus.read_l(MEMORY_START_ADDR).unwrap();
+        assert_eq!(bus.bus_cycles.get(), 2);
+
+        // area 2 on the 8 bit bus, CS2 is output
+        bus.write(P8DDR, 0x04).unwrap();
+        bus.write(ABWCR, 0x04).unwrap();
+        bus.bus_cycles.set(0);
+        bus.read_w(AREA2_START_ADDR).unwrap();
+        bus.read(AREA2_START_ADDR).unwrap();
+        assert_eq!(bus.bus_cycles.get(), 3);
+        bus.write(ABWCR, 0).unwrap();
+        bus.bus_cycles.set(0);
+        bus.write_l(AREA2_START_ADDR, 0).unwrap();
+        assert_eq!(bus.bus_cycles.get(), 2);
+    }
+
+    #[test]
+    fn test_watch_word_write() {
+        let mut bus = create_bus();
+        bus.watch_ranges = vec![(MEMORY_START_ADDR + 1)..(MEMORY_START_ADDR + 2)];
+        bus.write_w(MEMORY_START_ADDR, 0x1234).unwrap();
+        bus.write_l(MEMORY_START_ADDR + 4, 0).unwrap();
+        assert_eq!(bus.watch_hits.len(), 1);
+        assert_eq!((bus.watch_hits[0].addr, bus.watch_hits[0].size), (MEMORY_START_ADDR, 2));
+        assert_eq!((bus.watch_hits[0].old, bus.watch_hits[0].new), (0, 0x1234));
+    }
 }
@@ -0,0 +1,205 @@
+use crate::{
+    bus::{is_io_register, Bus, IO_REGISTERS1_START_ADDR, ROM_END_ADDR, VENCTOR_START_ADDR},
+    memory::{MEMORY_END_ADDR, MEMORY_START_ADDR},
+    registers::{ADRCR, BCR, BRCR, CSCR, MDCR, P8DDR, SYSCR},
+};
+use anyhow::{bail, Result};
+
+/// Mode 5: the on-chip ROM is enabled, the address space is 16MB and the external areas start with the 8 bit bus.
+pub const OPERATING_MODE: u8 = 5;
+
+const P1DDR: u32 = 0xfee000;
+const P2DDR: u32 = 0xfee001;
+const P5DDR: u32 = 0xfee004;
+
+// SYSCR
+pub const SYSCR_SSBY: u8 = 0x80;
+pub const SYSCR_UE: u8 = 0x08;
+pub const SYSCR_NMIEG: u8 = 0x04;
+pub const SYSCR_RAME: u8 = 0x01;
+
+// BRCR, A20 is output when A20E is cleared
+const BRCR_A20E: u8 = 0x10;
+
+// Reset values in mode 5 and the reserved bits, which are read as 1 and not changed by writes
+const SYSTEM_REGISTERS: [(u32, u8, u8); 6] = [
+    // the mode pins are read in MDS2-MDS0, the register can not be written
+    (MDCR, 0xc0 | OPERATING_MODE, 0xff),
+    // UE and RAME
+    (SYSCR, 0x09, 0x00),
+    // A23-A20 are ports
+    (BRCR, 0xfe, 0x0e),
+    (ADRCR, 0xff, 0xfe),
+    // CS7-CS4 are not output
+    (CSCR, 0x0f, 0x0f),
+    (BCR, 0xc6, 0x04),
+];
+
+impl Bus {
+    fn register(&self, addr: u32) -> u8 {
+        self.io_registrs1[(addr - IO_REGISTERS1_START_ADDR) as usize]
+    }
+
+    /// Sets the bus controller and the system control registers to their values after a reset.
+    pub fn reset_system_registers(&mut self) {
+        for (addr, value, _) in SYSTEM_REGISTERS {
+            self.io_registrs1[(addr - IO_REGISTERS1_START_ADDR) as usize] = value;
+        }
+    }
+
+    /// Writes a bus controller or system control register, keeping its reserved bits.
+    /// Returns false if the address is not one of them.
+    pub fn write_system_register(&mut self, addr: u32, value: u8) -> bool {
+        let Some((_, _, reserved)) = SYSTEM_REGISTERS.iter().find(|(register, _, _)| *register == addr) else {
+            return false;
+        };
+        let previous = self.register(addr);
+        let value = (previous & reserved) | (value & !reserved);
+        self.io_registrs1[(addr - IO_REGISTERS1_START_ADDR) as usize] = value;
+        // the on-chip RAM and the devices seen at the addresses change, so the decoded instructions are stale
+        if previous != value {
+            self.instruction_cache.clear();
+        }
+        true
+    }
+
+    pub fn syscr(&self) -> u8 {
+        self.register(SYSCR)
+    }
+
+    /// The on-chip RAM is enabled by RAME of SYSCR, its addresses are in the external area 7 when it is disabled.
+    pub fn is_ram_enabled(&self) -> bool {
+        self.syscr() & SYSCR_RAME != 0
+    }
+
+    /// Whether the chip select of the external area is output, by P8DDR for CS0-CS3 and by CSCR for CS4-CS7.
+    /// The DRAM interface outputs RAS of its areas instead.
+    pub fn is_area_selected(&self, area_index: u8) -> Result<bool> {
+        if self.check_dram_area(area_index)? {
+            return Ok(true);
+        }
+        Ok(match area_index {
+            0..=3 => (self.register(P8DDR) >> (4 - area_index)) & 1 == 1,
+            _ => (self.register(CSCR) >> area_index) & 1 == 1,
+        })
+    }
+
+    // A0-A20 are output by the DDR of ports 1, 2 and 5 and A20E of BRCR.
+    // The lines which are ports read as 0 by the device, A21-A23 select the area.
+    fn address_mask(&self) -> u32 {
+        let a20 = if self.register(BRCR) & BRCR_A20E == 0 { 1 << 20 } else { 0 };
+        0xe00000 | a20 | ((self.register(P5DDR) & 0x0f) as u32) << 16 | (self.register(P2DDR) as u32) << 8 | self.register(P1DDR) as u32
+    }
+
+    /// Address an access of the cpu reaches, or an error if no device responds.
+    /// Areas whose chip select is not output do not respond, and only area 2 has a device.
+    /// The on-chip RAM is in the external area 7 while RAME is cleared.
+    /// With `refresh_check`, the DRAM does not respond until its refresh cycles are enabled.
+    pub fn decode_access(&self, addr: u32) -> Result<u32> {
+        let on_chip = match addr {
+            MEMORY_START_ADDR..=MEMORY_END_ADDR => self.is_ram_enabled(),
+            VENCTOR_START_ADDR..=ROM_END_ADDR => true,
+            _ => is_io_register(addr),
+        };
+        if on_chip {
+            return Ok(addr);
+        }
+        let area_index = Bus::get_area_index(addr)?;
+        if area_index != 2 {
+            bail!("Invalid address [0x{:x}]", addr);
+        }
+        if !self.is_area_selected(area_index)? {
+            bail!("No device responds at [0x{:x}], CS{} is not output", addr, area_index);
+        }
+        // the DRAM interface outputs the row and the column addresses by itself
+        if self.check_dram_area(area_index)? {
+            if self.refresh_check && !self.module_manager.borrow().is_refreshing() {
+                bail!(
+                    "DRAM at [0x{:x}] is accessed before its refresh is configured (RCYCE of DRCRB and CKS of RTMCSR)",
+                    addr
+                );
+            }
+            Ok(addr)
+        } else {
+            Ok(addr & self.address_mask())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OPERATING_MODE, P1DDR, P2DDR, P5DDR};
+    use crate::{
+        bus::{Bus, AREA2_START_ADDR},
+        memory::MEMORY_START_ADDR,
+        modules::refresh::{DRCRB, DRCRB_RCYCE, RTMCSR},
+        registers::{BRCR, CSCR, DRCRA, MDCR, P8DDR, SYSCR},
+    };
+
+    #[test]
+    fn test_reset_values() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.peek(MDCR).unwrap() & 0x07, OPERATING_MODE);
+        assert_eq!(bus.peek(SYSCR).unwrap(), 0x09);
+        assert_eq!(bus.peek(CSCR).unwrap(), 0x0f);
+
+        // MDCR is read only and the reserved bits stay 1
+        bus.write(MDCR, 0).unwrap();
+        bus.write(CSCR, 0xf0).unwrap();
+        bus.write(BRCR, 0).unwrap();
+        assert_eq!(bus.peek(MDCR).unwrap() & 0x07, OPERATING_MODE);
+        assert_eq!(bus.peek(CSCR).unwrap(), 0xff);
+        assert_eq!(bus.peek(BRCR).unwrap(), 0x0e);
+    }
+
+    #[test]
+    fn test_ram_enable() {
+        let mut bus = Bus::new();
+        bus.write(MEMORY_START_ADDR, 0x12).unwrap();
+        bus.write(SYSCR, 0x08).unwrap();
+        assert!(bus.read(MEMORY_START_ADDR).is_err());
+        assert!(bus.write(MEMORY_START_ADDR, 0x34).is_err());
+        bus.write(SYSCR, 0x09).unwrap();
+        assert_eq!(bus.read(MEMORY_START_ADDR).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn test_chip_select() {
+        let mut bus = Bus::new();
+        bus.write(DRCRA, 0).unwrap();
+        assert!(bus.write(AREA2_START_ADDR, 0x12).is_err());
+
+        // CS2, and only A0-A7 are output
+        bus.write(P8DDR, 0x04).unwrap();
+        bus.write(P1DDR, 0xff).unwrap();
+        bus.write(AREA2_START_ADDR + 0x101, 0x12).unwrap();
+        assert_eq!(bus.read(AREA2_START_ADDR + 1).unwrap(), 0x12);
+
+        bus.write(P2DDR, 0xff).unwrap();
+        bus.write(P5DDR, 0x0f).unwrap();
+        bus.write(BRCR, 0xee).unwrap();
+        bus.write(AREA2_START_ADDR + 0x1fffff, 0x34).unwrap();
+        assert_eq!(bus.dram[0x1fffff], 0x34);
+        assert_eq!(bus.read(AREA2_START_ADDR + 0x101).unwrap(), 0);
+
+        // the DRAM interface outputs RAS2 without P8DDR
+        bus.write(P8DDR, 0).unwrap();
+        bus.write(DRCRA, 0x20).unwrap();
+        assert_eq!(bus.read(AREA2_START_ADDR + 1).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn test_refresh_check() {
+        let mut bus = Bus::new();
+        bus.write(DRCRA, 0x20).unwrap();
+        bus.write(AREA2_START_ADDR, 0x12).unwrap();
+        bus.refresh_check = true;
+        assert!(bus.read(AREA2_START_ADDR).is_err());
+
+        // the refresh cycles are enabled, but RTCNT does not count yet
+        bus.write(DRCRB, DRCRB_RCYCE).unwrap();
+        assert!(bus.read(AREA2_START_ADDR).is_err());
+        bus.write(RTMCSR, 0x10).unwrap();
+        assert_eq!(bus.read(AREA2_START_ADDR).unwrap(), 0x12);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{config::EmulatorConfig, cpu::Cpu, elf};
+use anyhow::{bail, Result};
+
+/// Lists the instructions of the elf the emulator does not implement, without running it.
+/// Fails if any is found.
+pub fn run(elf_path: &str) -> Result<()> {
+    let mut cpu = Cpu::new();
+    cpu.set_config(EmulatorConfig {
+        print_instruction: false,
+        ..Default::default()
+    });
+    elf::load(elf_path, &mut cpu, String::new())?;
+    let ranges = elf::load_code_ranges(elf_path)?;
+    let report = cpu.scan_unsupported(&ranges)?;
+
+    for u in &report.unsupported {
+        let words: Vec<String> = u.words.iter().map(|word| format!("{:04x}", word)).collect();
+        let location = match cpu.symbols.format_location(u.addr) {
+            Some(location) => format!(" ({})", location),
+            None => String::new(),
+        };
+        println!(
+            "0x{:06x} [{}] in {}{}",
+            u.addr.wrapping_sub(cpu.load_base),
+            words.join(" "),
+            u.function,
+            location
+        );
+    }
+    for f in &report.failures {
+        println!("0x{:06x} in {}: {}", f.addr.wrapping_sub(cpu.load_base), f.function, f.error);
+    }
+    let functions = ranges.iter().filter(|s| !s.name.starts_with(".L")).count();
+    println!(
+        "{} instructions in {} functions: {} unsupported, {} not decoded",
+        report.instructions + report.unsupported.len(),
+        functions,
+        report.unsupported.len(),
+        report.failures.len()
+    );
+
+    if !report.unsupported.is_empty() {
+        bail!("{} unsupported instructions", report.unsupported.len());
+    }
+    if !report.failures.is_empty() {
+        bail!("{} instructions could not be decoded", report.failures.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use anyhow::{bail, Context as _, Result};
+use std::fmt;
+
+pub const DEFAULT_CLOCK: usize = 20_000_000;
+
+/// Options of an emulator instance.
+/// Each `Cpu` owns one, and the socket (`config:<name>:<value>`) and the debugger (`set <name> <value>`) change it while running.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmulatorConfig {
+    // print the executed instructions
+    pub print_instruction: bool,
+    // print the messages sent to the socket
+    pub print_messages: bool,
+    // wait for a start message from the socket
+    pub wait_start: bool,
+    // no real-time pacing and no host time, so runs are reproducible
+    pub deterministic: bool,
+    pub instruction_cache: bool,
+    // fast-forward loops which only count registers or poll an address
+    pub idle_skip: bool,
+    // report accesses to the DRAM before its refresh is configured
+    pub refresh_check: bool,
+    // cpu clock in Hz, the states of a second of the guest
+    pub clock: usize,
+    // real-time speed multiplier of the pacing, None for unlimited
+    pub speed: Option<f64>,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig {
+            print_instruction: cfg!(debug_assertions),
+            print_messages: false,
+            wait_start: false,
+            deterministic: false,
+            instruction_cache: true,
+            idle_skip: true,
+            refresh_check: false,
+            clock: DEFAULT_CLOCK,
+            speed: Some(1.0),
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "1" | "true" | "on" => Ok(true),
+        "0" | "false" | "off" => Ok(false),
+        _ => bail!("invalid value [{}], expected on or off", value),
+    }
+}
+
+// Bounds of the clock in MHz, a millisecond of the guest takes at least 10 states
+const MIN_CLOCK_MHZ: f64 = 0.01;
+const MAX_CLOCK_MHZ: f64 = 1000.0;
+// Bounds of the speed multiplier, the pacing sleeps for a finite time
+const MIN_SPEED: f64 = 0.001;
+const MAX_SPEED: f64 = 1000.0;
+
+// The clock in MHz
+fn parse_clock(value: &str) -> Result<usize> {
+    let mhz: f64 = value.parse().with_context(|| format!("invalid clock [{}], expected MHz", value))?;
+    if !(MIN_CLOCK_MHZ..=MAX_CLOCK_MHZ).contains(&mhz) {
+        bail!("invalid clock [{}], expected {} to {} MHz", value, MIN_CLOCK_MHZ, MAX_CLOCK_MHZ);
+    }
+    Ok((mhz * 1_000_000.0) as usize)
+}
+
+// A multiplier like 0.1, 1 or 10, or unlimited
+fn parse_speed(value: &str) -> Result<Option<f64>> {
+    if value == "unlimited" {
+        return Ok(None);
+    }
+    let speed: f64 = value.parse().with_context(|| format!("invalid speed [{}]", value))?;
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        bail!("invalid speed [{}], expected {} to {} or unlimited", value, MIN_SPEED, MAX_SPEED);
+    }
+    Ok(Some(speed))
+}
+
+impl EmulatorConfig {
+    /// Sets the option by its name, e.g. `set("print_instruction", "on")`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let option = match name {
+            "clock" => {
+                self.clock = parse_clock(value)?;
+                return Ok(());
+            }
+            "speed" => {
+                self.speed = parse_speed(value)?;
+                return Ok(());
+            }
+            "print_instruction" => &mut self.print_instruction,
+            "print_messages" => &mut self.print_messages,
+            "wait_start" => &mut self.wait_start,
+            "deterministic" => &mut self.deterministic,
+            "instruction_cache" => &mut self.instruction_cache,
+            "idle_skip" => &mut self.idle_skip,
+            "refresh_check" => &mut self.refresh_check,
+            _ => bail!("unknown option [{}]", name),
+        };
+        *option = parse_bool(value)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for EmulatorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let options = [
+            ("print_instruction", self.print_instruction),
+            ("print_messages", self.print_messages),
+            ("wait_start", self.wait_start),
+            ("deterministic", self.deterministic),
+            ("instruction_cache", self.instruction_cache),
+            ("idle_skip", self.idle_skip),
+            ("refresh_check", self.refresh_check),
+        ];
+        let mut options: Vec<String> = options
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, if *value { "on" } else { "off" }))
+            .collect();
+        options.push(format!("clock = {}MHz", self.clock as f64 / 1_000_000.0));
+        options.push(match self.speed {
+            Some(speed) => format!("speed = {}x", speed),
+            None => "speed = unlimited".to_string(),
+        });
+        write!(f, "{}", options.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmulatorConfig;
+
+    #[test]
+    fn test_set() {
+        let mut config = EmulatorConfig::default();
+        config.set("deterministic", "on").unwrap();
+        config.set("print_instruction", "0").unwrap();
+        assert!(config.deterministic);
+        assert!(!config.print_instruction);
+        assert!(config.to_string().contains("deterministic = on\n"));
+        config.set("refresh_check", "on").unwrap();
+        assert!(config.refresh_check);
+
+        config.set("clock", "25").unwrap();
+        config.set("speed", "0.1").unwrap();
+        assert_eq!(config.clock, 25_000_000);
+        assert_eq!(config.speed, Some(0.1));
+        assert!(config.to_string().ends_with("clock = 25MHz\nspeed = 0.1x"));
+        config.set("speed", "unlimited").unwrap();
+        assert_eq!(config.speed, None);
+
+        assert!(config.set("deterministic", "yes").is_err());
+        assert!(config.set("clock", "0").is_err());
+        assert!(config.set("speed", "fast").is_err());
+        assert!(config.set("foo", "on").is_err());
+    }
+
+    #[test]
+    fn test_set_out_of_range() {
+        let mut config = EmulatorConfig::default();
+        // rounds to 0 Hz
+        assert!(config.set("clock", "0.0000001").is_err());
+        assert!(config.set("clock", "0.001").is_err());
+        assert!(config.set("clock", "1e300").is_err());
+        assert!(config.set("clock", "inf").is_err());
+        assert!(config.set("clock", "NaN").is_err());
+        config.set("clock", "0.01").unwrap();
+        assert_eq!(config.clock, 10_000);
+        config.set("clock", "1000").unwrap();
+        assert_eq!(config.clock, 1_000_000_000);
+
+        assert!(config.set("speed", "1e-300").is_err());
+        assert!(config.set("speed", "-1").is_err());
+        assert!(config.set("speed", "1e300").is_err());
+        assert!(config.set("speed", "inf").is_err());
+        assert!(config.set("speed", "NaN").is_err());
+        config.set("speed", "0.001").unwrap();
+        assert_eq!(config.speed, Some(0.001));
+        config.set("speed", "1000").unwrap();
+        assert_eq!(config.speed, Some(1000.0));
+    }
+}
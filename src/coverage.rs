@@ -0,0 +1,187 @@
+use crate::elf::{LineTable, Segment, Symbols};
+use anyhow::{Context as _, Result};
+use lcov::Lcov;
+use std::{collections::HashMap, ops::Range};
+
+mod lcov;
+
+/// Records executed instructions and conditional branch outcomes, written as lcov.
+#[derive(Clone)]
+pub struct Coverage {
+    path: String,
+    // source file name of addresses without line information
+    elf_path: String,
+    code: Vec<Range<u32>>,
+    hits: HashMap<u32, u64>,
+    // pc of bcc -> (taken, not taken)
+    branches: HashMap<u32, (u64, u64)>,
+}
+
+// Returns the length of a conditional branch instruction, excluding BRA and BRN
+fn conditional_branch_len(opcode: u16) -> Option<u32> {
+    match opcode >> 8 {
+        0x42..=0x4f => Some(2),
+        0x58 if opcode & 0xff0f == 0x5800 && (opcode >> 4) & 0xf >= 2 => Some(4),
+        _ => None,
+    }
+}
+
+impl Coverage {
+    pub fn new(path: &str, elf_path: &str, segments: &[Segment]) -> Self {
+        Coverage {
+            path: path.to_string(),
+            elf_path: elf_path.to_string(),
+            code: segments.iter().filter(|s| s.is_executable).map(|s| s.range.clone()).collect(),
+            hits: HashMap::new(),
+            branches: HashMap::new(),
+        }
+    }
+
+    fn is_code(&self, addr: u32) -> bool {
+        self.code.iter().any(|range| range.contains(&addr))
+    }
+
+    /// Called after an instruction at `pc` is executed. `next_pc` is the pc after the execution.
+    pub fn on_instruction(&mut self, pc: u32, opcode: u16, next_pc: u32) {
+        if !self.is_code(pc) {
+            return;
+        }
+        *self.hits.entry(pc).or_insert(0) += 1;
+
+        if let Some(len) = conditional_branch_len(opcode) {
+            let branch = self.branches.entry(pc).or_insert((0, 0));
+            if next_pc == pc + len {
+                branch.1 += 1;
+            } else {
+                branch.0 += 1;
+            }
+        }
+    }
+
+    // Returns the source file and line of `addr`.
+    // Addresses without line information are reported as lines of the elf file.
+    fn find_line<'a>(&'a self, line_table: &'a LineTable, addr: u32) -> (&'a str, u32) {
+        line_table.find(addr).unwrap_or((&self.elf_path, addr))
+    }
+
+    pub fn to_lcov(&self, symbols: &Symbols) -> Lcov {
+        let line_table = &symbols.lines;
+        let mut lcov = Lcov::new();
+
+        // Lines without executed instructions
+        for row in line_table.rows().iter().filter(|row| row.line != 0 && self.is_code(row.addr)) {
+            lcov.file(&line_table.files[row.file]).lines.entry(row.line).or_insert(0);
+        }
+        // The count of a line is the count of its most executed instruction
+        for (pc, count) in &self.hits {
+            let (file, line) = self.find_line(line_table, *pc);
+            let entry = lcov.file(file).lines.entry(line).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+
+        // Symbols without size are labels
+        for symbol in symbols.iter().filter(|s| s.size != 0 && self.is_code(s.addr)) {
+            let (file, line) = self.find_line(line_table, symbol.addr);
+            let count = self.hits.get(&symbol.addr).copied().unwrap_or(0);
+            let file = lcov.file(file);
+            file.functions.insert(symbol.name.clone(), (line, count));
+            file.lines.entry(line).or_insert(0);
+        }
+
+        let mut branches: Vec<(&u32, &(u64, u64))> = self.branches.iter().collect();
+        branches.sort();
+        let mut blocks: HashMap<(&str, u32), u32> = HashMap::new();
+        for (pc, (taken, not_taken)) in branches {
+            let (file, line) = self.find_line(line_table, *pc);
+            let block = blocks.entry((file, line)).or_insert(0);
+            let file = lcov.file(file);
+            file.branches.insert((line, *block, 0), *taken);
+            file.branches.insert((line, *block, 1), *not_taken);
+            *block += 1;
+        }
+        lcov
+    }
+
+    /// Writes the coverage to the file, adding the counts already in it.
+    pub fn finish(&self, symbols: &Symbols) -> Result<()> {
+        let mut lcov = self.to_lcov(symbols);
+        if let Ok(raw) = std::fs::read_to_string(&self.path) {
+            let previous = Lcov::parse(&raw).with_context(|| format!("failed to merge coverage [{}]", self.path))?;
+            lcov.merge(previous);
+        }
+        std::fs::write(&self.path, lcov.render()).with_context(|| format!("failed to write coverage [{}]", self.path))?;
+        log::info!("Wrote coverage [{}]", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use crate::elf::{Segment, Symbol, Symbols};
+
+    const MAIN: u32 = 0x1000;
+
+    fn create_coverage() -> Coverage {
+        let segments = [
+            Segment {
+                range: MAIN..MAIN + 0x100,
+                is_executable: true,
+                is_writable: false,
+            },
+            Segment {
+                range: 0x2000..0x2100,
+                is_executable: false,
+                is_writable: true,
+            },
+        ];
+        Coverage::new("", "prog.elf", &segments)
+    }
+
+    #[test]
+    fn test_branch() {
+        let mut coverage = create_coverage();
+        // beq taken, not taken
+        coverage.on_instruction(MAIN, 0x4710, MAIN + 0x12);
+        coverage.on_instruction(MAIN, 0x4710, MAIN + 2);
+        coverage.on_instruction(MAIN, 0x4710, MAIN + 2);
+        // bra.l is not a conditional branch
+        coverage.on_instruction(MAIN + 4, 0x5800, MAIN + 0x40);
+        // bne:16 not taken
+        coverage.on_instruction(MAIN + 8, 0x5860, MAIN + 12);
+        // outside of code
+        coverage.on_instruction(0x2000, 0x0000, 0x2002);
+
+        assert_eq!(coverage.hits.len(), 3);
+        assert_eq!(coverage.hits[&MAIN], 3);
+        assert_eq!(coverage.branches.len(), 2);
+        assert_eq!(coverage.branches[&MAIN], (1, 2));
+        assert_eq!(coverage.branches[&(MAIN + 8)], (0, 1));
+    }
+
+    #[test]
+    fn test_lcov_without_line_table() {
+        let mut coverage = create_coverage();
+        coverage.on_instruction(MAIN, 0x0000, MAIN + 2);
+        coverage.on_instruction(MAIN + 2, 0x4610, MAIN + 4);
+
+        let mut symbols = Symbols::new();
+        for (name, addr, size) in [("_main", MAIN, 0x10), (".L1", MAIN + 4, 0), ("_unused", MAIN + 0x80, 0x10)] {
+            symbols.push(Symbol {
+                name: name.to_string(),
+                addr,
+                size,
+            });
+        }
+
+        let lcov = coverage.to_lcov(&symbols);
+        let file = &lcov.files["prog.elf"];
+        assert_eq!(file.functions["_main"], (MAIN, 1));
+        assert_eq!(file.functions["_unused"], (MAIN + 0x80, 0));
+        assert!(!file.functions.contains_key(".L1"));
+        assert_eq!(file.lines.len(), 3);
+        assert_eq!(file.lines[&(MAIN + 0x80)], 0);
+        assert_eq!(file.branches[&(MAIN + 2, 0, 0)], 0);
+        assert_eq!(file.branches[&(MAIN + 2, 0, 1)], 1);
+    }
+}
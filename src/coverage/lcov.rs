@@ -0,0 +1,164 @@
+use anyhow::{bail, Context as _, Result};
+use std::collections::BTreeMap;
+
+#[derive(Default, Debug, PartialEq)]
+pub struct FileRecord {
+    // name -> (line, hit count)
+    pub functions: BTreeMap<String, (u32, u64)>,
+    // line -> hit count
+    pub lines: BTreeMap<u32, u64>,
+    // (line, block, branch) -> taken count
+    pub branches: BTreeMap<(u32, u32, u32), u64>,
+}
+
+/// Coverage data in the lcov tracefile format.
+#[derive(Default, Debug, PartialEq)]
+pub struct Lcov {
+    pub files: BTreeMap<String, FileRecord>,
+}
+
+impl Lcov {
+    pub fn new() -> Self {
+        Lcov { files: BTreeMap::new() }
+    }
+
+    pub fn file(&mut self, name: &str) -> &mut FileRecord {
+        self.files.entry(name.to_string()).or_default()
+    }
+
+    /// Adds the counts of `other`.
+    pub fn merge(&mut self, other: Lcov) {
+        for (name, other_file) in other.files {
+            let file = self.file(&name);
+            for (func, (line, count)) in other_file.functions {
+                file.functions.entry(func).or_insert((line, 0)).1 += count;
+            }
+            for (line, count) in other_file.lines {
+                *file.lines.entry(line).or_insert(0) += count;
+            }
+            for (key, count) in other_file.branches {
+                *file.branches.entry(key).or_insert(0) += count;
+            }
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Self> {
+        let mut lcov = Lcov::new();
+        let mut current: Option<String> = None;
+
+        for (i, line) in raw.lines().enumerate() {
+            let line = line.trim();
+            let (key, value) = line.split_once(':').unwrap_or((line, ""));
+            let result = (|| -> Result<()> {
+                match key {
+                    "SF" => current = Some(value.to_string()),
+                    "end_of_record" => current = None,
+                    "FN" | "FNDA" | "DA" | "BRDA" => {
+                        let Some(name) = &current else {
+                            bail!("record outside of SF");
+                        };
+                        let fields: Vec<&str> = value.split(',').collect();
+                        let file = lcov.file(name);
+                        match (key, fields.as_slice()) {
+                            ("FN", [line, func]) => {
+                                file.functions.entry(func.to_string()).or_insert((0, 0)).0 = line.parse()?;
+                            }
+                            ("FNDA", [count, func]) => {
+                                file.functions.entry(func.to_string()).or_insert((0, 0)).1 += count.parse::<u64>()?;
+                            }
+                            ("DA", [line, count, ..]) => {
+                                *file.lines.entry(line.parse()?).or_insert(0) += count.parse::<u64>()?;
+                            }
+                            ("BRDA", [line, block, branch, taken]) => {
+                                let taken = if *taken == "-" { 0 } else { taken.parse::<u64>()? };
+                                *file.branches.entry((line.parse()?, block.parse()?, branch.parse()?)).or_insert(0) += taken;
+                            }
+                            _ => bail!("invalid record"),
+                        }
+                    }
+                    // Summary records are recomputed when written
+                    _ => (),
+                }
+                Ok(())
+            })();
+            result.with_context(|| format!("lcov line {}: [{}]", i + 1, line))?;
+        }
+        Ok(lcov)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::from("TN:\n");
+        for (name, file) in &self.files {
+            out += &format!("SF:{}\n", name);
+            for (func, (line, _)) in &file.functions {
+                out += &format!("FN:{},{}\n", line, func);
+            }
+            for (func, (_, count)) in &file.functions {
+                out += &format!("FNDA:{},{}\n", count, func);
+            }
+            out += &format!("FNF:{}\n", file.functions.len());
+            out += &format!("FNH:{}\n", file.functions.values().filter(|(_, count)| *count != 0).count());
+            for ((line, block, branch), taken) in &file.branches {
+                out += &format!("BRDA:{},{},{},{}\n", line, block, branch, taken);
+            }
+            out += &format!("BRF:{}\n", file.branches.len());
+            out += &format!("BRH:{}\n", file.branches.values().filter(|taken| **taken != 0).count());
+            for (line, count) in &file.lines {
+                out += &format!("DA:{},{}\n", line, count);
+            }
+            out += &format!("LF:{}\n", file.lines.len());
+            out += &format!("LH:{}\n", file.lines.values().filter(|count| **count != 0).count());
+            out += "end_of_record\n";
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lcov;
+
+    const RAW: &str = "\
+TN:
+SF:main.c
+FN:3,main
+FNDA:1,main
+FNF:1
+FNH:1
+BRDA:5,0,0,2
+BRDA:5,0,1,-
+DA:3,1
+DA:5,3
+DA:6,0
+end_of_record
+";
+
+    #[test]
+    fn test_parse() {
+        let lcov = Lcov::parse(RAW).unwrap();
+        let file = &lcov.files["main.c"];
+        assert_eq!(file.functions["main"], (3, 1));
+        assert_eq!(file.lines[&5], 3);
+        assert_eq!(file.lines[&6], 0);
+        assert_eq!(file.branches[&(5, 0, 0)], 2);
+        assert_eq!(file.branches[&(5, 0, 1)], 0);
+        assert!(Lcov::parse("DA:1,1\n").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut lcov = Lcov::parse(RAW).unwrap();
+        lcov.merge(Lcov::parse(RAW).unwrap());
+        let file = &lcov.files["main.c"];
+        assert_eq!(file.functions["main"], (3, 2));
+        assert_eq!(file.lines[&5], 6);
+        assert_eq!(file.lines[&6], 0);
+        assert_eq!(file.branches[&(5, 0, 0)], 4);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let lcov = Lcov::parse(RAW).unwrap();
+        assert_eq!(Lcov::parse(&lcov.render()).unwrap(), lcov);
+    }
+}
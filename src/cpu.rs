@@ -1,30 +1,65 @@
 use crate::{
-    bus::{Bus, AREA0_START_ADDR, AREA7_END_ADDR},
-    elf::PROGRAM_START_ADDR,
+    bus::{is_io_register, Bus, AREA0_START_ADDR, AREA7_END_ADDR, ROM_END_ADDR, VENCTOR_START_ADDR},
+    config::EmulatorConfig,
+    coverage::Coverage,
+    debugger::{DebugAction, Debugger},
+    elf::{Segment, Symbols},
     memory::{MEMORY_END_ADDR, MEMORY_START_ADDR},
-    modules::ModuleManager,
+    mes2::Mes2,
+    modules::refresh::{DRCRB, DRCRB_RCYCE, RTCOR, RTMCSR},
+    profiler::Profiler,
     registers::{ABWCR, ASTCR, DRCRA, WCRH, WCRL},
-    setting,
+    stack_usage::StackUsage,
+    test_report::TestReport,
+    trace::Tracer,
 };
 use anyhow::{bail, Result};
+use decode::{Handler, Prefetch};
 use interrupt_controller::InterruptController;
-use std::{cell::RefCell, ops::Sub, rc::Rc, time::Duration};
+use std::{collections::HashSet, fmt, ops::Sub, time::Duration};
 use std::{ops::Add, time};
 
 #[cfg(not(test))]
 use crate::socket::Socket;
 
+// Encodings the decoder does not implement, with the address of the last fetched word
+macro_rules! unimpl {
+    ($op:expr, $pc:expr ) => {
+        return Err($crate::cpu::UnsupportedInstruction { opcode: $op, pc: $pc - 2 }.into())
+    };
+}
+
 mod addressing_mode;
+mod check;
+pub(crate) mod decode;
+mod idle;
+// Calls functions of the program from the tests
+mod call;
 mod instruction;
 pub(crate) mod interrupt_controller;
+mod memcheck;
 mod messages;
+mod trace;
 
 #[cfg(test)]
 mod testhelper;
 
-const CPU_CLOCK: usize = 20_000_000;
 pub const ADDRESS_MASK: u32 = 0x00ffffff;
-const SYNC_MESSAGE_INTERVAL: usize = CPU_CLOCK / 10;
+
+/// Error of an instruction `exec` can not decode.
+#[derive(Debug, PartialEq)]
+pub struct UnsupportedInstruction {
+    pub opcode: u16,
+    pub pc: u32,
+}
+
+impl fmt::Display for UnsupportedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unimplemented instruction:[0x{:>04x}] pc:[0x{:x}]", self.opcode, self.pc)
+    }
+}
+
+impl std::error::Error for UnsupportedInstruction {}
 
 #[cfg_attr(test, derive(Clone))]
 pub struct Cpu {
@@ -37,8 +72,39 @@ pub struct Cpu {
     pub er: [u32; 8],
     interrupt_controller: InterruptController,
     pub exit_addr: u32, // address of ___exit
-    module_manager: Rc<RefCell<ModuleManager>>,
+    exit_code: Option<u32>,
+    pub load_base: u32, // address the program is relocated to
+    pub symbols: Symbols,
+    pub segments: Vec<Segment>,
+    pub debugger: Option<Debugger>,
+    tracer: Option<Tracer>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub stack_usage: Option<StackUsage>,
+    pub mes2: Mes2,
+    pub test_report: TestReport,
+    // program output is kept here instead of printed if Some
+    pub stdout: Option<Vec<u8>>,
+    pub max_states: Option<usize>,
+    config: EmulatorConfig,
     state_sum: usize,
+    // instructions executed by the run loop
+    instruction_count: usize,
+    // states fast-forwarded in idle loops
+    idle_states: usize,
+    // branches of the loops which can not be fast-forwarded
+    busy_loops: HashSet<u32>,
+    // a byte or a word register was written, which the idle loops check
+    narrow_write: bool,
+    // waiting for an interrupt at the sleep instruction
+    sleep_mode: Option<SleepMode>,
+    // state of the instruction cache while an instruction is fetched and executed
+    prefetch: Prefetch,
+    cached_handler: Option<Handler>,
+    // states of a word fetch of the cached instruction being executed
+    fetch_states: Option<u8>,
+    fetched_pc: u32,
+    is_executing: bool,
 }
 
 #[allow(dead_code)]
@@ -53,6 +119,15 @@ pub enum CCR {
     I,
 }
 
+/// Modes entered by the sleep instruction, by SSBY of SYSCR
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SleepMode {
+    // the cpu stops and the modules run until an interrupt
+    Sleep,
+    // the clock stops until NMI
+    SoftwareStandby,
+}
+
 #[derive(PartialEq)]
 pub enum StateType {
     I,
@@ -63,33 +138,72 @@ pub enum StateType {
     N,
 }
 
-macro_rules! unimpl {
-    ($op:expr, $pc:expr ) => {
-        bail!(
-            "unimplemented instruction:[0x{:>04x}] pc:[0x{:x}(0x{:x})]",
-            $op,
-            $pc - 2,
-            $pc - 2 - PROGRAM_START_ADDR as u32
-        )
-    };
-}
-
 impl Cpu {
     pub fn new() -> Self {
-        let module_manager = Rc::new(RefCell::new(ModuleManager::new()));
-        Cpu {
+        let mut cpu = Cpu {
             #[cfg(not(test))]
             socket: None,
-            bus: Bus::new(Rc::downgrade(&module_manager)),
+            bus: Bus::new(),
             pc: 0,
             operating_pc: 0,
             ccr: 0,
             er: [0; 8],
             interrupt_controller: InterruptController::new(),
             exit_addr: 0,
-            module_manager: module_manager.clone(),
+            exit_code: None,
+            load_base: 0,
+            symbols: Symbols::new(),
+            segments: Vec::new(),
+            debugger: None,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            stack_usage: None,
+            mes2: Mes2::new(),
+            test_report: TestReport::new(None),
+            stdout: None,
+            max_states: None,
             state_sum: 0,
-        }
+            instruction_count: 0,
+            idle_states: 0,
+            busy_loops: HashSet::new(),
+            narrow_write: false,
+            sleep_mode: None,
+            prefetch: Prefetch::default(),
+            cached_handler: None,
+            fetch_states: None,
+            fetched_pc: 0,
+            is_executing: false,
+            config: EmulatorConfig::default(),
+        };
+        cpu.set_config(EmulatorConfig::default());
+        cpu
+    }
+
+    pub fn config(&self) -> &EmulatorConfig {
+        &self.config
+    }
+
+    /// States of a millisecond of the guest at the configured clock.
+    pub fn states_per_msec(&self) -> usize {
+        self.config.clock / 1000
+    }
+
+    /// Replaces the options, the ones of the bus too.
+    pub fn set_config(&mut self, config: EmulatorConfig) {
+        self.bus.print_messages = config.print_messages;
+        self.bus.instruction_cache.enabled = config.instruction_cache;
+        self.bus.refresh_check = config.refresh_check;
+        self.config = config;
+    }
+
+    /// Changes an option by its name while running, from the socket or the debugger.
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        let mut config = self.config.clone();
+        config.set(name, value)?;
+        log::info!("[config] {} = {}", name, value);
+        self.set_config(config);
+        Ok(())
     }
 
     #[cfg(not(test))]
@@ -102,6 +216,34 @@ impl Cpu {
     }
 
     pub fn run(&mut self) -> Result<()> {
+        let result = self.run_loop();
+
+        // Every report is written even if the execution or another report failed, and the execution error comes first
+        let mut finish_results = Vec::new();
+        if let Some(tracer) = &mut self.tracer {
+            finish_results.push(tracer.finish());
+        }
+        if let Some(profiler) = &self.profiler {
+            finish_results.push(profiler.finish(&self.symbols));
+        }
+        if let Some(coverage) = &self.coverage {
+            finish_results.push(coverage.finish(&self.symbols));
+        }
+        if let Some(stack_usage) = &mut self.stack_usage {
+            log::info!("Stack usage\n{}", stack_usage.report(&self.symbols));
+        }
+        self.print_memcheck_summary();
+        finish_results.push(self.test_report.finish(self.exit_code, self.state_sum));
+
+        let mut finish_result = Ok(());
+        for e in finish_results.into_iter().filter_map(|r| r.err()) {
+            log::error!("{:#}", e);
+            finish_result = finish_result.and(Err(e));
+        }
+        result.and(finish_result)
+    }
+
+    fn run_loop(&mut self) -> Result<()> {
         let exec_time = time::Instant::now();
 
         let sleeper = spin_sleep::SpinSleeper::default();
@@ -111,7 +253,7 @@ impl Cpu {
         let mut sleep_time = time::Duration::ZERO;
 
         #[cfg_attr(test, allow(unused_mut))]
-        let mut is_paused = if *setting::ENABLE_WAIT_START.read().unwrap() {
+        let mut is_paused = if self.config.wait_start {
             self.send_ready_message()?;
             true
         } else {
@@ -150,6 +292,9 @@ impl Cpu {
                         }
                         "u8" => self.parse_u8(list)?,
                         "ioport" => self.parse_ioport(list),
+                        "nmi" => self.parse_nmi(list),
+                        "stdin" => self.parse_stdin(&message),
+                        "config" => self.parse_config(list),
                         _ => (),
                     }
                 }
@@ -159,382 +304,205 @@ impl Cpu {
                 continue;
             }
 
+            // The guest time stops while a read of stdin waits for the socket
+            if self.mes2.stdin.is_waiting() {
+                sleeper.sleep(Duration::from_millis(1));
+                loop_time = time::Instant::now();
+                continue;
+            }
+
             // Interrupt
-            self.try_interrupt()?;
+            let interrupt_state = self.try_interrupt()? as usize;
+
+            // a sleeping cpu executes nothing until an interrupt, so the sleep instruction is counted once
+            if self.sleep_mode.is_none() {
+                if let Some(mut debugger) = self.debugger.take() {
+                    let action = debugger.before_exec(self);
+                    self.debugger = Some(debugger);
+                    if let DebugAction::Quit = action? {
+                        log::info!("Stopped program");
+                        return Ok(());
+                    }
+                }
 
-            if *setting::ENABLE_PRINT_OPCODE.read().unwrap() {
-                print!(" {:4x}:   ", self.pc.wrapping_sub(PROGRAM_START_ADDR as u32));
-            }
+                if self.config.print_instruction {
+                    print!(" {:4x}:   ", self.pc.wrapping_sub(self.load_base));
+                }
+
+                let pc = self.pc;
+                self.trace_begin();
+
+                let opcode = self.fetch();
+
+                let state = match self.exec(opcode) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        // the execution error is returned even if the trace can not be written
+                        if let Err(trace_error) = self.trace_instruction(pc, 0) {
+                            log::error!("{:#}", trace_error);
+                        }
+                        log::error!(
+                            "An error occurred when executing the opcode. [pc: {:0>8x}({:0>8x})] [opcode1: {:0>4x}]",
+                            self.pc - 2,
+                            (self.pc - 2).wrapping_sub(self.load_base),
+                            opcode
+                        );
+                        log::error!(
+                            "at {}",
+                            self.symbols.format_location(pc).unwrap_or_else(|| self.symbols.format_addr(pc))
+                        );
+                        self.print_er();
+                        return Err(e);
+                    }
+                };
+
+                self.trace_instruction(pc, state)?;
+
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.on_instruction(opcode, state, self.pc, self.er[7]);
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.on_instruction(pc, opcode, self.pc);
+                }
+                if let Some(stack_usage) = &mut self.stack_usage {
+                    stack_usage.on_instruction(opcode, self.pc, self.er[7]);
+                }
+                self.check_memory(pc);
 
-            let opcode = self.fetch();
-
-            let state = match self.exec(opcode) {
-                Ok(state) => state * 3, // Temporary speed adjustment
-                Err(e) => {
-                    log::error!(
-                        "An error occurred when executing the opcode. [pc: {:0>8x}({:0>8x})] [opcode1: {:0>4x}]",
-                        self.pc - 2,
-                        self.pc - 2 - PROGRAM_START_ADDR as u32,
-                        opcode
-                    );
-                    self.print_er();
-                    return Err(e);
+                if let Some(mut debugger) = self.debugger.take() {
+                    debugger.after_exec(self, opcode);
+                    self.debugger = Some(debugger);
                 }
-            };
 
-            self.state_sum += state as usize;
-            self.bus.cpu_state_sum = self.state_sum;
-            count_1msec += state as usize;
-            sync_count += state as usize;
+                let state = state as usize + interrupt_state;
+                self.state_sum += state;
+                self.bus.cpu_state_sum = self.state_sum;
+                self.instruction_count += 1;
+                count_1msec += state;
+                sync_count += state;
+
+                // a loop jumped back
+                if self.pc < pc && self.can_skip_idle_loop() {
+                    let state = self.skip_idle_loop(pc)?;
+                    count_1msec += state;
+                    sync_count += state;
+                }
+            }
 
-            if sync_count >= SYNC_MESSAGE_INTERVAL {
+            if let Some(mode) = self.sleep_mode {
+                let state = self.wait_in_sleep(mode)?;
+                count_1msec += state;
+                sync_count += state;
+            }
+
+            // every 100msec of the guest
+            let sync_interval = self.config.clock / 10;
+            if sync_count >= sync_interval {
                 self.send_sync_message()?;
-                sync_count -= SYNC_MESSAGE_INTERVAL;
+                sync_count -= sync_interval;
             }
 
-            self.module_manager
-                .borrow_mut()
-                .update_modules(&mut self.bus, state, &mut self.interrupt_controller)?;
+            self.update_modules();
 
-            if *setting::ENABLE_PRINT_OPCODE.read().unwrap() {
+            if self.config.print_instruction {
                 println!("");
             }
 
             if self.pc == self.exit_addr {
+                self.exit_code = Some(self.er[0]);
+            }
+            if let Some(exit_code) = self.exit_code {
                 log::info!("Finished program");
-                log::info!("Exit Code: {}", self.er[0]);
-                log::info!("state: {}, time: {}sec", self.state_sum, exec_time.elapsed().as_secs_f64());
+                log::info!("Exit Code: {}", exit_code);
+                log::info!(
+                    "state: {} ({} skipped in idle loops), instructions: {}, time: {}sec",
+                    self.state_sum,
+                    self.idle_states,
+                    self.instruction_count,
+                    exec_time.elapsed().as_secs_f64()
+                );
                 self.print_er();
                 return Ok(());
             }
 
-            // sleep every 1msec (Windows timer max precision)
-            if count_1msec >= 20000 {
-                let sleep_time_loop = Duration::from_secs_f64(count_1msec as f64 / CPU_CLOCK as f64).saturating_sub(loop_time.elapsed());
-                count_1msec = 0;
+            if let Some(max_states) = self.max_states {
+                if self.state_sum > max_states {
+                    bail!("The program did not finish within {} states", max_states);
+                }
+            }
 
-                sleep_time = sleep_time.add(sleep_time_loop);
-                if sleep_time.as_millis() > 1 {
-                    let sleep_duration = time::Duration::from_millis(sleep_time.as_millis() as u64);
-                    sleep_time = sleep_time.sub(sleep_duration);
-                    sleeper.sleep(sleep_duration);
+            // sleep every 1msec of the guest (Windows timer max precision) to run at the speed multiplier,
+            // deterministic runs and the unlimited speed go as fast as possible
+            if count_1msec >= self.states_per_msec() {
+                if let (Some(speed), false) = (self.config.speed, self.config.deterministic) {
+                    let guest_time = count_1msec as f64 / self.config.clock as f64;
+                    let sleep_time_loop = Duration::from_secs_f64(guest_time / speed).saturating_sub(loop_time.elapsed());
+
+                    sleep_time = sleep_time.add(sleep_time_loop);
+                    if sleep_time.as_millis() > 1 {
+                        let sleep_duration = time::Duration::from_millis(sleep_time.as_millis() as u64);
+                        sleep_time = sleep_time.sub(sleep_duration);
+                        sleeper.sleep(sleep_duration);
+                    }
                 }
+                count_1msec = 0;
                 loop_time = time::Instant::now();
             }
         }
     }
 
+    // Counts the modules up to the current state count when one of them has an event
+    fn update_modules(&mut self) {
+        let module_manager = self.bus.module_manager.get_mut();
+        if self.state_sum >= module_manager.next_event() {
+            module_manager.update_modules(self.state_sum, &mut self.interrupt_controller);
+        }
+    }
+
+    pub fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
     pub fn fetch(&mut self) -> u16 {
         let _pc = self.pc & !1;
 
         self.operating_pc = _pc;
 
-        let opcode = { ((self.bus.read(_pc).unwrap() as u16) << 8) | (self.bus.read(_pc + 1).unwrap() as u16) };
+        // The first word comes from the instruction cache, the rest from the words it holds
+        let cached = if self.is_executing {
+            self.prefetch.pop()
+        } else {
+            self.fetch_cached(_pc)
+        };
+        let opcode = match cached {
+            Some(word) => word,
+            None => {
+                let word = self.bus.read_w(_pc).unwrap();
+                // the first word of an instruction, the decoder caches the words it fetches
+                if !self.is_executing {
+                    self.prefetch.clear();
+                    self.cached_handler = None;
+                    self.fetched_pc = _pc;
+                }
+                self.prefetch.push(word);
+                word
+            }
+        };
 
-        if *setting::ENABLE_PRINT_OPCODE.read().unwrap() {
+        if self.config.print_instruction {
             print!("{:0>2x} {:0>2x} ", (opcode >> 8) as u8, opcode as u8);
         }
 
+        if let Some(tracer) = &mut self.tracer {
+            tracer.words.push(opcode);
+        }
+
         self.pc += 2;
         opcode
     }
 
     fn exec(&mut self, opcode: u16) -> Result<u8> {
-        match (opcode >> 8) as u8 {
-            0x0c | 0xf0..=0xff | 0x68 | 0x6e | 0x6c | 0x20..=0x2f | 0x30..=0x3f | 0x6a => return self.mov_b(opcode),
-            0x0d => return self.mov_w(opcode),
-            0x69 | 0x6f | 0x6d | 0x6b => return self.mov_w(opcode),
-            0x0f => return self.mov_l(opcode),
-
-            0x01 => match opcode as u8 {
-                0x00 => return self.mov_l(opcode),
-                0x40 => {
-                    let opcode2 = self.fetch();
-                    match (opcode2 >> 8) as u8 {
-                        0x69 => return self.stc_w_ern(opcode2),
-                        0x6f => return self.stc_w_disp16(opcode2),
-                        0x78 => return self.stc_w_disp24(opcode2),
-                        0x6d => return self.stc_w_inc_ern(opcode2),
-                        0x6b => match opcode2 as u8 {
-                            0x80 => return self.stc_abs16(),
-                            0xa0 => return self.stc_abs24(),
-                            _ => unimpl!(opcode, self.pc),
-                        },
-                        _ => unimpl!(opcode, self.pc),
-                    }
-                }
-                0xf0 => {
-                    let opcode2 = self.fetch();
-                    match (opcode2 >> 8) as u8 {
-                        0x64 => return self.or_l_rn(opcode, opcode2),
-                        0x65 => return self.xor_l_rn(opcode, opcode2),
-                        0x66 => return self.and_l_rn(opcode, opcode2),
-                        _ => unimpl!(opcode, self.pc),
-                    }
-                }
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x02 => return self.stc_b(opcode),
-
-            0x50 => return self.mulxu_b(opcode),
-            0x52 => return self.mulxu_w(opcode),
-
-            0x51 => return self.divxu_b(opcode),
-            0x53 => return self.divxu_w(opcode),
-
-            0x55 => return self.bsr_disp16(opcode),
-            0x5c => return self.bsr_disp24(opcode),
-
-            0x60 => return self.bset_rn_from_rn(opcode),
-            0x61 => return self.bnot_rn_from_rn(opcode),
-            0x62 => return self.bclr_rn_from_rn(opcode),
-            0x63 => return self.btst_rn_rn(opcode),
-
-            0x67 => match opcode & 0x80 {
-                0x00 => return self.bst_rn(opcode),
-                0x80 => return self.bist_rn(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x70 => return self.bset_rn_from_imm(opcode),
-            0x71 => return self.bnot_rn_from_imm(opcode),
-            0x72 => return self.bclr_rn_from_imm(opcode),
-            0x73 => return self.btst_imm_rn(opcode),
-
-            0x74 => match opcode & 0x80 {
-                0x00 => return self.bor_rn(opcode),
-                0x80 => return self.bior_rn(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x75 => match opcode & 0x80 {
-                0x00 => return self.bxor_rn(opcode),
-                0x80 => return self.bixor_rn(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x76 => match opcode & 0x80 {
-                0x00 => return self.band_rn(opcode),
-                0x80 => return self.biand_rn(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x77 => match opcode & 0x80 {
-                0x00 => return self.bld_rn(opcode),
-                0x080 => return self.bild_rn(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x78 => {
-                let opcode2 = self.fetch();
-                match (opcode2 >> 8) as u8 {
-                    0x6a => return self.mov_b_disp24(opcode, opcode2),
-                    0x6b => return self.mov_w_disp24(opcode, opcode2),
-                    _ => unimpl!(opcode, self.pc),
-                }
-            }
-
-            0x79 => match opcode & 0x00f0 {
-                0x0 => return self.mov_w(opcode),
-                0x0010 => return self.add_w(opcode),
-                0x0020 => return self.cmp_w_imm(opcode),
-                0x0030 => return self.sub_w(opcode),
-                0x0040 => return self.or_w_imm(opcode),
-                0x0050 => return self.xor_w_imm(opcode),
-                0x0060 => return self.and_w_imm(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x7a => match opcode & 0x00f0 {
-                0x0 => return self.mov_l(opcode),
-                0x0010 => return self.add_l(opcode),
-                0x0020 => return self.cmp_l_imm(opcode),
-                0x0030 => return self.sub_l(opcode),
-                0x0040 => return self.or_l_imm(opcode),
-                0x0050 => return self.xor_l_imm(opcode),
-                0x0060 => return self.and_l_imm(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x7c => {
-                let opcode2 = self.fetch();
-                match opcode2 & 0xff80 {
-                    0x6300 | 0x6380 => return self.btst_rn_ern(opcode, opcode2),
-                    0x7300 => return self.btst_imm_ern(opcode, opcode2),
-                    0x7400 => return self.bor_ern(opcode, opcode2),
-                    0x7480 => return self.bior_ern(opcode, opcode2),
-                    0x7500 => return self.bxor_ern(opcode, opcode2),
-                    0x7580 => return self.bixor_ern(opcode, opcode2),
-                    0x7600 => return self.band_ern(opcode, opcode2),
-                    0x7680 => return self.biand_ern(opcode, opcode2),
-                    0x7700 => return self.bld_ern(opcode, opcode2),
-                    0x7780 => return self.bild_ern(opcode, opcode2),
-                    _ => unimpl!(opcode, self.pc),
-                }
-            }
-
-            0x7d => {
-                let opcode2 = self.fetch();
-                match opcode2 & 0xff80 {
-                    0x6000 | 0x6080 | 0x7000 => return self.bset_ern(opcode, opcode2),
-                    0x6100 | 0x6180 | 0x7100 => return self.bnot_ern(opcode, opcode2),
-                    0x6200 | 0x6280 | 0x7200 => return self.bclr_ern(opcode, opcode2),
-                    0x6700 => return self.bst_ern(opcode, opcode2),
-                    0x6780 => return self.bist_ern(opcode, opcode2),
-                    _ => unimpl!(opcode, self.pc),
-                }
-            }
-
-            0x7e => {
-                let opcode2 = self.fetch();
-                match opcode2 & 0xff80 {
-                    0x6300 | 0x6380 => return self.btst_rn_abs(opcode, opcode2),
-                    0x7300 => return self.btst_imm_abs(opcode, opcode2),
-                    0x7400 => return self.bor_abs(opcode, opcode2),
-                    0x7480 => return self.bior_abs(opcode, opcode2),
-                    0x7500 => return self.bxor_abs(opcode, opcode2),
-                    0x7580 => return self.bixor_abs(opcode, opcode2),
-                    0x7600 => return self.band_abs(opcode, opcode2),
-                    0x7680 => return self.biand_abs(opcode, opcode2),
-                    0x7700 => return self.bld_abs(opcode, opcode2),
-                    0x7780 => return self.bild_abs(opcode, opcode2),
-                    _ => unimpl!(opcode, self.pc),
-                }
-            }
-
-            0x7f => {
-                let opcode2 = self.fetch();
-                match opcode2 & 0xff80 {
-                    0x6000 | 0x6080 | 0x7000 => return self.bset_abs(opcode, opcode2),
-                    0x6100 | 0x6180 | 0x7100 => return self.bnot_abs(opcode, opcode2),
-                    0x6200 | 0x6280 | 0x7200 => return self.bclr_abs(opcode, opcode2),
-                    0x6700 => return self.bst_abs(opcode, opcode2),
-                    0x6780 => return self.bist_abs(opcode, opcode2),
-                    _ => unimpl!(opcode, self.pc),
-                }
-            }
-
-            0x0a => match opcode as u8 {
-                0x00..=0x0f => return self.inc_b(opcode),
-                0x80..=0xf7 => return self.add_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x0b => match opcode as u8 {
-                0x50..=0x5f => return self.inc_w_1(opcode),
-                0xd0..=0xdf => return self.inc_w_2(opcode),
-                0x70..=0x77 => return self.inc_l_1(opcode),
-                0xf0..=0xf7 => return self.inc_l_2(opcode),
-                0x00..=0x07 => return self.adds1(opcode),
-                0x80..=0x87 => return self.adds2(opcode),
-                0x90..=0x97 => return self.adds4(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x10 => match opcode as u8 {
-                0x00..=0x0f => return self.shll_b(opcode),
-                0x10..=0x1f => return self.shll_w(opcode),
-                0x30..=0x37 => return self.shll_l(opcode),
-                0x80..=0x8f => return self.shal_b(opcode),
-                0x90..=0x9f => return self.shal_w(opcode),
-                0xb0..=0xb7 => return self.shal_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x11 => match opcode as u8 {
-                0x00..=0x0f => return self.shlr_b(opcode),
-                0x10..=0x1f => return self.shlr_w(opcode),
-                0x30..=0x3f => return self.shlr_l(opcode),
-                0x80..=0x8f => return self.shar_b(opcode),
-                0x90..=0x9f => return self.shar_w(opcode),
-                0xb0..=0xb7 => return self.shar_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x12 => match opcode as u8 {
-                0x00..=0x0f => return self.rotxl_b(opcode),
-                0x10..=0x1f => return self.rotxl_w(opcode),
-                0x30..=0x37 => return self.rotxl_l(opcode),
-                0x80..=0x8f => return self.rotl_b(opcode),
-                0x90..=0x9f => return self.rotl_w(opcode),
-                0xb0..=0xb7 => return self.rotl_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x13 => match opcode as u8 {
-                0x00..=0x0f => return self.rotxr_b(opcode),
-                0x10..=0x1f => return self.rotxr_w(opcode),
-                0x30..=0x37 => return self.rotxr_l(opcode),
-                0x80..=0x8f => return self.rotr_b(opcode),
-                0x90..=0x9f => return self.rotr_w(opcode),
-                0xb0..=0xb7 => return self.rotr_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x17 => match opcode as u8 {
-                0x00..=0x0f => return self.not_b(opcode),
-                0x10..=0x1f => return self.not_w(opcode),
-                0x30..=0x37 => return self.not_l(opcode),
-                0x50..=0x5f => return self.extu_w(opcode),
-                0x70..=0x77 => return self.extu_l(opcode),
-                0x80..=0x8f => return self.neg_b(opcode),
-                0x90..=0x9f => return self.neg_w(opcode),
-                0xb0..=0xb7 => return self.neg_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x1a => match opcode as u8 {
-                0x00..=0x0f => return self.dec_b(opcode),
-                0x80..=0xf7 => return self.sub_l(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x1b => match opcode as u8 {
-                0x50..=0x5f => return self.dec_w_1(opcode),
-                0xd0..=0xdf => return self.dec_w_2(opcode),
-                0x70..=0x77 => return self.dec_l_1(opcode),
-                0xf0..=0xf7 => return self.dec_l_2(opcode),
-                0x00..=0x07 => return self.subs1(opcode),
-                0x80..=0x87 => return self.subs2(opcode),
-                0x90..=0x97 => return self.subs4(opcode),
-                _ => unimpl!(opcode, self.pc),
-            },
-
-            0x80..=0x8f | 0x08 => return self.add_b(opcode),
-            0x09 => return self.add_w(opcode),
-
-            0x18 => return self.sub_b(opcode),
-            0x19 => return self.sub_w(opcode),
-
-            0x1c => return self.cmp_b_rn(opcode),
-            0xa0..=0xaf => return self.cmp_b_imm(opcode),
-            0x1d => return self.cmp_w_rn(opcode),
-            0x1f => return self.cmp_l_rn(opcode),
-
-            0xc0..=0xcf => return self.or_b_imm(opcode),
-            0x14 => return self.or_b_rn(opcode),
-            0x64 => return self.or_w_rn(opcode),
-
-            0xd0..=0xdf => return self.xor_b_imm(opcode),
-            0x15 => return self.xor_b_rn(opcode),
-            0x65 => return self.xor_w_rn(opcode),
-
-            0xe0..=0xef => return self.and_b_imm(opcode),
-            0x16 => return self.and_b_rn(opcode),
-            0x66 => return self.and_w_rn(opcode),
-
-            0x90..=0x9f => return self.addx_imm(opcode),
-            0x0e => return self.addx_rn(opcode),
-
-            0x59 | 0x5a | 0x5b => return self.jmp(opcode),
-            0x5d | 0x5e | 0x5f => return self.jsr(opcode),
-            0x40..=0x4f | 0x58 => return self.bcc(opcode),
-            0x54 => return self.rts(),
-            0x56 => return self.rte(),
-            0x57 => return self.trapa(opcode),
-            _ => unimpl!(opcode, self.pc),
-        }
+        self.exec_decoded(opcode)
     }
 
     pub fn write_ccr(&mut self, target: CCR, val: u8) {
@@ -561,10 +529,22 @@ impl Cpu {
         self.pc
     }
 
+    pub fn format_ccr(&self) -> String {
+        const NAMES: [&str; 8] = ["C", "V", "Z", "N", "U", "H", "UI", "I"];
+        let mut flags = String::new();
+        for (i, name) in NAMES.iter().enumerate().rev() {
+            if (self.ccr >> i) & 1 == 1 {
+                flags += name;
+                flags += " ";
+            }
+        }
+        format!("CCR:[0x{:02x}] ({})", self.ccr, flags.trim_end())
+    }
+
     fn get_wait_state(&self, area_index: u8) -> Result<u8> {
         match area_index {
-            0..=3 => return Ok((self.bus.read(WCRL)? >> (area_index * 2)) & 0x3),
-            4..=7 => return Ok((self.bus.read(WCRH)? >> ((area_index - 4) * 2)) & 0x3),
+            0..=3 => return Ok((self.bus.peek(WCRL)? >> (area_index * 2)) & 0x3),
+            4..=7 => return Ok((self.bus.peek(WCRH)? >> ((area_index - 4) * 2)) & 0x3),
             _ => bail!("Invalid area_index [{}]", area_index),
         }
     }
@@ -573,112 +553,125 @@ impl Cpu {
         if state_type == StateType::L || state_type == StateType::M {
             bail!("StateType L or M must be specified address. Use calc_state_with_addr.")
         }
+        // the fetches of a cached instruction are counted with the states decoded with it
+        if let Some(fetch_states) = self.fetch_states.filter(|_| state_type == StateType::I) {
+            return Ok(state * fetch_states);
+        }
         self.calc_state_with_addr(state_type, state, self.operating_pc)
     }
 
-    // todo 内蔵周辺モジュール
+    // States of `state` accesses of the type to the address, by the table of the hardware manual.
+    // I, J, K and M count word accesses and L counts byte accesses, in the bus cycles the bus counts for them.
     pub fn calc_state_with_addr(&self, state_type: StateType, state: u8, target_addr: u32) -> Result<u8> {
-        if state_type == StateType::N {
-            return Ok(state * 1);
-        }
-        match target_addr {
-            MEMORY_START_ADDR..=MEMORY_END_ADDR => match state_type {
-                StateType::N => return Ok(state * 1),
-                _ => return Ok(state * 2),
-            },
-            AREA0_START_ADDR..=AREA7_END_ADDR => {
-                let area_index = Bus::get_area_index(target_addr)?;
-                if (self.bus.read(ABWCR)? >> area_index) & 1 == 1 {
-                    // 8 bit
-
-                    // dram
-                    if self.bus.check_dram_area(area_index)? {
-                        // as 4state
-                        match state_type {
-                            StateType::I | StateType::J | StateType::K | StateType::M => {
-                                let wait_state: u8 = self.get_wait_state(area_index)?;
-                                return Ok(state * (8 + 2 * wait_state));
-                            }
-                            StateType::L => {
-                                let wait_state: u8 = self.get_wait_state(area_index)?;
-                                return Ok(state * (4 + wait_state));
-                            }
-                            StateType::N => return Ok(state * 1),
-                        }
-                    }
+        let size = match state_type {
+            StateType::N => return Ok(state),
+            StateType::L => 1,
+            _ => 2,
+        };
+        let cycles = self.bus.access_cycles(target_addr, size) as u8;
+        Ok(state * cycles * self.cycle_states(target_addr)?)
+    }
 
-                    if (self.bus.read(ASTCR)? >> area_index) & 1 == 0 {
-                        // 2 state
-                        match state_type {
-                            StateType::I | StateType::J | StateType::K | StateType::M => return Ok(state * 4),
-                            StateType::L => return Ok(state * 2),
-                            StateType::N => return Ok(state * 1),
-                        }
-                    } else {
-                        // 3 state
-                        match state_type {
-                            StateType::I | StateType::J | StateType::K | StateType::M => {
-                                let wait_state: u8 = self.get_wait_state(area_index)?;
-                                return Ok(state * (6 + 2 * wait_state));
-                            }
-                            StateType::L => {
-                                let wait_state: u8 = self.get_wait_state(area_index)?;
-                                return Ok(state * (3 + wait_state));
-                            }
-                            StateType::N => return Ok(state * 1),
-                        }
-                    }
+    // States of a bus cycle at the address
+    fn cycle_states(&self, addr: u32) -> Result<u8> {
+        match addr {
+            // on-chip memory, 2 state
+            // the on-chip RAM is in area 7 while it is disabled
+            MEMORY_START_ADDR..=MEMORY_END_ADDR | VENCTOR_START_ADDR..=ROM_END_ADDR
+                if addr <= ROM_END_ADDR || self.bus.is_ram_enabled() =>
+            {
+                Ok(2)
+            }
+            // on-chip supporting modules, 3 state
+            _ if is_io_register(addr) => Ok(3),
+            AREA0_START_ADDR..=AREA7_END_ADDR => {
+                let area_index = Bus::get_area_index(addr)?;
+                if self.bus.check_dram_area(area_index)? {
+                    // dram, as 4 state
+                    Ok(4 + self.get_wait_state(area_index)?)
+                } else if (self.bus.peek(ASTCR)? >> area_index) & 1 == 0 {
+                    // 2 state
+                    Ok(2)
                 } else {
-                    // 16 bit
-
-                    // dram
-                    if self.bus.check_dram_area(area_index)? {
-                        let wait_state: u8 = self.get_wait_state(area_index)?;
-                        return Ok(state * (4 + wait_state));
-                    }
-
-                    if (self.bus.read(ASTCR)? >> area_index) & 1 == 0 {
-                        // 2 state
-                        return Ok(state * 2);
-                    } else {
-                        // 3 state
-                        let wait_state: u8 = self.get_wait_state(area_index)?;
-                        return Ok(state * (3 + wait_state));
-                    }
+                    // 3 state
+                    Ok(3 + self.get_wait_state(area_index)?)
                 }
             }
-            _ => bail!("Invalid addr [0x{:x}]", target_addr),
+            _ => bail!("Invalid addr [0x{:x}]", addr),
         }
     }
 
-    fn init_registers(&mut self) -> Result<()> {
+    /// Sets up the bus and the DRAM as the monitor does before the program starts.
+    /// `run` does it, and functions called from the tests need it to reach the external memory.
+    pub fn init_registers(&mut self) -> Result<()> {
         self.bus.write(ABWCR, 0xff)?;
         self.bus.write(ASTCR, 0xfb)?;
         self.bus.write(WCRH, 0xff)?;
         self.bus.write(WCRL, 0xcf)?;
         self.bus.write(DRCRA, 0xe0)?;
+        // CAS-before-RAS refresh every 39 ticks of φ/8, without CMI
+        self.bus.write(DRCRB, DRCRB_RCYCE | 0x08)?;
+        self.bus.write(RTCOR, 38)?;
+        self.bus.write(RTMCSR, 0x10)?;
 
         return Ok(());
     }
 
-    fn print_er(&self) {
+    pub fn format_er(&self) -> String {
         let mut info = String::new();
         for i in 0..8 {
             info += &format!("ER{}:[0x{:x}] ", i, self.er[i]).to_string();
         }
-        log::trace!("Registers {}", info);
+        info
+    }
+
+    fn print_er(&self) {
+        log::trace!("Registers {}", self.format_er());
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        bus::AREA0_START_ADDR,
+        bus::ROM_END_ADDR,
         cpu::{Cpu, StateType},
         memory::MEMORY_START_ADDR,
-        registers::{ABWCR, ASTCR, WCRH, WCRL},
+        profiler::Profiler,
+        registers::{ABWCR, ASTCR, SYSCR, WCRH, WCRL},
+        test_report::TestReport,
     };
 
+    // area 0 after the on-chip ROM
+    const EXTERNAL_ADDR: u32 = ROM_END_ADDR + 1;
+
+    #[test]
+    fn test_run_error_and_reports() {
+        let summary = std::env::temp_dir().join(format!("run_report_{}.json", std::process::id()));
+        let mut cpu = Cpu::new();
+        cpu.profiler = Some(Profiler::new("/nonexistent/profile.txt", MEMORY_START_ADDR));
+        cpu.test_report = TestReport::new(Some(summary.to_string_lossy().into_owned()));
+        // an undefined instruction
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0x00, 0xff, 0xff]).unwrap();
+        cpu.er[2] = MEMORY_START_ADDR;
+
+        // the execution error is returned, and the reports after the failed profile are written
+        let error = cpu.run().unwrap_err();
+        assert!(format!("{:#}", error).starts_with("unimplemented instruction"));
+        assert!(summary.exists());
+        std::fs::remove_file(&summary).unwrap();
+    }
+
+    #[test]
+    fn test_set_option() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("print_messages", "on").unwrap();
+        cpu.set_option("instruction_cache", "on").unwrap();
+        assert!(cpu.config().print_messages && cpu.bus.print_messages);
+        assert!(cpu.bus.instruction_cache.enabled);
+        assert!(cpu.set_option("print_messages", "2").is_err());
+        assert!(cpu.config().print_messages);
+    }
+
     #[test]
     fn test_get_wait_state_wcrl() {
         let mut cpu = Cpu::new();
@@ -757,7 +750,7 @@ mod tests {
     #[test]
     fn test_calc_state_external_8bit_2state() {
         let mut cpu = Cpu::new();
-        cpu.operating_pc = AREA0_START_ADDR;
+        cpu.operating_pc = EXTERNAL_ADDR;
         cpu.bus.write(ABWCR, 0x01).unwrap();
         cpu.bus.write(ASTCR, 0xfe).unwrap();
         const STATE: u8 = 2;
@@ -770,7 +763,7 @@ mod tests {
     #[test]
     fn test_calc_state_external_8bit_3state() {
         let mut cpu = Cpu::new();
-        cpu.operating_pc = AREA0_START_ADDR;
+        cpu.operating_pc = EXTERNAL_ADDR;
         cpu.bus.write(ABWCR, 0x01).unwrap();
         cpu.bus.write(ASTCR, 0x01).unwrap();
         cpu.bus.write(WCRL, 0x03).unwrap();
@@ -785,7 +778,7 @@ mod tests {
     #[test]
     fn test_calc_state_external_16bit_2state() {
         let mut cpu = Cpu::new();
-        cpu.operating_pc = AREA0_START_ADDR;
+        cpu.operating_pc = EXTERNAL_ADDR;
         cpu.bus.write(ABWCR, 0xfe).unwrap();
         cpu.bus.write(ASTCR, 0xfe).unwrap();
         const STATE: u8 = 2;
@@ -798,7 +791,7 @@ mod tests {
     #[test]
     fn test_calc_state_external_16bit_3state() {
         let mut cpu = Cpu::new();
-        cpu.operating_pc = AREA0_START_ADDR;
+        cpu.operating_pc = EXTERNAL_ADDR;
         cpu.bus.write(ABWCR, 0xfe).unwrap();
         cpu.bus.write(ASTCR, 0x01).unwrap();
         cpu.bus.write(WCRL, 0x03).unwrap();
@@ -824,8 +817,8 @@ mod tests {
         cpu.bus.write(ABWCR, 0x01).unwrap();
         cpu.bus.write(ASTCR, 0xfe).unwrap();
         const STATE: u8 = 2;
-        assert_eq!(cpu.calc_state_with_addr(StateType::L, STATE, AREA0_START_ADDR).unwrap(), 2 * STATE);
-        assert_eq!(cpu.calc_state_with_addr(StateType::M, STATE, AREA0_START_ADDR).unwrap(), 4 * STATE);
+        assert_eq!(cpu.calc_state_with_addr(StateType::L, STATE, EXTERNAL_ADDR).unwrap(), 2 * STATE);
+        assert_eq!(cpu.calc_state_with_addr(StateType::M, STATE, EXTERNAL_ADDR).unwrap(), 4 * STATE);
     }
 
     #[test]
@@ -837,11 +830,11 @@ mod tests {
         const STATE: u8 = 2;
         const WAIT_STATE: u8 = 3;
         assert_eq!(
-            cpu.calc_state_with_addr(StateType::L, STATE, AREA0_START_ADDR).unwrap(),
+            cpu.calc_state_with_addr(StateType::L, STATE, EXTERNAL_ADDR).unwrap(),
             (3 + WAIT_STATE) * STATE
         );
         assert_eq!(
-            cpu.calc_state_with_addr(StateType::M, STATE, AREA0_START_ADDR).unwrap(),
+            cpu.calc_state_with_addr(StateType::M, STATE, EXTERNAL_ADDR).unwrap(),
             (6 + 2 * WAIT_STATE) * STATE
         );
     }
@@ -852,8 +845,8 @@ mod tests {
         cpu.bus.write(ABWCR, 0xfe).unwrap();
         cpu.bus.write(ASTCR, 0xfe).unwrap();
         const STATE: u8 = 2;
-        assert_eq!(cpu.calc_state_with_addr(StateType::L, STATE, AREA0_START_ADDR).unwrap(), 2 * STATE);
-        assert_eq!(cpu.calc_state_with_addr(StateType::M, STATE, AREA0_START_ADDR).unwrap(), 2 * STATE);
+        assert_eq!(cpu.calc_state_with_addr(StateType::L, STATE, EXTERNAL_ADDR).unwrap(), 2 * STATE);
+        assert_eq!(cpu.calc_state_with_addr(StateType::M, STATE, EXTERNAL_ADDR).unwrap(), 2 * STATE);
     }
 
     #[test]
@@ -865,12 +858,30 @@ mod tests {
         const STATE: u8 = 2;
         const WAIT_STATE: u8 = 3;
         assert_eq!(
-            cpu.calc_state_with_addr(StateType::L, STATE, AREA0_START_ADDR).unwrap(),
+            cpu.calc_state_with_addr(StateType::L, STATE, EXTERNAL_ADDR).unwrap(),
             (3 + WAIT_STATE) * STATE
         );
         assert_eq!(
-            cpu.calc_state_with_addr(StateType::M, STATE, AREA0_START_ADDR).unwrap(),
+            cpu.calc_state_with_addr(StateType::M, STATE, EXTERNAL_ADDR).unwrap(),
             (3 + WAIT_STATE) * STATE
         );
     }
+
+    #[test]
+    fn test_calc_state_with_addr_ram_disabled() {
+        let mut cpu = Cpu::new();
+        // the on-chip RAM is in area 7, 8 bit 2 state
+        cpu.bus.write(SYSCR, 0x08).unwrap();
+        cpu.bus.write(ABWCR, 0x80).unwrap();
+        cpu.bus.write(ASTCR, 0x7f).unwrap();
+        const STATE: u8 = 2;
+        assert_eq!(cpu.calc_state_with_addr(StateType::L, STATE, MEMORY_START_ADDR).unwrap(), 2 * STATE);
+        assert_eq!(cpu.calc_state_with_addr(StateType::M, STATE, MEMORY_START_ADDR).unwrap(), 4 * STATE);
+        // the bus counts the same cycles for a word
+        assert_eq!(cpu.bus.access_cycles(MEMORY_START_ADDR, 2), 2);
+
+        cpu.bus.write(SYSCR, 0x09).unwrap();
+        assert_eq!(cpu.calc_state_with_addr(StateType::M, STATE, MEMORY_START_ADDR).unwrap(), 2 * STATE);
+        assert_eq!(cpu.bus.access_cycles(MEMORY_START_ADDR, 2), 1);
+    }
 }
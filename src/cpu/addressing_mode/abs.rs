@@ -54,112 +54,69 @@ impl Cpu {
     pub(in super::super) fn write_abs8_w(&mut self, addr: u8, value: u16) -> Result<()> {
         let real_addr = self.get_addr_abs8(addr);
         self.bus
-            .write(real_addr, (value >> 8) as u8)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        self.bus
-            .write(real_addr + 1, value as u8)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        Ok(())
+            .write_w(real_addr, value)
+            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
     }
 
     pub(in super::super) fn read_abs8_w(&self, addr: u8) -> Result<u16> {
         let real_addr = self.get_addr_abs8(addr);
-        Ok(
-            (self.bus.read(real_addr).with_context(|| format!("addr [{:x}]", addr))? as u16) << 8
-                | self.bus.read(real_addr + 1).with_context(|| format!("addr [{:x}]", addr))? as u16,
-        )
-    }
-
-    #[inline]
-    fn _write_abs16_w(&mut self, addr: u16, value: u16) -> Result<()> {
-        let real_addr = self.get_addr_abs16(addr);
-        self.bus.write(real_addr, (value >> 8) as u8)?;
-        self.bus.write(real_addr + 1, value as u8)?;
-        Ok(())
+        self.bus.read_w(real_addr).with_context(|| format!("addr [{:x}]", addr))
     }
 
     pub(in super::super) fn write_abs16_w(&mut self, addr: u16, value: u16) -> Result<()> {
-        self._write_abs16_w(addr, value)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
-    }
-
-    #[inline]
-    fn _read_abs16_w(&self, addr: u16) -> Result<u16> {
         let real_addr = self.get_addr_abs16(addr);
-        return Ok((self.bus.read(real_addr)? as u16) << 8 | self.bus.read(real_addr + 1)? as u16);
+        self.bus
+            .write_w(real_addr, value)
+            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
     }
 
     pub(in super::super) fn read_abs16_w(&self, addr: u16) -> Result<u16> {
-        self._read_abs16_w(addr).with_context(|| format!("addr [{:x}]", addr))
+        let real_addr = self.get_addr_abs16(addr);
+        self.bus.read_w(real_addr).with_context(|| format!("addr [{:x}]", addr))
     }
 
     pub(in super::super) fn write_abs24_w(&mut self, addr: u32, value: u16) -> Result<()> {
         self.bus
-            .write(addr, (value >> 8) as u8)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        self.bus
-            .write(addr + 1, value as u8)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        Ok(())
+            .write_w(addr, value)
+            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
     }
 
     pub(in super::super) fn read_abs24_w(&self, addr: u32) -> Result<u16> {
-        Ok((self.bus.read(addr).with_context(|| format!("addr [{:x}]", addr))? as u16) << 8
-            | self.bus.read(addr + 1).with_context(|| format!("addr [{:x}]", addr))? as u16)
+        self.bus.read_w(addr).with_context(|| format!("addr [{:x}]", addr))
     }
 
     pub(in super::super) fn write_abs8_l(&mut self, addr: u8, value: u32) -> Result<()> {
         let real_addr = self.get_addr_abs8(addr);
-        self.write_abs24_w(real_addr, (value >> 16) as u16)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        self.write_abs24_w(real_addr + 2, value as u16)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        Ok(())
+        self.bus
+            .write_l(real_addr, value)
+            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
     }
 
     pub(in super::super) fn read_abs8_l(&self, addr: u8) -> Result<u32> {
         let real_addr = self.get_addr_abs8(addr);
-        Ok(
-            (self.read_abs24_w(real_addr).with_context(|| format!("addr [{:x}]", addr))? as u32) << 16
-                | self.read_abs24_w(real_addr + 2).with_context(|| format!("addr [{:x}]", addr))? as u32,
-        )
-    }
-
-    #[inline]
-    pub(in super::super) fn _write_abs16_l(&mut self, addr: u16, value: u32) -> Result<()> {
-        let real_addr = self.get_addr_abs16(addr);
-        self.write_abs24_w(real_addr, (value >> 16) as u16)?;
-        self.write_abs24_w(real_addr + 2, value as u16)?;
-        Ok(())
+        self.bus.read_l(real_addr).with_context(|| format!("addr [{:x}]", addr))
     }
 
     pub(in super::super) fn write_abs16_l(&mut self, addr: u16, value: u32) -> Result<()> {
-        self._write_abs16_l(addr, value)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
-    }
-
-    fn _read_abs16_l(&self, addr: u16) -> Result<u32> {
         let real_addr = self.get_addr_abs16(addr);
-        return Ok((self.read_abs24_w(real_addr)? as u32) << 16 | self.read_abs24_w(real_addr + 2)? as u32);
+        self.bus
+            .write_l(real_addr, value)
+            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
     }
 
     pub(in super::super) fn read_abs16_l(&self, addr: u16) -> Result<u32> {
-        self._read_abs16_l(addr).with_context(|| format!("addr [{:x}]", addr))
+        let real_addr = self.get_addr_abs16(addr);
+        self.bus.read_l(real_addr).with_context(|| format!("addr [{:x}]", addr))
     }
 
     pub(in super::super) fn write_abs24_l(&mut self, addr: u32, value: u32) -> Result<()> {
-        self.write_abs24_w(addr, (value >> 16) as u16)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        self.write_abs24_w(addr + 2, value as u16)
-            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))?;
-        Ok(())
+        self.bus
+            .write_l(addr, value)
+            .with_context(|| format!("addr [{:x}] value [{:x}]", addr, value))
     }
 
     pub(in super::super) fn read_abs24_l(&self, addr: u32) -> Result<u32> {
-        Ok(
-            (self.read_abs24_w(addr).with_context(|| format!("addr [{:x}]", addr))? as u32) << 16
-                | self.read_abs24_w(addr + 2).with_context(|| format!("addr [{:x}]", addr))? as u32,
-        )
+        self.bus.read_l(addr).with_context(|| format!("addr [{:x}]", addr))
     }
 }
 
@@ -234,9 +191,10 @@ mod tests {
 
         {
             let mut cpu = Cpu::new();
+            // bit 0 of the address is ignored
             cpu.write_abs8_w(0x01, 0x0fff).unwrap();
             assert_eq!(
-                (cpu.bus.read(0xffff01).unwrap() as u16) << 8 | cpu.bus.read(0xffff02).unwrap() as u16,
+                (cpu.bus.read(0xffff00).unwrap() as u16) << 8 | cpu.bus.read(0xffff01).unwrap() as u16,
                 0x0fff
             );
         }
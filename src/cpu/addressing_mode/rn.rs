@@ -3,6 +3,7 @@ use anyhow::{bail, Result};
 
 impl Cpu {
     pub(in super::super) fn write_rn_b(&mut self, register_field: u8, value: u8) -> Result<()> {
+        self.narrow_write = true;
         match register_field {
             // R0H..=R7H
             0..=7 => self.er[register_field as usize] = (self.er[register_field as usize] & 0xffff00ff) | ((value as u32) << 8),
@@ -24,6 +25,7 @@ impl Cpu {
     }
 
     pub(in super::super) fn write_rn_w(&mut self, register_field: u8, value: u16) -> Result<()> {
+        self.narrow_write = true;
         match register_field {
             // R0..=R7
             0..=7 => self.er[register_field as usize] = (self.er[register_field as usize] & 0xffff0000) | (value as u32),
@@ -0,0 +1,125 @@
+use crate::cpu::{Cpu, ADDRESS_MASK};
+use anyhow::{bail, Context as _, Result};
+
+// Return address of the called function. The call ends when the pc reaches it.
+const SENTINEL_RETURN_ADDR: u32 = 0xfffffe;
+const CALL_STATE_LIMIT: usize = 100_000_000;
+
+// ER0-ER2 hold the first arguments, the others are on the stack
+const REGISTER_ARGS: usize = 3;
+
+impl Cpu {
+    /// Copies the data below the stack pointer and returns its address, to pass pointers to called functions.
+    pub fn push_data(&mut self, data: &[u8]) -> Result<u32> {
+        let sp = u32::try_from(data.len())
+            .ok()
+            .and_then(|len| self.er[7].checked_sub(len))
+            .with_context(|| format!("{} bytes do not fit below the stack pointer [0x{:x}]", data.len(), self.er[7]))?;
+        self.er[7] = sp & !3;
+        for (i, value) in data.iter().enumerate() {
+            self.write_abs24_b(self.er[7] + i as u32, *value)?;
+        }
+        Ok(self.er[7])
+    }
+
+    /// Calls a function of the loaded program by the GCC H8/300H ABI and returns ER0 and ER1.
+    /// `name` is the C name, the leading underscore of the symbol can be omitted.
+    /// The stack pointer and the pc are restored when the call returns or fails.
+    pub fn call_function(&mut self, name: &str, args: &[u32]) -> Result<(u32, u32)> {
+        let symbol = self
+            .symbols
+            .find_by_name(name)
+            .or_else(|| self.symbols.find_by_name(&format!("_{}", name)))
+            .with_context(|| format!("symbol [{}] is not found", name))?;
+        let func_addr = symbol.addr;
+
+        let (sp, pc) = (self.er[7], self.pc);
+        let result = self.run_function(name, func_addr, args);
+        // The caller pops the stack arguments
+        self.er[7] = sp;
+        self.pc = pc;
+        result
+    }
+
+    fn run_function(&mut self, name: &str, func_addr: u32, args: &[u32]) -> Result<(u32, u32)> {
+        // The rest of the arguments are pushed from the last one, then the return address
+        for arg in args.iter().skip(REGISTER_ARGS).rev() {
+            self.write_dec_ern_l(7, *arg)?;
+        }
+        self.write_dec_ern_l(7, SENTINEL_RETURN_ADDR)?;
+        for (i, arg) in args.iter().take(REGISTER_ARGS).enumerate() {
+            self.er[i] = *arg;
+        }
+        self.pc = func_addr & ADDRESS_MASK;
+
+        let start_state = self.state_sum;
+        while self.pc != SENTINEL_RETURN_ADDR {
+            if self.pc == self.exit_addr {
+                bail!("[{}] called exit with {}", name, self.er[0]);
+            }
+            if self.state_sum - start_state > CALL_STATE_LIMIT {
+                bail!("[{}] did not return in {} states", name, CALL_STATE_LIMIT);
+            }
+            self.state_sum += self.try_interrupt()? as usize;
+            if self.sleep_mode.is_none() {
+                let pc = self.pc;
+                let opcode = self.fetch();
+                let state = self
+                    .exec(opcode)
+                    .with_context(|| format!("failed in [{}] at {}", name, self.symbols.format_addr(pc)))?;
+                self.state_sum += state as usize;
+                self.bus.cpu_state_sum = self.state_sum;
+                if self.pc < pc && self.can_skip_idle_loop() {
+                    self.skip_idle_loop(pc)?;
+                }
+            }
+            if let Some(mode) = self.sleep_mode {
+                self.wait_in_sleep(mode)?;
+            }
+            self.update_modules();
+        }
+        Ok((self.er[0], self.er[1]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::Cpu, elf};
+
+    fn load(path: &str) -> Cpu {
+        let mut cpu = Cpu::new();
+        elf::load(path, &mut cpu, String::new()).unwrap();
+        cpu.init_registers().unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut cpu = load("example/printf.elf");
+        let sp = cpu.er[7];
+        let hello = cpu.push_data(b"hello\0").unwrap();
+        let (er0, _) = cpu.call_function("strlen", &[hello]).unwrap();
+        assert_eq!(er0 & 0xffff, 5);
+
+        let number = cpu.push_data(b"123\0").unwrap();
+        let (er0, _) = cpu.call_function("_stoi", &[number]).unwrap();
+        assert_eq!(er0 & 0xffff, 123);
+        assert_eq!(cpu.er[7], number);
+
+        assert!(cpu.call_function("no_such_function", &[]).is_err());
+        assert!(cpu.er[7] < sp);
+    }
+
+    #[test]
+    fn test_call_function_error() {
+        let mut cpu = load("example/printf.elf");
+        let (sp, pc) = (cpu.er[7], cpu.pc);
+        let error = cpu.call_function("__exit", &[1]).unwrap_err();
+        assert_eq!(error.to_string(), "[__exit] called exit with 1");
+        assert_eq!((cpu.er[7], cpu.pc), (sp, pc));
+
+        cpu.er[7] = 4;
+        assert!(cpu.push_data(b"hello\0").is_err());
+        assert_eq!(cpu.er[7], 4);
+    }
+}
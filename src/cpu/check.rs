@@ -0,0 +1,183 @@
+use crate::{
+    cpu::{
+        decode::{decode, peek_word},
+        Cpu, UnsupportedInstruction,
+    },
+    elf::Symbol,
+};
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Instruction `exec` does not implement, found by the static scan.
+#[derive(Debug, PartialEq)]
+pub struct UnsupportedEncoding {
+    pub addr: u32,
+    // words read until the decoder gave up
+    pub words: Vec<u16>,
+    // enclosing function and the offset in it
+    pub function: String,
+}
+
+/// Instruction the scan could not decode for another reason, e.g. its words are outside the memory.
+#[derive(Debug, PartialEq)]
+pub struct DecodeFailure {
+    pub addr: u32,
+    pub function: String,
+    pub error: String,
+}
+
+/// Result of the static scan.
+pub struct ScanReport {
+    pub instructions: usize,
+    pub unsupported: Vec<UnsupportedEncoding>,
+    pub failures: Vec<DecodeFailure>,
+}
+
+// rts, rte, bra and jmp: the next instruction is reached only by a label
+fn is_unconditional_branch(opcode: u16) -> bool {
+    matches!((opcode >> 8) as u8, 0x54 | 0x56 | 0x40 | 0x59 | 0x5a | 0x5b) || opcode == 0x5800
+}
+
+// Local labels of the compiler, which also mark jump tables and constants in the code
+fn is_local_label(symbol: &Symbol) -> bool {
+    symbol.name.starts_with(".L")
+}
+
+// Enclosing function of the address and the offset in it
+fn function_at(ranges: &[Symbol], addr: u32) -> String {
+    ranges
+        .iter()
+        .filter(|s| !is_local_label(s) && s.addr <= addr)
+        .max_by_key(|s| s.addr)
+        .map_or(format!("0x{:x}", addr), |s| format!("{}+0x{:x}", s.name, addr - s.addr))
+}
+
+impl Cpu {
+    /// Decodes the instructions of the code ranges with the decoder of `exec`, from the start of each range until an unconditional branch.
+    /// Nothing is executed, the words are read with `peek` and the size of each instruction is given by the decoder.
+    /// A local label that does not start with a valid instruction is taken as data.
+    pub fn scan_unsupported(&self, ranges: &[Symbol]) -> Result<ScanReport> {
+        let mut decoded = BTreeSet::new();
+        let mut unsupported = BTreeMap::new();
+        let mut failures = BTreeMap::new();
+        for range in ranges {
+            let end = range.addr.saturating_add(range.size);
+            let mut addr = range.addr;
+            while addr < end && !decoded.contains(&addr) {
+                let result = peek_word(&self.bus, addr).and_then(|opcode| Ok((opcode, decode(&self.bus, addr, opcode)?)));
+                let (opcode, size) = match result {
+                    Ok((opcode, (_, size))) => {
+                        decoded.insert(addr);
+                        (opcode, size)
+                    }
+                    Err(e) if addr == range.addr && is_local_label(range) => {
+                        log::debug!("{} is taken as data: {:#}", range.name, e);
+                        break;
+                    }
+                    Err(e) => {
+                        let function = function_at(ranges, addr);
+                        let Some(u) = e.downcast_ref::<UnsupportedInstruction>() else {
+                            // the size is not known, the rest of the range is not decoded
+                            let error = format!("{:#}", e);
+                            failures.insert(addr, DecodeFailure { addr, function, error });
+                            break;
+                        };
+                        let words = (addr..=u.pc)
+                            .step_by(2)
+                            .map(|a| peek_word(&self.bus, a))
+                            .collect::<Result<Vec<u16>>>()?;
+                        unsupported.insert(addr, UnsupportedEncoding { addr, words, function });
+                        // the scan goes on after the words read
+                        (u.opcode, u.pc + 2 - addr)
+                    }
+                };
+                if is_unconditional_branch(opcode) {
+                    break;
+                }
+                addr = addr.saturating_add(size);
+            }
+        }
+
+        Ok(ScanReport {
+            instructions: decoded.len(),
+            unsupported: unsupported.into_values().collect(),
+            failures: failures.into_values().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsupportedEncoding;
+    use crate::{
+        bus::AREA2_END_ADDR,
+        cpu::Cpu,
+        elf::{self, Symbol},
+        memory::MEMORY_START_ADDR,
+    };
+
+    #[test]
+    fn test_scan_unsupported() {
+        let mut cpu = Cpu::new();
+        // mov.b r0l,r1l / andc #0x7f,ccr (unsupported) / jsr @er0 / mov.l #0x12345678,er0 / rts / .L1: jump table
+        let code = [
+            0x0c, 0x89, 0x06, 0x7f, 0x5d, 0x00, 0x7a, 0x00, 0x12, 0x34, 0x56, 0x78, 0x54, 0x70, 0x00, 0x00, 0x01, 0x00,
+        ];
+        cpu.bus.load(MEMORY_START_ADDR, &code).unwrap();
+        let ranges = [
+            Symbol {
+                name: "_f".to_string(),
+                addr: MEMORY_START_ADDR,
+                size: 14,
+            },
+            Symbol {
+                name: ".L1".to_string(),
+                addr: MEMORY_START_ADDR + 14,
+                size: 4,
+            },
+        ];
+        let report = cpu.scan_unsupported(&ranges).unwrap();
+        assert_eq!(report.instructions, 4);
+        assert_eq!(
+            report.unsupported,
+            vec![UnsupportedEncoding {
+                addr: MEMORY_START_ADDR + 2,
+                words: vec![0x067f],
+                function: "_f+0x2".to_string(),
+            }]
+        );
+        assert_eq!(report.failures, vec![]);
+        // nothing is executed
+        assert_eq!((cpu.pc, cpu.er), (0, [0; 8]));
+    }
+
+    #[test]
+    fn test_scan_decode_failure() {
+        let mut cpu = Cpu::new();
+        // the second word of mov.l is past the end of the DRAM, and the size of the range overflows
+        cpu.bus.load(AREA2_END_ADDR - 1, &[0x01, 0x00]).unwrap();
+        let ranges = [Symbol {
+            name: "_f".to_string(),
+            addr: AREA2_END_ADDR - 1,
+            size: u32::MAX,
+        }];
+        let report = cpu.scan_unsupported(&ranges).unwrap();
+        assert_eq!(report.instructions, 0);
+        assert_eq!(report.unsupported, vec![]);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].addr, AREA2_END_ADDR - 1);
+        assert_eq!(report.failures[0].function, "_f+0x0");
+        assert!(report.failures[0].error.contains("0x600000"));
+    }
+
+    #[test]
+    fn test_scan_example() {
+        let mut cpu = Cpu::new();
+        elf::load("example/printf.elf", &mut cpu, String::new()).unwrap();
+        let ranges = elf::load_code_ranges("example/printf.elf").unwrap();
+        let report = cpu.scan_unsupported(&ranges).unwrap();
+        assert!(report.instructions > 1000);
+        assert_eq!(report.unsupported, vec![]);
+        assert_eq!(report.failures, vec![]);
+    }
+}
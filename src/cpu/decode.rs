@@ -0,0 +1,663 @@
+use crate::{
+    bus::Bus,
+    cpu::{Cpu, StateType},
+};
+use anyhow::Result;
+
+/// Handler of a decoded instruction, called with the first word. The other words are fetched by the handler.
+pub(crate) type Handler = fn(&mut Cpu, u16) -> Result<u8>;
+
+// Longest instruction in words (e.g. mov.l @(d:24,ERs),ERd)
+const MAX_WORDS: usize = 5;
+// Direct-mapped by the word address, enough for the hot code of a program
+const CACHE_ENTRIES: usize = 1 << 13;
+
+// Handler of an instruction dispatched by its second word, which is fetched before the call
+macro_rules! second_word {
+    ($f:ident(opcode, opcode2)) => {
+        |cpu: &mut Cpu, opcode: u16| {
+            let opcode2 = cpu.fetch();
+            cpu.$f(opcode, opcode2)
+        }
+    };
+    ($f:ident(opcode2)) => {
+        |cpu: &mut Cpu, _| {
+            let opcode2 = cpu.fetch();
+            cpu.$f(opcode2)
+        }
+    };
+    ($f:ident()) => {
+        |cpu: &mut Cpu, _| {
+            cpu.fetch();
+            cpu.$f()
+        }
+    };
+}
+
+#[derive(Clone, Copy)]
+struct Decoded {
+    pc: u32,
+    handler: Handler,
+    words: [u16; MAX_WORDS],
+    len: u8,
+    // states of a word fetched at the pc, by the area and the wait states of the bus controller
+    fetch_states: u8,
+}
+
+/// Instructions decoded once and executed again without the decoder, the bus and the timing table, keyed by the pc.
+/// The words with the operands and the states of the fetch are kept with the handler.
+/// Writes to the bus invalidate the instructions they overlap, so modified code is decoded again,
+/// and writes to the registers of the bus timing drop every instruction.
+#[derive(Clone)]
+pub struct InstructionCache {
+    // allocated at the first insert
+    entries: Vec<Option<Decoded>>,
+    pub enabled: bool,
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        InstructionCache {
+            entries: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    fn index(pc: u32) -> usize {
+        (pc as usize >> 1) & (CACHE_ENTRIES - 1)
+    }
+
+    fn get(&self, pc: u32) -> Option<&Decoded> {
+        if !self.enabled {
+            return None;
+        }
+        match self.entries.get(Self::index(pc)) {
+            Some(Some(decoded)) if decoded.pc == pc => Some(decoded),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, pc: u32, handler: Handler, words: &[u16], fetch_states: u8) {
+        if !self.enabled || words.is_empty() || words.len() > MAX_WORDS {
+            return;
+        }
+        if self.entries.is_empty() {
+            self.entries = vec![None; CACHE_ENTRIES];
+        }
+        let mut decoded = Decoded {
+            pc,
+            handler,
+            words: [0; MAX_WORDS],
+            len: words.len() as u8,
+            fetch_states,
+        };
+        decoded.words[..words.len()].copy_from_slice(words);
+        self.entries[Self::index(pc)] = Some(decoded);
+    }
+
+    /// Drops every instruction, when the memory seen at the addresses changes.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drops the instructions that contain the byte at `addr`.
+    pub fn invalidate(&mut self, addr: u32) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let last = addr & !1;
+        for pc in (last.saturating_sub(2 * (MAX_WORDS as u32 - 1))..=last).step_by(2) {
+            let entry = &mut self.entries[Self::index(pc)];
+            if matches!(entry, Some(decoded) if decoded.pc == pc && addr < pc + 2 * decoded.len as u32) {
+                *entry = None;
+            }
+        }
+    }
+}
+
+/// Words of the instruction being executed: the cached words served to `fetch`, or the words read from the bus to cache.
+#[derive(Clone, Default)]
+pub(super) struct Prefetch {
+    words: [u16; MAX_WORDS],
+    next: u8,
+    len: u8,
+}
+
+impl Prefetch {
+    pub(super) fn pop(&mut self) -> Option<u16> {
+        if self.next < self.len {
+            self.next += 1;
+            Some(self.words[self.next as usize - 1])
+        } else {
+            None
+        }
+    }
+
+    // Records a word read from the bus, which is not served again
+    pub(super) fn push(&mut self, word: u16) {
+        if (self.len as usize) < MAX_WORDS {
+            self.words[self.len as usize] = word;
+            self.len += 1;
+        }
+        self.next = self.len;
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+}
+
+impl Cpu {
+    /// Serves the first word of the instruction at the pc from the cache, and keeps the rest for the handler.
+    pub(super) fn fetch_cached(&mut self, pc: u32) -> Option<u16> {
+        let decoded = *self.bus.instruction_cache.get(pc)?;
+        self.prefetch.words = decoded.words;
+        self.prefetch.next = 1;
+        self.prefetch.len = decoded.len;
+        self.cached_handler = Some(decoded.handler);
+        self.fetch_states = Some(decoded.fetch_states);
+        Some(decoded.words[0])
+    }
+
+    // Executes the instruction whose first word is fetched, with the cached handler or the decoder
+    pub(super) fn exec_decoded(&mut self, opcode: u16) -> Result<u8> {
+        if let Some(handler) = self.cached_handler.take() {
+            self.is_executing = true;
+            let result = handler(self, opcode);
+            self.is_executing = false;
+            self.fetch_states = None;
+            self.prefetch.clear();
+            return result;
+        }
+
+        let pc = self.pc.wrapping_sub(2);
+        let (handler, size) = decode(&self.bus, pc, opcode)?;
+        self.is_executing = true;
+        let result = handler(self, opcode);
+        self.is_executing = false;
+        // the words fetched from the bus while executing, kept in the prefetch buffer
+        let fetched = self.prefetch.words[0] == opcode && self.fetched_pc == pc && 2 * self.prefetch.len as u32 == size;
+        if result.is_ok() && fetched && self.bus.instruction_cache.enabled {
+            if let Ok(fetch_states) = self.calc_state_with_addr(StateType::I, 1, pc) {
+                let words = self.prefetch.words;
+                self.bus
+                    .instruction_cache
+                    .insert(pc, handler, &words[..self.prefetch.len as usize], fetch_states);
+            }
+        }
+        self.prefetch.clear();
+        result
+    }
+}
+
+pub(super) fn peek_word(bus: &Bus, addr: u32) -> Result<u16> {
+    Ok(((bus.peek(addr)? as u16) << 8) | bus.peek(addr + 1)? as u16)
+}
+
+/// Decodes the instruction at `pc` whose first word is `opcode`, reading the second word of the prefixed instructions with `peek`.
+/// Returns the handler and the size of the instruction in bytes, or `UnsupportedInstruction` with the address of the last word read.
+pub(crate) fn decode(bus: &Bus, pc: u32, opcode: u16) -> Result<(Handler, u32)> {
+    // unimpl! reports the word before the given address
+    let (first, second) = (pc + 2, pc + 4);
+    let (handler, words): (Handler, u32) = match (opcode >> 8) as u8 {
+        0x0c | 0xf0..=0xff | 0x68 | 0x6c | 0x20..=0x2f | 0x30..=0x3f => (Cpu::mov_b, 1),
+        0x6e => (Cpu::mov_b, 2),
+        0x6a => match opcode & 0x00f0 {
+            0x00 | 0x80 => (Cpu::mov_b, 2),
+            0x20 | 0xa0 => (Cpu::mov_b, 3),
+            _ => unimpl!(opcode, first),
+        },
+        0x0d | 0x69 | 0x6d => (Cpu::mov_w, 1),
+        0x6f => (Cpu::mov_w, 2),
+        0x6b => match opcode & 0x00f0 {
+            0x00 | 0x80 => (Cpu::mov_w, 2),
+            0x20 | 0xa0 => (Cpu::mov_w, 3),
+            _ => unimpl!(opcode, first),
+        },
+        0x0f => match opcode & 0x0080 {
+            0x80 => (Cpu::mov_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x01 => match opcode as u8 {
+            0x00 => {
+                let opcode2 = peek_word(bus, pc + 2)?;
+                match (opcode2 >> 8) as u8 {
+                    0x69 | 0x6d => (Cpu::mov_l, 2),
+                    0x6f => (Cpu::mov_l, 3),
+                    0x78 => (Cpu::mov_l, 5),
+                    0x6b => match opcode2 & 0x00f0 {
+                        0x00 | 0x80 => (Cpu::mov_l, 3),
+                        0x20 | 0xa0 => (Cpu::mov_l, 4),
+                        _ => unimpl!(opcode, second),
+                    },
+                    _ => unimpl!(opcode, second),
+                }
+            }
+            0x80 => (|cpu, _| cpu.sleep(), 1),
+            0x40 => {
+                let opcode2 = peek_word(bus, pc + 2)?;
+                match (opcode2 >> 8) as u8 {
+                    0x69 => (second_word!(stc_w_ern(opcode2)), 2),
+                    0x6f => (second_word!(stc_w_disp16(opcode2)), 3),
+                    0x78 => (second_word!(stc_w_disp24(opcode2)), 5),
+                    0x6d => (second_word!(stc_w_inc_ern(opcode2)), 2),
+                    0x6b => match opcode2 as u8 {
+                        0x80 => (second_word!(stc_abs16()), 3),
+                        0xa0 => (second_word!(stc_abs24()), 4),
+                        _ => unimpl!(opcode, second),
+                    },
+                    _ => unimpl!(opcode, second),
+                }
+            }
+            0xf0 => {
+                let opcode2 = peek_word(bus, pc + 2)?;
+                match (opcode2 >> 8) as u8 {
+                    0x64 => (second_word!(or_l_rn(opcode, opcode2)), 2),
+                    0x65 => (second_word!(xor_l_rn(opcode, opcode2)), 2),
+                    0x66 => (second_word!(and_l_rn(opcode, opcode2)), 2),
+                    _ => unimpl!(opcode, second),
+                }
+            }
+            _ => unimpl!(opcode, first),
+        },
+
+        0x02 => (Cpu::stc_b, 1),
+
+        0x50 => (Cpu::mulxu_b, 1),
+        0x52 => (Cpu::mulxu_w, 1),
+
+        0x51 => (Cpu::divxu_b, 1),
+        0x53 => (Cpu::divxu_w, 1),
+
+        0x55 => (Cpu::bsr_disp16, 1),
+        0x5c => (Cpu::bsr_disp24, 2),
+
+        0x60 => (Cpu::bset_rn_from_rn, 1),
+        0x61 => (Cpu::bnot_rn_from_rn, 1),
+        0x62 => (Cpu::bclr_rn_from_rn, 1),
+        0x63 => (Cpu::btst_rn_rn, 1),
+
+        0x67 => match opcode & 0x80 {
+            0x00 => (Cpu::bst_rn, 1),
+            _ => (Cpu::bist_rn, 1),
+        },
+
+        0x70 => (Cpu::bset_rn_from_imm, 1),
+        0x71 => (Cpu::bnot_rn_from_imm, 1),
+        0x72 => (Cpu::bclr_rn_from_imm, 1),
+        0x73 => (Cpu::btst_imm_rn, 1),
+
+        0x74 => match opcode & 0x80 {
+            0x00 => (Cpu::bor_rn, 1),
+            _ => (Cpu::bior_rn, 1),
+        },
+
+        0x75 => match opcode & 0x80 {
+            0x00 => (Cpu::bxor_rn, 1),
+            _ => (Cpu::bixor_rn, 1),
+        },
+
+        0x76 => match opcode & 0x80 {
+            0x00 => (Cpu::band_rn, 1),
+            _ => (Cpu::biand_rn, 1),
+        },
+
+        0x77 => match opcode & 0x80 {
+            0x00 => (Cpu::bld_rn, 1),
+            _ => (Cpu::bild_rn, 1),
+        },
+
+        0x78 => {
+            let opcode2 = peek_word(bus, pc + 2)?;
+            match (opcode2 >> 8) as u8 {
+                0x6a => (second_word!(mov_b_disp24(opcode, opcode2)), 4),
+                0x6b => (second_word!(mov_w_disp24(opcode, opcode2)), 4),
+                _ => unimpl!(opcode, second),
+            }
+        }
+
+        0x79 => match opcode & 0x00f0 {
+            0x0 => (Cpu::mov_w, 2),
+            0x0010 => (Cpu::add_w, 2),
+            0x0020 => (Cpu::cmp_w_imm, 2),
+            0x0030 => (Cpu::sub_w, 2),
+            0x0040 => (Cpu::or_w_imm, 2),
+            0x0050 => (Cpu::xor_w_imm, 2),
+            0x0060 => (Cpu::and_w_imm, 2),
+            _ => unimpl!(opcode, first),
+        },
+
+        // the register is ERd, bit 3 is 0
+        0x7a => match opcode & 0x00f8 {
+            0x0 => (Cpu::mov_l, 3),
+            0x0010 => (Cpu::add_l, 3),
+            0x0020 => (Cpu::cmp_l_imm, 3),
+            0x0030 => (Cpu::sub_l, 3),
+            0x0040 => (Cpu::or_l_imm, 3),
+            0x0050 => (Cpu::xor_l_imm, 3),
+            0x0060 => (Cpu::and_l_imm, 3),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x7c => {
+            let opcode2 = peek_word(bus, pc + 2)?;
+            match opcode2 & 0xff80 {
+                0x6300 | 0x6380 => (second_word!(btst_rn_ern(opcode, opcode2)), 2),
+                0x7300 => (second_word!(btst_imm_ern(opcode, opcode2)), 2),
+                0x7400 => (second_word!(bor_ern(opcode, opcode2)), 2),
+                0x7480 => (second_word!(bior_ern(opcode, opcode2)), 2),
+                0x7500 => (second_word!(bxor_ern(opcode, opcode2)), 2),
+                0x7580 => (second_word!(bixor_ern(opcode, opcode2)), 2),
+                0x7600 => (second_word!(band_ern(opcode, opcode2)), 2),
+                0x7680 => (second_word!(biand_ern(opcode, opcode2)), 2),
+                0x7700 => (second_word!(bld_ern(opcode, opcode2)), 2),
+                0x7780 => (second_word!(bild_ern(opcode, opcode2)), 2),
+                _ => unimpl!(opcode, second),
+            }
+        }
+
+        0x7d => {
+            let opcode2 = peek_word(bus, pc + 2)?;
+            match opcode2 & 0xff80 {
+                // bset, bnot and bclr take the bit number in bits 4-6 only
+                0x6000 | 0x6080 | 0x7000 if opcode2 & 0x000f == 0 => (second_word!(bset_ern(opcode, opcode2)), 2),
+                0x6100 | 0x6180 | 0x7100 if opcode2 & 0x000f == 0 => (second_word!(bnot_ern(opcode, opcode2)), 2),
+                0x6200 | 0x6280 | 0x7200 if opcode2 & 0x000f == 0 => (second_word!(bclr_ern(opcode, opcode2)), 2),
+                0x6700 => (second_word!(bst_ern(opcode, opcode2)), 2),
+                0x6780 => (second_word!(bist_ern(opcode, opcode2)), 2),
+                _ => unimpl!(opcode, second),
+            }
+        }
+
+        0x7e => {
+            let opcode2 = peek_word(bus, pc + 2)?;
+            match opcode2 & 0xff80 {
+                0x6300 | 0x6380 => (second_word!(btst_rn_abs(opcode, opcode2)), 2),
+                0x7300 => (second_word!(btst_imm_abs(opcode, opcode2)), 2),
+                0x7400 => (second_word!(bor_abs(opcode, opcode2)), 2),
+                0x7480 => (second_word!(bior_abs(opcode, opcode2)), 2),
+                0x7500 => (second_word!(bxor_abs(opcode, opcode2)), 2),
+                0x7580 => (second_word!(bixor_abs(opcode, opcode2)), 2),
+                0x7600 => (second_word!(band_abs(opcode, opcode2)), 2),
+                0x7680 => (second_word!(biand_abs(opcode, opcode2)), 2),
+                0x7700 => (second_word!(bld_abs(opcode, opcode2)), 2),
+                0x7780 => (second_word!(bild_abs(opcode, opcode2)), 2),
+                _ => unimpl!(opcode, second),
+            }
+        }
+
+        0x7f => {
+            let opcode2 = peek_word(bus, pc + 2)?;
+            match opcode2 & 0xff80 {
+                0x6000 | 0x6080 | 0x7000 if opcode2 & 0x000f == 0 => (second_word!(bset_abs(opcode, opcode2)), 2),
+                0x6100 | 0x6180 | 0x7100 if opcode2 & 0x000f == 0 => (second_word!(bnot_abs(opcode, opcode2)), 2),
+                0x6200 | 0x6280 | 0x7200 if opcode2 & 0x000f == 0 => (second_word!(bclr_abs(opcode, opcode2)), 2),
+                0x6700 => (second_word!(bst_abs(opcode, opcode2)), 2),
+                0x6780 => (second_word!(bist_abs(opcode, opcode2)), 2),
+                _ => unimpl!(opcode, second),
+            }
+        }
+
+        0x0a => match opcode as u8 {
+            0x00..=0x0f => (Cpu::inc_b, 1),
+            0x80..=0xf7 => (Cpu::add_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x0b => match opcode as u8 {
+            0x50..=0x5f => (Cpu::inc_w_1, 1),
+            0xd0..=0xdf => (Cpu::inc_w_2, 1),
+            0x70..=0x77 => (Cpu::inc_l_1, 1),
+            0xf0..=0xf7 => (Cpu::inc_l_2, 1),
+            0x00..=0x07 => (Cpu::adds1, 1),
+            0x80..=0x87 => (Cpu::adds2, 1),
+            0x90..=0x97 => (Cpu::adds4, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x10 => match opcode as u8 {
+            0x00..=0x0f => (Cpu::shll_b, 1),
+            0x10..=0x1f => (Cpu::shll_w, 1),
+            0x30..=0x37 => (Cpu::shll_l, 1),
+            0x80..=0x8f => (Cpu::shal_b, 1),
+            0x90..=0x9f => (Cpu::shal_w, 1),
+            0xb0..=0xb7 => (Cpu::shal_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x11 => match opcode as u8 {
+            0x00..=0x0f => (Cpu::shlr_b, 1),
+            0x10..=0x1f => (Cpu::shlr_w, 1),
+            0x30..=0x3f => (Cpu::shlr_l, 1),
+            0x80..=0x8f => (Cpu::shar_b, 1),
+            0x90..=0x9f => (Cpu::shar_w, 1),
+            0xb0..=0xb7 => (Cpu::shar_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x12 => match opcode as u8 {
+            0x00..=0x0f => (Cpu::rotxl_b, 1),
+            0x10..=0x1f => (Cpu::rotxl_w, 1),
+            0x30..=0x37 => (Cpu::rotxl_l, 1),
+            0x80..=0x8f => (Cpu::rotl_b, 1),
+            0x90..=0x9f => (Cpu::rotl_w, 1),
+            0xb0..=0xb7 => (Cpu::rotl_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x13 => match opcode as u8 {
+            0x00..=0x0f => (Cpu::rotxr_b, 1),
+            0x10..=0x1f => (Cpu::rotxr_w, 1),
+            0x30..=0x37 => (Cpu::rotxr_l, 1),
+            0x80..=0x8f => (Cpu::rotr_b, 1),
+            0x90..=0x9f => (Cpu::rotr_w, 1),
+            0xb0..=0xb7 => (Cpu::rotr_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x17 => match opcode as u8 {
+            0x00..=0x0f => (Cpu::not_b, 1),
+            0x10..=0x1f => (Cpu::not_w, 1),
+            0x30..=0x37 => (Cpu::not_l, 1),
+            0x50..=0x5f => (Cpu::extu_w, 1),
+            0x70..=0x77 => (Cpu::extu_l, 1),
+            0x80..=0x8f => (Cpu::neg_b, 1),
+            0x90..=0x9f => (Cpu::neg_w, 1),
+            0xb0..=0xb7 => (Cpu::neg_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x1a => match opcode as u8 {
+            0x00..=0x0f => (Cpu::dec_b, 1),
+            0x80..=0xf7 => (Cpu::sub_l, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x1b => match opcode as u8 {
+            0x50..=0x5f => (Cpu::dec_w_1, 1),
+            0xd0..=0xdf => (Cpu::dec_w_2, 1),
+            0x70..=0x77 => (Cpu::dec_l_1, 1),
+            0xf0..=0xf7 => (Cpu::dec_l_2, 1),
+            0x00..=0x07 => (Cpu::subs1, 1),
+            0x80..=0x87 => (Cpu::subs2, 1),
+            0x90..=0x97 => (Cpu::subs4, 1),
+            _ => unimpl!(opcode, first),
+        },
+
+        0x80..=0x8f | 0x08 => (Cpu::add_b, 1),
+        0x09 => (Cpu::add_w, 1),
+
+        0x18 => (Cpu::sub_b, 1),
+        0x19 => (Cpu::sub_w, 1),
+
+        0x1c => (Cpu::cmp_b_rn, 1),
+        0xa0..=0xaf => (Cpu::cmp_b_imm, 1),
+        0x1d => (Cpu::cmp_w_rn, 1),
+        0x1f => (Cpu::cmp_l_rn, 1),
+
+        0xc0..=0xcf => (Cpu::or_b_imm, 1),
+        0x14 => (Cpu::or_b_rn, 1),
+        0x64 => (Cpu::or_w_rn, 1),
+
+        0xd0..=0xdf => (Cpu::xor_b_imm, 1),
+        0x15 => (Cpu::xor_b_rn, 1),
+        0x65 => (Cpu::xor_w_rn, 1),
+
+        0xe0..=0xef => (Cpu::and_b_imm, 1),
+        0x16 => (Cpu::and_b_rn, 1),
+        0x66 => (Cpu::and_w_rn, 1),
+
+        0x90..=0x9f => (Cpu::addx_imm, 1),
+        0x0e => (Cpu::addx_rn, 1),
+
+        0x59 | 0x5b => (Cpu::jmp, 1),
+        0x5a => (Cpu::jmp, 2),
+        0x5d | 0x5f => (Cpu::jsr, 1),
+        0x5e => (Cpu::jsr, 2),
+        0x40..=0x4f => (Cpu::bcc, 1),
+        // the condition is in bits 4-7
+        0x58 => match opcode & 0x000f {
+            0 => (Cpu::bcc, 2),
+            _ => unimpl!(opcode, first),
+        },
+        0x54 => (|cpu, _| cpu.rts(), 1),
+        0x56 => (|cpu, _| cpu.rte(), 1),
+        0x57 => (Cpu::trapa, 1),
+        _ => unimpl!(opcode, first),
+    };
+    Ok((handler, 2 * words))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::AREA2_START_ADDR,
+        config::EmulatorConfig,
+        cpu::{Cpu, StateType},
+        elf,
+        memory::MEMORY_START_ADDR,
+        registers::WCRL,
+    };
+    use std::time::Instant;
+
+    fn exec_at(cpu: &mut Cpu, pc: u32) -> u8 {
+        cpu.pc = pc;
+        let opcode = cpu.fetch();
+        cpu.exec(opcode).unwrap()
+    }
+
+    #[test]
+    fn test_cache_hit() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("instruction_cache", "on").unwrap();
+        // mov.l #0x12345678,er0 / mov.l #0,er0
+        cpu.bus
+            .load(MEMORY_START_ADDR, &[0x7a, 0x00, 0x12, 0x34, 0x56, 0x78, 0x7a, 0x00, 0, 0, 0, 0])
+            .unwrap();
+        exec_at(&mut cpu, MEMORY_START_ADDR);
+        assert!(cpu.bus.instruction_cache.get(MEMORY_START_ADDR).is_some());
+
+        cpu.er[0] = 0;
+        // the words are served by the cache, the memory is not read
+        cpu.bus.memory[2..6].copy_from_slice(&[0; 4]);
+        assert_eq!(exec_at(&mut cpu, MEMORY_START_ADDR), 6);
+        assert_eq!(cpu.er[0], 0x12345678);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + 6);
+
+        // the next instruction is not served from the rest of the cached words
+        exec_at(&mut cpu, MEMORY_START_ADDR + 6);
+        assert_eq!(cpu.er[0], 0);
+    }
+
+    #[test]
+    fn test_cache_second_word() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("instruction_cache", "on").unwrap();
+        // stc.w ccr,@(0x10,er1) / and.l er0,er1
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0x40, 0x6f, 0x90, 0x00, 0x10]).unwrap();
+        cpu.bus.load(MEMORY_START_ADDR + 6, &[0x01, 0xf0, 0x66, 0x01]).unwrap();
+        for _ in 0..2 {
+            cpu.er[0] = 0xff00;
+            cpu.er[1] = MEMORY_START_ADDR + 0x100;
+            cpu.ccr = 0x85;
+            exec_at(&mut cpu, MEMORY_START_ADDR);
+            assert_eq!(cpu.bus.peek(MEMORY_START_ADDR + 0x111).unwrap(), 0x85);
+            exec_at(&mut cpu, MEMORY_START_ADDR + 6);
+            assert_eq!(cpu.er[1], (MEMORY_START_ADDR + 0x100) & 0xff00);
+        }
+        assert!(cpu.bus.instruction_cache.get(MEMORY_START_ADDR).is_some());
+        assert!(cpu.bus.instruction_cache.get(MEMORY_START_ADDR + 6).is_some());
+    }
+
+    #[test]
+    fn test_cache_invalidate() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("instruction_cache", "on").unwrap();
+        // mov.w #0x1234,r0 / mov.b #0x56,r0l
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x00, 0x12, 0x34, 0xf8, 0x56]).unwrap();
+        exec_at(&mut cpu, MEMORY_START_ADDR);
+        exec_at(&mut cpu, MEMORY_START_ADDR + 4);
+
+        // a write to the immediate of the first instruction
+        cpu.bus.write(MEMORY_START_ADDR + 3, 0x00).unwrap();
+        assert!(cpu.bus.instruction_cache.get(MEMORY_START_ADDR).is_none());
+        assert!(cpu.bus.instruction_cache.get(MEMORY_START_ADDR + 4).is_some());
+        exec_at(&mut cpu, MEMORY_START_ADDR);
+        assert_eq!(cpu.er[0] & 0xffff, 0x1200);
+
+        cpu.bus.load(MEMORY_START_ADDR + 5, &[0x78]).unwrap();
+        assert!(cpu.bus.instruction_cache.get(MEMORY_START_ADDR + 4).is_none());
+        exec_at(&mut cpu, MEMORY_START_ADDR + 4);
+        assert_eq!(cpu.er[0] & 0xff, 0x78);
+    }
+
+    #[test]
+    fn test_cache_fetch_states() {
+        let mut cpu = Cpu::new();
+        cpu.init_registers().unwrap();
+        // mov.b r0l,r1l in the DRAM, fetched with the wait states of area 2
+        cpu.bus.load(AREA2_START_ADDR, &[0x0c, 0x89]).unwrap();
+        let before = exec_at(&mut cpu, AREA2_START_ADDR);
+        assert_eq!(exec_at(&mut cpu, AREA2_START_ADDR), before);
+
+        // 3 wait states in area 2
+        cpu.bus.write(WCRL, 0xff).unwrap();
+        assert!(cpu.bus.instruction_cache.get(AREA2_START_ADDR).is_none());
+        let states = exec_at(&mut cpu, AREA2_START_ADDR);
+        assert!(states > before);
+        assert_eq!(states, cpu.calc_state_with_addr(StateType::I, 1, AREA2_START_ADDR).unwrap());
+        assert_eq!(exec_at(&mut cpu, AREA2_START_ADDR), states);
+        assert!(cpu.bus.instruction_cache.get(AREA2_START_ADDR).is_some());
+    }
+
+    // Instructions per second of the wait loop of one.elf
+    fn measure_ips(cache_enabled: bool) -> f64 {
+        let mut cpu = Cpu::new();
+        cpu.set_config(EmulatorConfig {
+            print_instruction: false,
+            deterministic: true,
+            instruction_cache: cache_enabled,
+            // every iteration of the loop is executed
+            idle_skip: false,
+            ..Default::default()
+        });
+        elf::load("example/one.elf", &mut cpu, String::new()).unwrap();
+        cpu.max_states = Some(100_000_000);
+        let start = Instant::now();
+        // stopped by max_states, one.elf does not exit
+        assert!(cpu.run().is_err());
+        cpu.instruction_count as f64 / start.elapsed().as_secs_f64()
+    }
+
+    // cargo test --release bench_instructions_per_second -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_instructions_per_second() {
+        let uncached = measure_ips(false);
+        let cached = measure_ips(true);
+        println!("without cache: {:.0} instructions/sec", uncached);
+        println!("with cache:    {:.0} instructions/sec ({:.2}x)", cached, cached / uncached);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::cpu::{
+    decode::{decode, peek_word},
+    Cpu, SleepMode,
+};
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+// Loops of up to this many instructions, the branch excluded, are checked
+const MAX_LOOP_INSTRUCTIONS: usize = 16;
+// Shorter skips are not worth the checks, and leave room for the two iterations run before the next event
+const MIN_SKIP_STATES: usize = 1000;
+
+// Addresses read and written in an iteration, true for a write
+type Accesses = Vec<(u32, bool)>;
+
+// Instructions from the branch target to the branch
+struct LoopBody {
+    // addresses of the instructions, the branch last
+    addrs: Vec<u32>,
+    // end of the branch
+    end: u32,
+    // the branch is taken by the sign, which changes once while a register counts less than 2^31
+    signed_branch: bool,
+}
+
+fn advance(registers: &[u32; 8], delta: &[u32; 8], iterations: usize) -> [u32; 8] {
+    std::array::from_fn(|i| registers[i].wrapping_add(delta[i].wrapping_mul(iterations as u32)))
+}
+
+impl Cpu {
+    // The tools and the printing see every instruction, so loops are not skipped while they are used
+    pub(super) fn can_skip_idle_loop(&self) -> bool {
+        self.config.idle_skip
+            && !self.config.print_instruction
+            && self.tracer.is_none()
+            && self.profiler.is_none()
+            && self.coverage.is_none()
+            && self.stack_usage.is_none()
+            && self.debugger.is_none()
+            && self.bus.memcheck.is_none()
+    }
+
+    /// Waits at the sleep instruction for an interrupt, and returns the states passed.
+    /// The sleep mode jumps to the next event of the modules, 1msec of the guest at most so the socket messages are not delayed.
+    /// The clock stops in software standby, which only NMI from the socket leaves.
+    pub(super) fn wait_in_sleep(&mut self, mode: SleepMode) -> Result<usize> {
+        #[cfg(not(test))]
+        let has_socket = self.socket.is_some();
+        #[cfg(test)]
+        let has_socket = false;
+        match mode {
+            SleepMode::Sleep => {
+                let next_event = self.bus.module_manager.get_mut().next_event();
+                if next_event == usize::MAX && !has_socket {
+                    bail!("sleep with no interrupt to wake up");
+                }
+                let states = next_event.saturating_sub(self.state_sum).min(self.states_per_msec());
+                self.state_sum += states;
+                self.bus.cpu_state_sum = self.state_sum;
+                self.idle_states += states;
+                Ok(states)
+            }
+            SleepMode::SoftwareStandby => {
+                if !has_socket {
+                    bail!("software standby with no NMI to wake up");
+                }
+                std::thread::sleep(Duration::from_millis(1));
+                Ok(0)
+            }
+        }
+    }
+
+    /// Fast-forwards a loop which only counts registers or polls a fixed address, after the branch at `branch_pc` jumped back.
+    /// Two iterations are run to see how the registers change and what the loop reads and writes in an iteration,
+    /// then the registers and the state count jump to the last iteration before the loop exits or the next event of the modules.
+    /// A skip is 1msec of the guest at most, so the socket messages are not delayed and the pacing does not stall.
+    /// Returns the states of the iterations run and skipped, which are added to the state count.
+    pub(super) fn skip_idle_loop(&mut self, branch_pc: u32) -> Result<usize> {
+        if self.busy_loops.contains(&branch_pc) || self.interrupt_controller.has_requests() {
+            return Ok(0);
+        }
+        let target = self.pc;
+        let Some(body) = self.decode_idle_loop(target, branch_pc) else {
+            self.busy_loops.insert(branch_pc);
+            return Ok(0);
+        };
+
+        let next_event = self.bus.module_manager.get_mut().next_event();
+        let budget = next_event.saturating_sub(self.state_sum).min(self.states_per_msec());
+        if budget < MIN_SKIP_STATES {
+            return Ok(0);
+        }
+
+        let start = self.state_sum;
+        let first = self.er;
+        self.narrow_write = false;
+        let Some((states, accesses)) = self.run_logged_iteration(&body)? else {
+            return Ok(self.state_sum - start);
+        };
+        let second = self.er;
+        let ccr = self.ccr;
+        // polls a fixed address, which can change by the socket messages
+        let reads = !accesses.is_empty();
+        let writes = accesses.iter().any(|(_, write)| *write);
+        // the registers of the modules change between their events, e.g. a counter polled with its interrupts off
+        let module_manager = self.bus.module_manager.get_mut();
+        let polls_module = accesses.iter().any(|(addr, _)| module_manager.is_claimed(*addr));
+        if self.run_logged_iteration(&body)? != Some((states, accesses)) {
+            return Ok(self.state_sum - start);
+        }
+        let delta: [u32; 8] = std::array::from_fn(|i| second[i].wrapping_sub(first[i]));
+        let counts = delta.iter().any(|d| *d != 0);
+        // the projection of a longword register does not follow a byte or a word register over a carry
+        let narrow = self.narrow_write;
+        if writes
+            || polls_module
+            || advance(&second, &delta, 1) != self.er
+            || self.ccr != ccr
+            || (counts && (narrow || reads || !body.signed_branch))
+        {
+            self.busy_loops.insert(branch_pc);
+            return Ok(self.state_sum - start);
+        }
+
+        let third = self.er;
+        let mut max_iterations = (budget - (self.state_sum - start)) / states;
+        for d in delta.iter().filter(|d| **d != 0) {
+            max_iterations = max_iterations.min(i32::MAX as usize / (*d as i32).unsigned_abs() as usize);
+        }
+        let iterations = if counts {
+            self.search_idle_exit(&body, &third, &delta, ccr, states, max_iterations)
+        } else {
+            // every iteration is the same until a module or the socket changes the memory
+            max_iterations
+        };
+
+        self.er = advance(&third, &delta, iterations);
+        self.ccr = ccr;
+        self.pc = target;
+        self.state_sum += iterations * states;
+        self.bus.cpu_state_sum = self.state_sum;
+        self.idle_states += iterations * states;
+        Ok(self.state_sum - start)
+    }
+
+    // Iterations going on before the exit, found by doubling the probed iteration and then a binary search.
+    // The exit condition is monotonic, as a register counting less than 2^31 changes its sign once.
+    #[allow(clippy::too_many_arguments)]
+    fn search_idle_exit(
+        &mut self,
+        body: &LoopBody,
+        registers: &[u32; 8],
+        delta: &[u32; 8],
+        ccr: u8,
+        states: usize,
+        max_iterations: usize,
+    ) -> usize {
+        let (mut low, mut high) = (0, max_iterations);
+        let mut step = 1;
+        while low < high {
+            let probe = (low + step).min(high) - 1;
+            if !self.probe_idle_iteration(body, registers, delta, ccr, probe, states) {
+                high = probe;
+                break;
+            }
+            low = probe + 1;
+            step *= 2;
+        }
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.probe_idle_iteration(body, registers, delta, ccr, middle, states) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    // The loop from the branch target, which must end with a conditional branch or bra at `branch_pc`.
+    // The instructions are walked with the decoder, what they do is seen when the iterations run.
+    fn decode_idle_loop(&self, target: u32, branch_pc: u32) -> Option<LoopBody> {
+        let mut addrs = Vec::new();
+        let mut addr = target;
+        while addr < branch_pc {
+            if addrs.len() >= MAX_LOOP_INSTRUCTIONS || addr == self.exit_addr {
+                return None;
+            }
+            addrs.push(addr);
+            let (_, size) = decode(&self.bus, addr, peek_word(&self.bus, addr).ok()?).ok()?;
+            addr += size;
+        }
+        let opcode = peek_word(&self.bus, branch_pc).ok()?;
+        let (_, size) = decode(&self.bus, branch_pc, opcode).ok()?;
+        let condition = match opcode >> 8 {
+            0x40..=0x4f => (opcode >> 8) as u8 & 0x0f,
+            0x58 => (opcode >> 4) as u8 & 0x0f,
+            _ => return None,
+        };
+        addrs.push(branch_pc);
+        (addr == branch_pc && branch_pc != self.exit_addr).then_some(LoopBody {
+            addrs,
+            end: branch_pc + size,
+            // bpl, bmi, bge, blt, bgt and ble
+            signed_branch: condition >= 0xa,
+        })
+    }
+
+    // Runs the body and the branch of the loop, Some(states) if it jumped back to the target.
+    // It stops at an instruction which does not go on to the next one, sleeps or exits.
+    fn run_idle_iteration(&mut self, body: &LoopBody) -> Result<Option<usize>> {
+        let mut states = 0;
+        self.pc = body.addrs[0];
+        let mut goes_on = true;
+        for addr in &body.addrs {
+            if self.pc != *addr || self.sleep_mode.is_some() || self.exit_code.is_some() {
+                goes_on = false;
+                break;
+            }
+            let opcode = self.fetch();
+            states += self.exec(opcode)? as usize;
+        }
+        self.state_sum += states;
+        self.bus.cpu_state_sum = self.state_sum;
+        Ok((goes_on && self.pc == body.addrs[0] && self.sleep_mode.is_none()).then_some(states))
+    }
+
+    // Runs an iteration with the accesses logged, and returns the addresses read and written out of the loop code
+    fn run_logged_iteration(&mut self, body: &LoopBody) -> Result<Option<(usize, Accesses)>> {
+        let saved = self.bus.access_log.replace(Default::default());
+        let result = self.run_idle_iteration(body);
+        let log = std::mem::replace(&mut self.bus.access_log, saved).unwrap_or_default().into_inner();
+        let code = body.addrs[0]..body.end;
+        let accesses = log
+            .into_iter()
+            .filter(|(addr, _, write)| *write || !code.contains(addr))
+            .map(|(addr, _, write)| (addr, write))
+            .collect();
+        Ok(result?.map(|states| (states, accesses)))
+    }
+
+    // Runs the iteration after the `iterations` from the registers and tells whether it goes on the same way.
+    // The registers and the state count are restored.
+    #[allow(clippy::too_many_arguments)]
+    fn probe_idle_iteration(
+        &mut self,
+        body: &LoopBody,
+        registers: &[u32; 8],
+        delta: &[u32; 8],
+        ccr: u8,
+        iterations: usize,
+        states: usize,
+    ) -> bool {
+        let state_sum = self.state_sum;
+        self.er = advance(registers, delta, iterations);
+        self.state_sum += iterations * states;
+        self.bus.cpu_state_sum = self.state_sum;
+        let result = self.run_idle_iteration(body);
+        let goes_on =
+            matches!(result, Ok(Some(s)) if s == states) && self.er == advance(registers, delta, iterations + 1) && self.ccr == ccr;
+        self.state_sum = state_sum;
+        self.bus.cpu_state_sum = state_sum;
+        goes_on
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::EmulatorConfig, cpu::Cpu, elf};
+
+    fn call_my_wait(config: EmulatorConfig) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_config(EmulatorConfig {
+            print_instruction: false,
+            ..config
+        });
+        elf::load("example/one.elf", &mut cpu, String::new()).unwrap();
+        cpu.init_registers().unwrap();
+        cpu.call_function("myWait", &[]).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_skip_count_loop() {
+        let expected = call_my_wait(EmulatorConfig {
+            idle_skip: false,
+            ..Default::default()
+        });
+        assert_eq!(expected.idle_states, 0);
+
+        for config in [
+            EmulatorConfig::default(),
+            EmulatorConfig {
+                deterministic: true,
+                ..Default::default()
+            },
+        ] {
+            let cpu = call_my_wait(config);
+            assert!(cpu.idle_states > expected.state_sum / 2);
+            assert_eq!(cpu.state_sum, expected.state_sum);
+            assert_eq!((cpu.er, cpu.ccr), (expected.er, expected.ccr));
+        }
+    }
+
+    #[test]
+    fn test_skip_polling_loop() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("deterministic", "on").unwrap();
+        // loop: mov.b @0xffff10:8,r0l / beq loop
+        let code = [0x28, 0x10, 0x47, 0xfc];
+        cpu.bus.load(0xffc000, &code).unwrap();
+        cpu.pc = 0xffc000;
+        assert_eq!(cpu.skip_idle_loop(0xffc002).unwrap(), cpu.states_per_msec() / 8 * 8);
+        assert_eq!(cpu.pc, 0xffc000);
+
+        // exits when the polled value is set
+        cpu.bus.load(0xffff10, &[1]).unwrap();
+        assert_eq!(cpu.skip_idle_loop(0xffc002).unwrap(), 8);
+        assert_eq!(cpu.pc, 0xffc004);
+    }
+
+    #[test]
+    fn test_polling_timer() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("deterministic", "on").unwrap();
+        // 8-bit timer 0 at φ/8192 without interrupts
+        cpu.bus.write(0xffff80, 0x03).unwrap();
+        // loop: mov.b @0xff88:8,r0l / beq loop
+        cpu.bus.load(0xffc000, &[0x28, 0x88, 0x47, 0xfc]).unwrap();
+        cpu.pc = 0xffc000;
+        // the counter changes with no event of the modules, so the loop runs every iteration
+        cpu.skip_idle_loop(0xffc002).unwrap();
+        assert!(cpu.busy_loops.contains(&0xffc002));
+        assert_eq!(cpu.idle_states, 0);
+        assert!(cpu.state_sum < 8192);
+    }
+
+    #[test]
+    fn test_skip_at_most_1msec() {
+        let mut cpu = Cpu::new();
+        cpu.set_option("deterministic", "on").unwrap();
+        // loop: dec.l #1,er0 / bpl loop
+        cpu.bus.load(0xffc000, &[0x1b, 0x70, 0x4a, 0xfc]).unwrap();
+        cpu.er[0] = 0x7fffffff;
+        cpu.pc = 0xffc000;
+        let states = cpu.skip_idle_loop(0xffc002).unwrap();
+        assert!(states > 0 && states <= cpu.states_per_msec());
+        // dec.l takes 2 states and bpl 4
+        assert_eq!(states, (0x7fffffff - cpu.er[0] as usize) * 6);
+    }
+
+    #[test]
+    fn test_busy_loop() {
+        // loop: mov.b r0l,@er1 / bra loop
+        // loop: inc.b r0l / bne loop
+        for (code, states) in [([0x68, 0x98, 0x40, 0xfc], 16), ([0x0a, 0x08, 0x46, 0xfc], 12)] {
+            let mut cpu = Cpu::new();
+            cpu.bus.load(0xffc000, &code).unwrap();
+            cpu.pc = 0xffc000;
+            // the two iterations run to see what the loop does
+            assert_eq!(cpu.skip_idle_loop(0xffc002).unwrap(), states);
+            assert!(cpu.busy_loops.contains(&0xffc002));
+            assert_eq!(cpu.skip_idle_loop(0xffc002).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_loop_not_going_on() {
+        let mut cpu = Cpu::new();
+        // loop: sleep / bra loop
+        cpu.bus.load(0xffc000, &[0x01, 0x80, 0x40, 0xfc]).unwrap();
+        cpu.pc = 0xffc000;
+        cpu.skip_idle_loop(0xffc002).unwrap();
+        assert!(cpu.sleep_mode.is_some());
+        assert!(!cpu.busy_loops.contains(&0xffc002));
+    }
+}
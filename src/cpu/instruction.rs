@@ -46,6 +46,7 @@ mod shal;
 mod shar;
 mod shll;
 mod shlr;
+mod sleep;
 mod stc;
 mod sub_b;
 mod sub_l;
@@ -66,3 +67,206 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::AREA2_START_ADDR,
+        cpu::Cpu,
+        memory::MEMORY_START_ADDR,
+        registers::{ABWCR, ASTCR, BRCR, DRCRA, P8DDR, WCRL},
+    };
+
+    // Operands of the instructions in the on-chip memory: registers, @aa:8 (0xffff10), @aa:16 and @aa:24 (0xffc000)
+    const SCRATCH_ADDR: u32 = 0xffc100;
+
+    // Instruction, encoding and I, J, K, L, M, N of the table of the execution states (advanced mode) of the hardware manual
+    #[rustfmt::skip]
+    const STATE_TABLE: &[(&str, &[u8], [u8; 6])] = &[
+        ("mov.b #xx:8,r0l", &[0xf8, 0x12], [1, 0, 0, 0, 0, 0]),
+        ("mov.b r0l,r1l", &[0x0c, 0x89], [1, 0, 0, 0, 0, 0]),
+        ("mov.b @er0,r1l", &[0x68, 0x09], [1, 0, 0, 1, 0, 0]),
+        ("mov.b @(d:16,er0),r1l", &[0x6e, 0x09, 0x00, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("mov.b @(d:24,er0),r1l", &[0x78, 0x00, 0x6a, 0x29, 0x00, 0x00, 0x00, 0x10], [4, 0, 0, 1, 0, 0]),
+        ("mov.b @er0+,r1l", &[0x6c, 0x09], [1, 0, 0, 1, 0, 2]),
+        ("mov.b @aa:8,r1l", &[0x29, 0x10], [1, 0, 0, 1, 0, 0]),
+        ("mov.b @aa:16,r1l", &[0x6a, 0x09, 0xc0, 0x00], [2, 0, 0, 1, 0, 0]),
+        ("mov.b @aa:24,r1l", &[0x6a, 0x29, 0x00, 0xff, 0xc0, 0x00], [3, 0, 0, 1, 0, 0]),
+        ("mov.b r1l,@er0", &[0x68, 0x89], [1, 0, 0, 1, 0, 0]),
+        ("mov.b r1l,@-er0", &[0x6c, 0x89], [1, 0, 0, 1, 0, 2]),
+        ("mov.b r1l,@aa:8", &[0x39, 0x10], [1, 0, 0, 1, 0, 0]),
+        ("mov.w #xx:16,r0", &[0x79, 0x00, 0x12, 0x34], [2, 0, 0, 0, 0, 0]),
+        ("mov.w r0,r1", &[0x0d, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("mov.w @er0,r1", &[0x69, 0x01], [1, 0, 0, 0, 1, 0]),
+        ("mov.w @(d:16,er0),r1", &[0x6f, 0x01, 0x00, 0x10], [2, 0, 0, 0, 1, 0]),
+        ("mov.w @er0+,r1", &[0x6d, 0x01], [1, 0, 0, 0, 1, 2]),
+        ("mov.w @aa:16,r1", &[0x6b, 0x01, 0xc0, 0x00], [2, 0, 0, 0, 1, 0]),
+        ("mov.w @aa:24,r1", &[0x6b, 0x21, 0x00, 0xff, 0xc0, 0x00], [3, 0, 0, 0, 1, 0]),
+        ("mov.w r1,@er0", &[0x69, 0x81], [1, 0, 0, 0, 1, 0]),
+        ("mov.w r1,@-er0", &[0x6d, 0x81], [1, 0, 0, 0, 1, 2]),
+        ("mov.l #xx:32,er0", &[0x7a, 0x00, 0x12, 0x34, 0x56, 0x78], [3, 0, 0, 0, 0, 0]),
+        ("mov.l er0,er1", &[0x0f, 0x81], [1, 0, 0, 0, 0, 0]),
+        ("mov.l @er0,er1", &[0x01, 0x00, 0x69, 0x01], [2, 0, 0, 0, 2, 0]),
+        ("mov.l @(d:16,er0),er1", &[0x01, 0x00, 0x6f, 0x01, 0x00, 0x10], [3, 0, 0, 0, 2, 0]),
+        ("mov.l @(d:24,er0),er1", &[0x01, 0x00, 0x78, 0x00, 0x6b, 0x21, 0x00, 0x00, 0x00, 0x10], [5, 0, 0, 0, 2, 0]),
+        ("mov.l @er0+,er1", &[0x01, 0x00, 0x6d, 0x01], [2, 0, 0, 0, 2, 2]),
+        ("mov.l @aa:16,er1", &[0x01, 0x00, 0x6b, 0x01, 0xc0, 0x00], [3, 0, 0, 0, 2, 0]),
+        ("mov.l @aa:24,er1", &[0x01, 0x00, 0x6b, 0x21, 0x00, 0xff, 0xc0, 0x00], [4, 0, 0, 0, 2, 0]),
+        ("mov.l er1,@er0", &[0x01, 0x00, 0x69, 0x81], [2, 0, 0, 0, 2, 0]),
+        ("mov.l er1,@-er0", &[0x01, 0x00, 0x6d, 0x81], [2, 0, 0, 0, 2, 2]),
+        ("add.b #xx:8,r0l", &[0x88, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("add.b r0l,r1l", &[0x08, 0x89], [1, 0, 0, 0, 0, 0]),
+        ("add.w #xx:16,r0", &[0x79, 0x10, 0x00, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("add.w r0,r1", &[0x09, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("add.l #xx:32,er0", &[0x7a, 0x10, 0x00, 0x00, 0x00, 0x01], [3, 0, 0, 0, 0, 0]),
+        ("add.l er0,er1", &[0x0a, 0x81], [1, 0, 0, 0, 0, 0]),
+        ("adds #4,er0", &[0x0b, 0x90], [1, 0, 0, 0, 0, 0]),
+        ("addx #xx:8,r0l", &[0x98, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("addx r0l,r1l", &[0x0e, 0x89], [1, 0, 0, 0, 0, 0]),
+        ("cmp.b #xx:8,r0l", &[0xa8, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("cmp.w #xx:16,r0", &[0x79, 0x20, 0x00, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("cmp.l #xx:32,er0", &[0x7a, 0x20, 0x00, 0x00, 0x00, 0x01], [3, 0, 0, 0, 0, 0]),
+        ("cmp.l er0,er1", &[0x1f, 0x81], [1, 0, 0, 0, 0, 0]),
+        ("sub.b r0l,r1l", &[0x18, 0x89], [1, 0, 0, 0, 0, 0]),
+        ("sub.w #xx:16,r0", &[0x79, 0x30, 0x00, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("sub.l #xx:32,er0", &[0x7a, 0x30, 0x00, 0x00, 0x00, 0x01], [3, 0, 0, 0, 0, 0]),
+        ("sub.l er0,er1", &[0x1a, 0x81], [1, 0, 0, 0, 0, 0]),
+        ("subs #4,er0", &[0x1b, 0x90], [1, 0, 0, 0, 0, 0]),
+        ("inc.b r0l", &[0x0a, 0x08], [1, 0, 0, 0, 0, 0]),
+        ("inc.l #1,er0", &[0x0b, 0x70], [1, 0, 0, 0, 0, 0]),
+        ("dec.w #1,r0", &[0x1b, 0x50], [1, 0, 0, 0, 0, 0]),
+        ("neg.b r0l", &[0x17, 0x88], [1, 0, 0, 0, 0, 0]),
+        ("extu.w r0", &[0x17, 0x50], [1, 0, 0, 0, 0, 0]),
+        ("extu.l er0", &[0x17, 0x70], [1, 0, 0, 0, 0, 0]),
+        ("mulxu.b r0l,r1", &[0x50, 0x81], [1, 0, 0, 0, 0, 12]),
+        ("mulxu.w r0,er1", &[0x52, 0x01], [1, 0, 0, 0, 0, 20]),
+        ("divxu.b r0l,r1", &[0x51, 0x81], [1, 0, 0, 0, 0, 12]),
+        ("divxu.w r0,er1", &[0x53, 0x01], [1, 0, 0, 0, 0, 20]),
+        ("and.b #xx:8,r0l", &[0xe8, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("and.w #xx:16,r0", &[0x79, 0x60, 0x00, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("and.l #xx:32,er0", &[0x7a, 0x60, 0x00, 0x00, 0x00, 0x01], [3, 0, 0, 0, 0, 0]),
+        ("and.l er0,er1", &[0x01, 0xf0, 0x66, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("or.b r0l,r1l", &[0x14, 0x89], [1, 0, 0, 0, 0, 0]),
+        ("or.l er0,er1", &[0x01, 0xf0, 0x64, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("xor.w r0,r1", &[0x65, 0x01], [1, 0, 0, 0, 0, 0]),
+        ("xor.l er0,er1", &[0x01, 0xf0, 0x65, 0x01], [2, 0, 0, 0, 0, 0]),
+        ("not.b r0l", &[0x17, 0x08], [1, 0, 0, 0, 0, 0]),
+        ("shll.b r0l", &[0x10, 0x08], [1, 0, 0, 0, 0, 0]),
+        ("shlr.l er0", &[0x11, 0x30], [1, 0, 0, 0, 0, 0]),
+        ("shal.w r0", &[0x10, 0x90], [1, 0, 0, 0, 0, 0]),
+        ("shar.b r0l", &[0x11, 0x88], [1, 0, 0, 0, 0, 0]),
+        ("rotl.b r0l", &[0x12, 0x88], [1, 0, 0, 0, 0, 0]),
+        ("rotr.l er0", &[0x13, 0xb0], [1, 0, 0, 0, 0, 0]),
+        ("rotxl.w r0", &[0x12, 0x10], [1, 0, 0, 0, 0, 0]),
+        ("rotxr.b r0l", &[0x13, 0x08], [1, 0, 0, 0, 0, 0]),
+        ("bset #1,r0l", &[0x70, 0x18], [1, 0, 0, 0, 0, 0]),
+        ("bset #1,@er0", &[0x7d, 0x00, 0x70, 0x10], [2, 0, 0, 2, 0, 0]),
+        ("bset #1,@aa:8", &[0x7f, 0x10, 0x70, 0x10], [2, 0, 0, 2, 0, 0]),
+        ("bset r1l,@er0", &[0x7d, 0x00, 0x60, 0x90], [2, 0, 0, 2, 0, 0]),
+        ("bclr #1,@er0", &[0x7d, 0x00, 0x72, 0x10], [2, 0, 0, 2, 0, 0]),
+        ("bclr #1,@aa:8", &[0x7f, 0x10, 0x72, 0x10], [2, 0, 0, 2, 0, 0]),
+        ("bnot #1,@er0", &[0x7d, 0x00, 0x71, 0x10], [2, 0, 0, 2, 0, 0]),
+        ("btst #1,r0l", &[0x73, 0x18], [1, 0, 0, 0, 0, 0]),
+        ("btst #1,@er0", &[0x7c, 0x00, 0x73, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("btst #1,@aa:8", &[0x7e, 0x10, 0x73, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("bld #1,@er0", &[0x7c, 0x00, 0x77, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("bild #1,@er0", &[0x7c, 0x00, 0x77, 0x90], [2, 0, 0, 1, 0, 0]),
+        ("bst #1,@er0", &[0x7d, 0x00, 0x67, 0x10], [2, 0, 0, 2, 0, 0]),
+        ("bist #1,@aa:8", &[0x7f, 0x10, 0x67, 0x90], [2, 0, 0, 2, 0, 0]),
+        ("band #1,@er0", &[0x7c, 0x00, 0x76, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("biand #1,@er0", &[0x7c, 0x00, 0x76, 0x90], [2, 0, 0, 1, 0, 0]),
+        ("bor #1,@er0", &[0x7c, 0x00, 0x74, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("bior #1,@aa:8", &[0x7e, 0x10, 0x74, 0x90], [2, 0, 0, 1, 0, 0]),
+        ("bxor #1,@er0", &[0x7c, 0x00, 0x75, 0x10], [2, 0, 0, 1, 0, 0]),
+        ("bixor #1,@er0", &[0x7c, 0x00, 0x75, 0x90], [2, 0, 0, 1, 0, 0]),
+        ("bra d:8", &[0x40, 0x10], [2, 0, 0, 0, 0, 0]),
+        ("beq d:8", &[0x47, 0x10], [2, 0, 0, 0, 0, 0]),
+        ("bra d:16", &[0x58, 0x00, 0x00, 0x10], [2, 0, 0, 0, 0, 2]),
+        ("bne d:16", &[0x58, 0x60, 0x00, 0x10], [2, 0, 0, 0, 0, 2]),
+        ("jmp @er0", &[0x59, 0x00], [2, 0, 0, 0, 0, 0]),
+        ("jmp @aa:24", &[0x5a, 0xff, 0xc0, 0x00], [2, 0, 0, 0, 0, 2]),
+        ("jmp @@aa:8", &[0x5b, 0x10], [2, 2, 0, 0, 0, 2]),
+        ("bsr d:8", &[0x55, 0x10], [2, 0, 2, 0, 0, 0]),
+        ("bsr d:16", &[0x5c, 0x00, 0x00, 0x10], [2, 0, 2, 0, 0, 2]),
+        ("jsr @er0", &[0x5d, 0x00], [2, 0, 2, 0, 0, 0]),
+        ("jsr @aa:24", &[0x5e, 0xff, 0xc0, 0x00], [2, 0, 2, 0, 0, 2]),
+        ("jsr @@aa:8", &[0x5f, 0x10], [2, 2, 2, 0, 0, 0]),
+        ("rts", &[0x54, 0x70], [2, 0, 2, 0, 0, 2]),
+        ("rte", &[0x56, 0x70], [2, 0, 2, 0, 0, 2]),
+        ("trapa #1", &[0x57, 0x10], [2, 2, 2, 0, 0, 4]),
+        ("sleep", &[0x01, 0x80], [1, 0, 0, 0, 0, 0]),
+        ("stc ccr,r0l", &[0x02, 0x08], [1, 0, 0, 0, 0, 0]),
+        ("stc ccr,@er0", &[0x01, 0x40, 0x69, 0x80], [2, 0, 0, 0, 1, 0]),
+        ("stc ccr,@(d:16,er0)", &[0x01, 0x40, 0x6f, 0x80, 0x00, 0x10], [3, 0, 0, 0, 1, 0]),
+        ("stc ccr,@-er0", &[0x01, 0x40, 0x6d, 0x80], [2, 0, 0, 0, 1, 2]),
+        ("stc ccr,@aa:16", &[0x01, 0x40, 0x6b, 0x80, 0xc0, 0x00], [3, 0, 0, 0, 1, 0]),
+        ("stc ccr,@aa:24", &[0x01, 0x40, 0x6b, 0xa0, 0x00, 0xff, 0xc0, 0x00], [4, 0, 0, 0, 1, 0]),
+    ];
+
+    // Executes the instruction at `code_addr` with the registers pointing to `data_addr`
+    fn exec_states(cpu: &Cpu, encoding: &[u8], code_addr: u32, data_addr: u32) -> u8 {
+        let mut cpu = cpu.clone();
+        cpu.bus.load(code_addr, encoding).unwrap();
+        cpu.er = [data_addr; 8];
+        cpu.pc = code_addr;
+        let opcode = cpu.fetch();
+        cpu.exec(opcode).unwrap()
+    }
+
+    // Area 2 as an external area without the DRAM interface, CS2 and A0-A20 are output
+    fn external_area2(bus_8bit: bool, three_state: bool, wait_state: u8) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.bus.write(DRCRA, 0).unwrap();
+        cpu.bus.write(P8DDR, 0x04).unwrap();
+        for port_ddr in [0xfee000, 0xfee001, 0xfee004] {
+            cpu.bus.write(port_ddr, 0xff).unwrap();
+        }
+        cpu.bus.write(BRCR, 0xee).unwrap();
+        cpu.bus.write(ABWCR, if bus_8bit { 0x04 } else { 0 }).unwrap();
+        cpu.bus.write(ASTCR, if three_state { 0x04 } else { 0 }).unwrap();
+        cpu.bus.write(WCRL, wait_state << 4).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_states_by_manual() {
+        let cpu = Cpu::new();
+        for (name, encoding, [i, j, k, l, m, n]) in STATE_TABLE {
+            // every access to the on-chip memory takes 2 states
+            let expected = 2 * (i + j + k + l + m) + n;
+            assert_eq!(exec_states(&cpu, encoding, MEMORY_START_ADDR, SCRATCH_ADDR), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_states_external_fetch() {
+        // instruction fetches from an 8 bit 2 state area take 2 cycles of 2 states each
+        let cpu = external_area2(true, false, 0);
+        for (name, encoding, [i, j, k, l, m, n]) in STATE_TABLE {
+            let expected = 4 * i + 2 * (j + k + l + m) + n;
+            assert_eq!(exec_states(&cpu, encoding, AREA2_START_ADDR, SCRATCH_ADDR), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_states_data_access() {
+        // mov.b @er0,r1l / mov.w @er0,r1 / mov.l @er0,er1, fetched from the on-chip memory in 2, 2 and 4 states
+        const MOV_B: &[u8] = &[0x68, 0x09];
+        const MOV_W: &[u8] = &[0x69, 0x01];
+        const MOV_L: &[u8] = &[0x01, 0x00, 0x69, 0x01];
+        let cases = [
+            // on-chip supporting modules on the 8 bit bus, 3 states a byte
+            (Cpu::new(), 0xfee0c0, [5, 8, 16]),
+            // the 16 bit timer on the 16 bit bus, 3 states a word
+            (Cpu::new(), 0xffff6a, [5, 5, 10]),
+            (external_area2(false, false, 0), AREA2_START_ADDR, [4, 4, 8]),
+            (external_area2(true, false, 0), AREA2_START_ADDR, [4, 6, 12]),
+            (external_area2(false, true, 1), AREA2_START_ADDR, [6, 6, 12]),
+            (external_area2(true, true, 1), AREA2_START_ADDR, [6, 10, 20]),
+        ];
+        for (cpu, data_addr, expected) in cases {
+            let states = [MOV_B, MOV_W, MOV_L].map(|encoding| exec_states(&cpu, encoding, MEMORY_START_ADDR, data_addr));
+            assert_eq!(states, expected, "0x{:x}", data_addr);
+        }
+    }
+}
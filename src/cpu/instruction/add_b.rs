@@ -1,12 +1,12 @@
 use crate::cpu::{Cpu, StateType, CCR};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn add_b(&mut self, opcode: u16) -> Result<u8> {
         match (opcode >> 8) as u8 {
             0x80..=0x8f => return self.add_b_imm(opcode),
             0x08 => return self.add_b_rn(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
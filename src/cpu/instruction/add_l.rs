@@ -1,12 +1,12 @@
 use crate::cpu::{Cpu, StateType, CCR};
-use anyhow::{bail, Context as _, Result};
+use anyhow::{Context as _, Result};
 
 impl Cpu {
     pub(in super::super) fn add_l(&mut self, opcode: u16) -> Result<u8> {
         match (opcode >> 8) as u8 {
             0x7a => return self.add_l_imm(opcode),
             0x0a => return self.add_l_rn(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
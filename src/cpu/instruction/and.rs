@@ -139,7 +139,7 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.pc = MEMORY_START_ADDR;
 
-        cpu.bus.memory[0..2].copy_from_slice(&[0xe0, 0x80]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0xe0, 0x80]).unwrap();
         cpu.write_rn_b(0, 0xaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -148,7 +148,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0).unwrap(), 0x80);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0xef, 0x80]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0xef, 0x80]).unwrap();
         cpu.write_rn_b(0xf, 0xaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -157,7 +157,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0xf).unwrap(), 0x80);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0xe0, 0xaa]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0xe0, 0xaa]).unwrap();
         cpu.write_rn_b(0, 0x55).unwrap();
         let opcode = cpu.fetch();
         cpu.exec(opcode).unwrap();
@@ -170,7 +170,7 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.pc = MEMORY_START_ADDR;
 
-        cpu.bus.memory[0..2].copy_from_slice(&[0x16, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x16, 0x0f]).unwrap();
         cpu.write_rn_b(0, 0xaf).unwrap();
         cpu.write_rn_b(0xf, 0x80).unwrap();
         let opcode = cpu.fetch();
@@ -183,7 +183,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0).unwrap(), 0xaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x16, 0xf0]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x16, 0xf0]).unwrap();
         cpu.write_rn_b(0xf, 0xaf).unwrap();
         cpu.write_rn_b(0, 0x80).unwrap();
         let opcode = cpu.fetch();
@@ -196,7 +196,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0xf).unwrap(), 0xaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x16, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x16, 0x0f]).unwrap();
         cpu.write_rn_b(0xf, 0xaa).unwrap();
         cpu.write_rn_b(0, 0x55).unwrap();
         let opcode = cpu.fetch();
@@ -210,7 +210,7 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.pc = MEMORY_START_ADDR;
 
-        cpu.bus.memory[0..4].copy_from_slice(&[0x79, 0x60, 0x80, 0x80]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x60, 0x80, 0x80]).unwrap();
         cpu.write_rn_w(0, 0xafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -219,7 +219,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0).unwrap(), 0x8080);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x79, 0x6f, 0x80, 0x80]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x6f, 0x80, 0x80]).unwrap();
         cpu.write_rn_w(0xf, 0xafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -228,7 +228,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0xf).unwrap(), 0x8080);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x79, 0x60, 0xaa, 0xa]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x60, 0xaa, 0xa]).unwrap();
         cpu.write_rn_w(0, 0x5555).unwrap();
         let opcode = cpu.fetch();
         cpu.exec(opcode).unwrap();
@@ -241,7 +241,7 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.pc = MEMORY_START_ADDR;
 
-        cpu.bus.memory[0..2].copy_from_slice(&[0x66, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x66, 0x0f]).unwrap();
         cpu.write_rn_w(0, 0xafaf).unwrap();
         cpu.write_rn_w(0xf, 0x8080).unwrap();
         let opcode = cpu.fetch();
@@ -255,7 +255,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0).unwrap(), 0xafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x66, 0xf0]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x66, 0xf0]).unwrap();
         cpu.write_rn_w(0xf, 0xafaf).unwrap();
         cpu.write_rn_w(0, 0x8080).unwrap();
         let opcode = cpu.fetch();
@@ -269,7 +269,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0xf).unwrap(), 0xafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x66, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x66, 0x0f]).unwrap();
         cpu.write_rn_w(0xf, 0xaa).unwrap();
         cpu.write_rn_w(0, 0x55).unwrap();
         let opcode = cpu.fetch();
@@ -283,7 +283,7 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.pc = MEMORY_START_ADDR;
 
-        cpu.bus.memory[0..6].copy_from_slice(&[0x7a, 0x60, 0x80, 0x80, 0x80, 0x80]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x7a, 0x60, 0x80, 0x80, 0x80, 0x80]).unwrap();
         cpu.write_rn_l(0, 0xafafafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -292,7 +292,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(0).unwrap(), 0x80808080);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..6].copy_from_slice(&[0x7a, 0x67, 0x80, 0x80, 0x80, 0x80]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x7a, 0x67, 0x80, 0x80, 0x80, 0x80]).unwrap();
         cpu.write_rn_l(0x7, 0xafafafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -301,7 +301,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(0x7).unwrap(), 0x80808080);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..6].copy_from_slice(&[0x7a, 0x60, 0xaa, 0xaa, 0xaa, 0xaa]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x7a, 0x60, 0xaa, 0xaa, 0xaa, 0xaa]).unwrap();
         cpu.write_rn_l(0, 0x55555555).unwrap();
         let opcode = cpu.fetch();
         cpu.exec(opcode).unwrap();
@@ -314,7 +314,7 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.pc = MEMORY_START_ADDR;
 
-        cpu.bus.memory[0..4].copy_from_slice(&[0x01, 0xf0, 0x66, 0x07]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0xf0, 0x66, 0x07]).unwrap();
         cpu.write_rn_l(0, 0xafafafaf).unwrap();
         cpu.write_rn_l(7, 0x80808080).unwrap();
         let opcode = cpu.fetch();
@@ -327,7 +327,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(0).unwrap(), 0xafafafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x01, 0xf0, 0x66, 0x70]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0xf0, 0x66, 0x70]).unwrap();
         cpu.write_rn_l(7, 0xafafafaf).unwrap();
         cpu.write_rn_l(0, 0x80808080).unwrap();
         let opcode = cpu.fetch();
@@ -340,7 +340,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(7).unwrap(), 0xafafafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x01, 0xf0, 0x66, 0x70]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0xf0, 0x66, 0x70]).unwrap();
         cpu.write_rn_l(7, 0xaaaaaaaa).unwrap();
         cpu.write_rn_l(0, 0x55555555).unwrap();
         let opcode = cpu.fetch();
@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, CCR};
-use anyhow::{bail, Context as _, Result};
+use anyhow::{Context as _, Result};
 
 impl Cpu {
     pub(in super::super) fn bcc(&mut self, opcode: u16) -> Result<u8> {
@@ -37,9 +37,9 @@ impl Cpu {
                 0xd0 => return self.blt16(),
                 0xe0 => return self.bgt16(),
                 0xf0 => return self.ble16(),
-                _ => bail!("invalid opcode [{:>04x}]", opcode),
+                _ => unimpl!(opcode, self.pc),
             },
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
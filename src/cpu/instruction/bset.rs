@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn bset_rn_from_imm(&mut self, opcode: u16) -> Result<u8> {
@@ -34,7 +34,7 @@ impl Cpu {
                 let value = self.read_ern_b(register_ern)?;
                 self.write_ern_b(register_ern, value | (1 << bit))?;
             }
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
         Ok(self.calc_state(StateType::I, 2)? + self.calc_state_with_addr(StateType::L, 2, access_addr)?)
     }
@@ -52,7 +52,7 @@ impl Cpu {
                 let value = self.read_abs8_b(opcode as u8)?;
                 self.write_abs8_b(opcode as u8, value | (1 << bit))?;
             }
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
         let access_addr = self.get_addr_abs8(opcode as u8);
         Ok(self.calc_state(StateType::I, 2)? + self.calc_state_with_addr(StateType::L, 2, access_addr)?)
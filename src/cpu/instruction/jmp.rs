@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn jmp(&mut self, opcode: u16) -> Result<u8> {
@@ -7,7 +7,7 @@ impl Cpu {
             0x59 => return self.jmp_ern(opcode),
             0x5a => return self.jmp_abs(opcode),
             0x5b => return self.jmp_indirect(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
@@ -26,7 +26,7 @@ impl Cpu {
         self.pc = addr;
         let access_addr = self.get_addr_abs8(opcode as u8);
         Ok(self.calc_state(StateType::I, 2)?
-            + self.calc_state_with_addr(StateType::J, 1, access_addr)?
+            + self.calc_state_with_addr(StateType::J, 2, access_addr)?
             + self.calc_state(StateType::N, 2)?)
     }
 }
@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, ADDRESS_MASK};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn jsr(&mut self, opcode: u16) -> Result<u8> {
@@ -7,7 +7,7 @@ impl Cpu {
             0x5d => return self.jsr_ern(opcode),
             0x5e => return self.jsr_abs(opcode),
             0x5f => return self.jsr_indirect(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
@@ -31,6 +31,7 @@ impl Cpu {
     }
 
     fn jsr_indirect(&mut self, opcode: u16) -> Result<u8> {
+        let stack_addr = (self.read_rn_l(7)? - 4) & ADDRESS_MASK;
         let abs_addr = (opcode & 0x00ff) as u8;
         self.write_dec_ern_l(7, self.pc)?;
         let addr = self.read_abs8_l(abs_addr)?;
@@ -38,6 +39,6 @@ impl Cpu {
         let access_addr = self.get_addr_abs8(opcode as u8);
         Ok(self.calc_state(StateType::I, 2)?
             + self.calc_state_with_addr(StateType::J, 2, access_addr)?
-            + self.calc_state(StateType::K, 2)?)
+            + self.calc_state_with_addr(StateType::K, 2, stack_addr)?)
     }
 }
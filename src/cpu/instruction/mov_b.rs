@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, ADDRESS_MASK, CCR};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn mov_b(&mut self, opcode: u16) -> Result<u8> {
@@ -11,7 +11,7 @@ impl Cpu {
             0x6c => return self.mov_b_inc_or_dec(opcode),
             0x20..=0x2f | 0x30..=0x3f => return self.mov_b_abs8(opcode),
             0x6a => return self.mov_b_abs_16_or_24(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
@@ -130,7 +130,7 @@ impl Cpu {
         match opcode & 0xfff0 {
             0x6a00 | 0x6a80 => return self.mov_b_abs16(opcode),
             0x6a20 | 0x6aa0 => return self.mov_b_abs24(opcode),
-            _ => bail!("invalid opcode [{:x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, ADDRESS_MASK, CCR};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn mov_l(&mut self, opcode: u16) -> Result<u8> {
@@ -18,9 +18,9 @@ impl Cpu {
             0x6b => match opcode2 & 0xfff0 {
                 0x6b00 | 0x6b80 => return self.mov_l_abs16(opcode2),
                 0x6b20 | 0x6ba0 => return self.mov_l_abs24(opcode2),
-                _ => bail!("invalid opcode2 [{:x}]", opcode2),
+                _ => unimpl!(opcode, self.pc),
             },
-            _ => bail!("invalid opcode [{:>04x} {:>04x}]", opcode, opcode2),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
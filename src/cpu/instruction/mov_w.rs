@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, ADDRESS_MASK, CCR};
-use anyhow::{bail, Result};
+use anyhow::Result;
 
 impl Cpu {
     pub(in super::super) fn mov_w(&mut self, opcode: u16) -> Result<u8> {
@@ -12,9 +12,9 @@ impl Cpu {
             0x6b => match opcode & 0xfff0 {
                 0x6b00 | 0x6b80 => return self.mov_w_abs16(opcode),
                 0x6b20 | 0x6ba0 => return self.mov_w_abs24(opcode),
-                _ => bail!("invalid opcode [{:x}]", opcode),
+                _ => unimpl!(opcode, self.pc),
             },
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
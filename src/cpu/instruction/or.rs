@@ -139,7 +139,7 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0xc0, 0x30]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0xc0, 0x30]).unwrap();
         cpu.write_rn_b(0, 0xaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -148,7 +148,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0).unwrap(), 0xbf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0xcf, 0x30]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0xcf, 0x30]).unwrap();
         cpu.write_rn_b(0xf, 0xaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -157,7 +157,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0xf).unwrap(), 0xbf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0xc0, 0x00]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0xc0, 0x00]).unwrap();
         cpu.write_rn_b(0, 0).unwrap();
         let opcode = cpu.fetch();
         cpu.exec(opcode).unwrap();
@@ -170,7 +170,7 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x14, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x14, 0x0f]).unwrap();
         cpu.write_rn_b(0, 0xaf).unwrap();
         cpu.write_rn_b(0xf, 0x30).unwrap();
         let opcode = cpu.fetch();
@@ -183,7 +183,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0).unwrap(), 0xaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x14, 0xf0]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x14, 0xf0]).unwrap();
         cpu.write_rn_b(0xf, 0xaf).unwrap();
         cpu.write_rn_b(0, 0x30).unwrap();
         let opcode = cpu.fetch();
@@ -196,7 +196,7 @@ mod tests {
         assert_eq!(cpu.read_rn_b(0xf).unwrap(), 0xaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x14, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x14, 0x0f]).unwrap();
         cpu.write_rn_b(0xf, 0).unwrap();
         cpu.write_rn_b(0, 0).unwrap();
         let opcode = cpu.fetch();
@@ -210,7 +210,7 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x79, 0x40, 0x30, 0x30]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x40, 0x30, 0x30]).unwrap();
         cpu.write_rn_w(0, 0xafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -219,7 +219,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0).unwrap(), 0xbfbf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x79, 0x4f, 0x30, 0x30]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x4f, 0x30, 0x30]).unwrap();
         cpu.write_rn_w(0xf, 0xafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -228,7 +228,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0xf).unwrap(), 0xbfbf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x79, 0x40, 0x00, 0x00]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x79, 0x40, 0x00, 0x00]).unwrap();
         cpu.write_rn_w(0, 0).unwrap();
         let opcode = cpu.fetch();
         cpu.exec(opcode).unwrap();
@@ -241,7 +241,7 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x64, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x64, 0x0f]).unwrap();
         cpu.write_rn_w(0, 0xafaf).unwrap();
         cpu.write_rn_w(0xf, 0x3030).unwrap();
         let opcode = cpu.fetch();
@@ -255,7 +255,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0).unwrap(), 0xafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x64, 0xf0]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x64, 0xf0]).unwrap();
         cpu.write_rn_w(0xf, 0xafaf).unwrap();
         cpu.write_rn_w(0, 0x3030).unwrap();
         let opcode = cpu.fetch();
@@ -269,7 +269,7 @@ mod tests {
         assert_eq!(cpu.read_rn_w(0xf).unwrap(), 0xafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..2].copy_from_slice(&[0x64, 0x0f]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x64, 0x0f]).unwrap();
         cpu.write_rn_w(0xf, 0).unwrap();
         cpu.write_rn_w(0, 0).unwrap();
         let opcode = cpu.fetch();
@@ -283,7 +283,7 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..6].copy_from_slice(&[0x7a, 0x40, 0x30, 0x30, 0x30, 0x30]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x7a, 0x40, 0x30, 0x30, 0x30, 0x30]).unwrap();
         cpu.write_rn_l(0, 0xafafafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -292,7 +292,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(0).unwrap(), 0xbfbfbfbf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..6].copy_from_slice(&[0x7a, 0x47, 0x30, 0x30, 0x30, 0x30]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x7a, 0x47, 0x30, 0x30, 0x30, 0x30]).unwrap();
         cpu.write_rn_l(0x7, 0xafafafaf).unwrap();
         let opcode = cpu.fetch();
         let state = cpu.exec(opcode).unwrap();
@@ -301,7 +301,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(0x7).unwrap(), 0xbfbfbfbf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..6].copy_from_slice(&[0x7a, 0x40, 0x00, 0x00, 0x00, 0x00]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x7a, 0x40, 0x00, 0x00, 0x00, 0x00]).unwrap();
         cpu.write_rn_l(0, 0).unwrap();
         let opcode = cpu.fetch();
         cpu.exec(opcode).unwrap();
@@ -314,7 +314,7 @@ mod tests {
         let mut cpu = Cpu::new();
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x01, 0xf0, 0x64, 0x07]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0xf0, 0x64, 0x07]).unwrap();
         cpu.write_rn_l(0, 0xafafafaf).unwrap();
         cpu.write_rn_l(7, 0x30303030).unwrap();
         let opcode = cpu.fetch();
@@ -327,7 +327,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(0).unwrap(), 0xafafafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x01, 0xf0, 0x64, 0x70]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0xf0, 0x64, 0x70]).unwrap();
         cpu.write_rn_l(7, 0xafafafaf).unwrap();
         cpu.write_rn_l(0, 0x30303030).unwrap();
         let opcode = cpu.fetch();
@@ -340,7 +340,7 @@ mod tests {
         assert_eq!(cpu.read_rn_l(7).unwrap(), 0xafafafaf);
 
         cpu.pc = MEMORY_START_ADDR;
-        cpu.bus.memory[0..4].copy_from_slice(&[0x01, 0xf0, 0x64, 0x70]);
+        cpu.bus.load(MEMORY_START_ADDR, &[0x01, 0xf0, 0x64, 0x70]).unwrap();
         cpu.write_rn_l(7, 0).unwrap();
         cpu.write_rn_l(0, 0).unwrap();
         let opcode = cpu.fetch();
@@ -0,0 +1,19 @@
+use crate::{
+    bus_controller::SYSCR_SSBY,
+    cpu::{Cpu, SleepMode, StateType},
+};
+use anyhow::Result;
+
+impl Cpu {
+    // The pc stays at the instruction until an interrupt, which returns to the next one.
+    // The cpu executes nothing while it sleeps, so the states of the instruction are counted once.
+    pub(in super::super) fn sleep(&mut self) -> Result<u8> {
+        self.pc -= 2;
+        self.sleep_mode = Some(if self.bus.syscr() & SYSCR_SSBY != 0 {
+            SleepMode::SoftwareStandby
+        } else {
+            SleepMode::Sleep
+        });
+        self.calc_state(StateType::I, 1)
+    }
+}
@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, CCR};
-use anyhow::{bail, Context as _, Result};
+use anyhow::{Context as _, Result};
 
 impl Cpu {
     pub fn sub_l_calc(&mut self, dest: u32, src: u32) -> u32 {
@@ -18,7 +18,7 @@ impl Cpu {
         match (opcode >> 8) as u8 {
             0x7a => return self.sub_l_imm(opcode),
             0x1a => return self.sub_l_rn(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StateType, CCR};
-use anyhow::{bail, Context as _, Result};
+use anyhow::{Context as _, Result};
 
 impl Cpu {
     pub fn sub_w_calc(&mut self, dest: u16, src: u16) -> u16 {
@@ -18,7 +18,7 @@ impl Cpu {
         match (opcode >> 8) as u8 {
             0x79 => return self.sub_w_imm(opcode),
             0x19 => return self.sub_w_rn(opcode),
-            _ => bail!("invalid opcode [{:>04x}]", opcode),
+            _ => unimpl!(opcode, self.pc),
         }
     }
 
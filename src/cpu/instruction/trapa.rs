@@ -1,71 +1,275 @@
-use crate::cpu::{Cpu, StateType, ADDRESS_MASK};
-use anyhow::{bail, Result};
+use crate::{
+    cpu::{Cpu, StateType, ADDRESS_MASK},
+    test_report::{Snapshot, TestResult},
+};
+use anyhow::{anyhow, bail, Result};
+
+// MES2 system call ids, the arguments are passed by the array at er1.
+// 103, 104 and 116 are the ids the newlib stubs of the example programs use (___read, ___write and ___exit),
+// and 113 is the one the emulator handled from the start. The other calls are left unsupported
+// until their ids are checked against the MES2 call table.
+const SYS_READ: u32 = 103;
+const SYS_WRITE: u32 = 104;
+const SYS_SET_HANDLER: u32 = 113;
+const SYS_EXIT: u32 = 116;
+
+// Test control calls of the emulator
+const TEST_PASS: u32 = 200;
+const TEST_FAIL: u32 = 201;
+const TEST_ASSERT_EQ: u32 = 202;
+const TEST_STATE_SUM: u32 = 203;
+const TEST_SNAPSHOT: u32 = 204;
+const TEST_TERMINATE: u32 = 205;
+
+const MAX_PATH_LEN: u32 = 256;
 
 impl Cpu {
     pub(in super::super) fn trapa(&mut self, opcode: u16) -> Result<u8> {
         let access_addr = self.read_rn_l(7)? & ADDRESS_MASK;
         let imm = Cpu::get_nibble_opcode(opcode, 3)?;
+        let vec_addr: u32 = (0x20 + 4 * imm).into();
 
         if imm == 0 {
             self.trapa_emulate_mes2()?;
         } else {
             self.write_dec_ern_l(7, ((self.ccr as u32) << 24) | self.pc)?;
-            let vec_addr: u32 = (0x20 + 4 * imm).into();
             let dest_addr = self.read_abs24_l(vec_addr)?;
             self.pc = dest_addr & ADDRESS_MASK;
-            self.write_ccr(crate::cpu::CCR::I, 1);
-            // TODO: set CCR::UI to 1 if it is used as interrupt mask bit.
+            self.mask_interrupts();
         }
 
-        // TODO: calc J
         Ok(self.calc_state(StateType::I, 2)?
+            + self.calc_state_with_addr(StateType::J, 2, vec_addr)?
             + self.calc_state_with_addr(StateType::K, 2, access_addr)?
             + self.calc_state(StateType::N, 4)?)
     }
 
     fn trapa_emulate_mes2(&mut self) -> Result<()> {
         let id = self.read_rn_l(0)?;
-        match id {
-            113 => {
-                // set_handler
-                let arg_addr = self.read_rn_l(1)?;
-                let arg0 = self.read_abs24_l(arg_addr)?; // vector num
-                let arg1 = self.read_abs24_l(arg_addr + 4)?; // callback address
-
-                if arg0 < 1 || arg0 >= 64 {
-                    return Ok(());
+        let arg_addr = self.read_rn_l(1)?;
+        let result = match id {
+            SYS_READ => {
+                let fd = self.read_mes2_arg(arg_addr, 0)?;
+                let buf = self.read_mes2_arg(arg_addr, 1)?;
+                let len = self.read_mes2_arg(arg_addr, 2)?;
+                match self.mes2.read(fd, len as usize) {
+                    Ok(Some(data)) => {
+                        for (i, value) in data.iter().enumerate() {
+                            self.write_abs24_b(buf + i as u32, *value)?;
+                        }
+                        Ok(data.len() as u32)
+                    }
+                    Ok(None) => {
+                        // Execute this trapa again when the input arrives
+                        self.pc -= 2;
+                        return Ok(());
+                    }
+                    Err(e) => Err(e),
                 }
-
-                let inst = arg1 + 0x5a000000;
-                self.write_abs24_l(arg0 * 4, inst)?;
-
-                let gotsave = 0xfffd10 + arg0 * 4; // segment + vector num * 4
-                self.write_abs24_l(gotsave, self.er[5])?;
-
-                log::trace!("[set_handler] vector: {}, addr: 0x{:x}", arg0, arg1);
             }
-            104 => {
-                // __write
-                let arg_addr = self.read_rn_l(1)?;
-                let arg0 = self.read_abs24_l(arg_addr)?;
-                let arg1 = self.read_abs24_l(arg_addr + 4)?;
-                let arg2 = self.read_abs24_l(arg_addr + 8)?;
+            SYS_WRITE => {
+                let fd = self.read_mes2_arg(arg_addr, 0)?;
+                let buf = self.read_mes2_arg(arg_addr, 1)?;
+                let len = self.read_mes2_arg(arg_addr, 2)?;
 
                 let mut chars = Vec::<u8>::new();
-                for i in 0..arg2 {
-                    let char_addr = arg1 + i;
+                for i in 0..len {
+                    let char_addr = buf + i;
                     let char = self.read_abs24_b(char_addr)?;
                     chars.push(char);
                 }
-                let print_string = String::from_utf8(chars)?;
+                match fd {
+                    // the console of MES2 is read and written by fd 0 too, printf writes to it
+                    0..=2 => {
+                        let print_string = String::from_utf8(chars)?;
 
-                // Print strings
-                print!("{}", print_string);
-                // println!("[program] [__write] [fd: {}] {}", arg0, print_string);
-                self.send_stdout_message(&print_string)?;
+                        // Print strings
+                        match &mut self.stdout {
+                            Some(stdout) => stdout.extend_from_slice(print_string.as_bytes()),
+                            None => print!("{}", print_string),
+                        }
+                        self.send_stdout_message(&print_string)?;
+                        Ok(len)
+                    }
+                    _ => Err(anyhow!("fd {} is not writable", fd)),
+                }
+            }
+            SYS_SET_HANDLER => {
+                let arg0 = self.read_mes2_arg(arg_addr, 0)?; // vector num
+                let arg1 = self.read_mes2_arg(arg_addr, 1)?; // callback address
+
+                if !(1..64).contains(&arg0) {
+                    Err(anyhow!("invalid vector {}", arg0))
+                } else {
+                    let inst = arg1 + 0x5a000000;
+                    self.write_abs24_l(arg0 * 4, inst)?;
+
+                    let gotsave = 0xfffd10 + arg0 * 4; // segment + vector num * 4
+                    self.write_abs24_l(gotsave, self.er[5])?;
+
+                    log::trace!("[set_handler] vector: {}, addr: 0x{:x}", arg0, arg1);
+                    Ok(0)
+                }
+            }
+            SYS_EXIT => {
+                let code = self.read_mes2_arg(arg_addr, 0)?;
+                self.exit_code = Some(code);
+                Ok(0)
+            }
+            TEST_PASS | TEST_FAIL => {
+                let message_addr = self.read_mes2_arg(arg_addr, 0)?;
+                let message = self.read_mes2_string(message_addr)?;
+                self.report_test(id == TEST_PASS, message, None);
+                Ok(0)
+            }
+            TEST_ASSERT_EQ => {
+                let expected = self.read_mes2_arg(arg_addr, 0)?;
+                let actual = self.read_mes2_arg(arg_addr, 1)?;
+                let message_addr = self.read_mes2_arg(arg_addr, 2)?;
+                let message = self.read_mes2_string(message_addr)?;
+                self.report_test(expected == actual, message, Some((expected, actual)));
+                Ok((expected == actual) as u32)
+            }
+            TEST_STATE_SUM => {
+                // The whole count is written to the pointer, er0 gets the lower 32 bits
+                let ptr = self.read_mes2_arg(arg_addr, 0)?;
+                if ptr != 0 {
+                    self.write_abs24_l(ptr, (self.state_sum as u64 >> 32) as u32)?;
+                    self.write_abs24_l(ptr + 4, self.state_sum as u32)?;
+                }
+                Ok(self.state_sum as u32)
+            }
+            TEST_SNAPSHOT => {
+                let name_addr = self.read_mes2_arg(arg_addr, 0)?;
+                let name = self.read_mes2_string(name_addr)?;
+                log::info!("[snapshot] {} {}", name, self.format_er());
+                self.test_report.snapshots.push(Snapshot {
+                    name,
+                    pc: self.pc - 2,
+                    ccr: self.ccr,
+                    er: self.er,
+                    state_sum: self.state_sum,
+                });
+                Ok(0)
+            }
+            TEST_TERMINATE => {
+                let code = self.read_mes2_arg(arg_addr, 0)?;
+                self.test_report.terminated = Some(code);
+                self.exit_code = Some(code);
+                Ok(0)
             }
             _ => bail!("unsupported mes2 command id:{}", id),
+        };
+
+        // -1 on failure
+        let value = result.unwrap_or_else(|e| {
+            log::debug!("mes2 command id:{} failed: {:#}", id, e);
+            u32::MAX
+        });
+        self.write_rn_l(0, value)
+    }
+
+    fn report_test(&mut self, passed: bool, message: String, values: Option<(u32, u32)>) {
+        let pc = self.pc - 2;
+        if !passed {
+            let location = self.symbols.format_location(pc).unwrap_or_else(|| self.symbols.format_addr(pc));
+            match values {
+                Some((expected, actual)) => {
+                    log::error!(
+                        "[test] failed: {} (expected: {}, actual: {}) at {}",
+                        message,
+                        expected,
+                        actual,
+                        location
+                    )
+                }
+                None => log::error!("[test] failed: {} at {}", message, location),
+            }
+        } else {
+            log::debug!("[test] passed: {}", message);
+        }
+        self.test_report.results.push(TestResult {
+            passed,
+            message,
+            pc,
+            values,
+        });
+    }
+
+    fn read_mes2_arg(&mut self, arg_addr: u32, index: u32) -> Result<u32> {
+        self.read_abs24_l(arg_addr + 4 * index)
+    }
+
+    fn read_mes2_string(&mut self, addr: u32) -> Result<String> {
+        let mut chars = Vec::new();
+        for i in 0..MAX_PATH_LEN {
+            match self.read_abs24_b(addr + i)? {
+                0 => return Ok(String::from_utf8_lossy(&chars).into_owned()),
+                char => chars.push(char),
+            }
         }
-        Ok(())
+        bail!("string at [0x{:x}] is too long", addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SYS_WRITE, TEST_ASSERT_EQ, TEST_PASS, TEST_SNAPSHOT, TEST_STATE_SUM, TEST_TERMINATE};
+    use crate::{cpu::Cpu, memory::MEMORY_START_ADDR};
+
+    const ARGS: u32 = MEMORY_START_ADDR;
+    const DATA: u32 = MEMORY_START_ADDR + 0x100;
+
+    fn call(cpu: &mut Cpu, id: u32, args: &[u32]) -> u32 {
+        for (i, arg) in args.iter().enumerate() {
+            cpu.write_abs24_l(ARGS + 4 * i as u32, *arg).unwrap();
+        }
+        cpu.er[0] = id;
+        cpu.er[1] = ARGS;
+        cpu.trapa_emulate_mes2().unwrap();
+        cpu.er[0]
+    }
+
+    #[test]
+    fn test_mes2_console() {
+        let mut cpu = Cpu::new();
+        cpu.stdout = Some(Vec::new());
+        for (i, c) in b"hi".iter().enumerate() {
+            cpu.write_abs24_b(DATA + i as u32, *c).unwrap();
+        }
+        assert_eq!(call(&mut cpu, SYS_WRITE, &[0, DATA, 2]), 2);
+        assert_eq!(cpu.stdout.as_deref(), Some(&b"hi"[..]));
+        // no file is opened by the supported calls
+        assert_eq!(call(&mut cpu, SYS_WRITE, &[3, DATA, 2]), u32::MAX);
+
+        // the ids not checked against the MES2 call table are not supported
+        for id in [101, 102, 105, 106, 107, 108, 109, 110, 111, 112, 114] {
+            cpu.er[0] = id;
+            assert!(cpu.trapa_emulate_mes2().is_err());
+        }
+    }
+
+    #[test]
+    fn test_test_control() {
+        let mut cpu = Cpu::new();
+        for (i, c) in b"ok\0".iter().enumerate() {
+            cpu.write_abs24_b(DATA + i as u32, *c).unwrap();
+        }
+        cpu.pc = 0x1002;
+        call(&mut cpu, TEST_PASS, &[DATA]);
+        assert_eq!(call(&mut cpu, TEST_ASSERT_EQ, &[1, 1, DATA]), 1);
+        assert_eq!(call(&mut cpu, TEST_ASSERT_EQ, &[1, 2, DATA]), 0);
+        assert_eq!(cpu.test_report.failed(), 1);
+        assert_eq!(cpu.test_report.results[2].values, Some((1, 2)));
+        assert_eq!(cpu.test_report.results[2].pc, 0x1000);
+
+        cpu.state_sum = 0x1_0000_0002;
+        assert_eq!(call(&mut cpu, TEST_STATE_SUM, &[DATA + 0x10]), 2);
+        assert_eq!(cpu.read_abs24_l(DATA + 0x10).unwrap(), 1);
+        call(&mut cpu, TEST_SNAPSHOT, &[DATA]);
+        assert_eq!(cpu.test_report.snapshots[0].name, "ok");
+
+        call(&mut cpu, TEST_TERMINATE, &[5]);
+        assert_eq!(cpu.exit_code, Some(5));
     }
 }
@@ -1,39 +1,362 @@
 use anyhow::Result;
-use std::collections::VecDeque;
 
-use super::{Cpu, ADDRESS_MASK};
+use super::{Cpu, SleepMode, StateType, ADDRESS_MASK, CCR};
+use crate::{
+    bus::Bus,
+    bus_controller::{SYSCR_NMIEG, SYSCR_UE},
+    registers::{IPRA, IPRB},
+};
+
+pub const NMI_VECTOR: u8 = 7;
 
 #[derive(Clone)]
 pub(crate) struct InterruptController {
-    interrupt_requests: VecDeque<u8>,
+    // bit n is set while the vector n is requested, until it is accepted or the source clears its flag
+    interrupt_requests: u64,
+    // level of the NMI pin, pulled up
+    nmi_level: bool,
 }
 
 impl InterruptController {
     pub fn new() -> Self {
         InterruptController {
-            interrupt_requests: VecDeque::new(),
+            interrupt_requests: 0,
+            nmi_level: true,
         }
     }
 
+    /// Requests the vector, which stays requested however many times the source requests it before it is accepted.
     pub fn request_interrupt(&mut self, num: u8) {
-        self.interrupt_requests.push_back(num);
+        self.interrupt_requests |= 1 << num;
+    }
+
+    /// Withdraws the request of the vector, when the source clears its flag before the request is accepted.
+    pub fn clear_interrupt(&mut self, num: u8) {
+        self.interrupt_requests &= !(1 << num);
+    }
+
+    pub fn has_requests(&self) -> bool {
+        self.interrupt_requests != 0
+    }
+
+    /// Changes the level of the NMI pin, which requests NMI at the edge selected by NMIEG of SYSCR.
+    pub fn set_nmi_pin(&mut self, level: bool, syscr: u8) {
+        let rising_edge = syscr & SYSCR_NMIEG != 0;
+        if level != self.nmi_level && level == rising_edge {
+            self.request_interrupt(NMI_VECTOR);
+        }
+        self.nmi_level = level;
+    }
+}
+
+// Bit of IPRA or IPRB which gives the source of the vector priority 1, None for NMI and the sources without it
+fn priority_bit(vector: u8) -> Option<(u32, u8)> {
+    let (register, bit) = match vector {
+        12 => (IPRA, 7),
+        13 => (IPRA, 6),
+        14 | 15 => (IPRA, 5),
+        16 | 17 => (IPRA, 4),
+        // the watchdog timer and the refresh controller
+        20 | 21 => (IPRA, 3),
+        // 16-bit timer channel 0-2
+        24..=27 => (IPRA, 2),
+        28..=31 => (IPRA, 1),
+        32..=35 => (IPRA, 0),
+        // 8-bit timer channel 0/1 and 2/3, and the DMA controller
+        36..=39 => (IPRB, 7),
+        40..=43 => (IPRB, 6),
+        44..=47 => (IPRB, 5),
+        // SCI channel 0 and 1
+        52..=55 => (IPRB, 3),
+        56..=59 => (IPRB, 2),
+        _ => return None,
+    };
+    Some((register, bit))
+}
+
+fn priority(bus: &Bus, vector: u8) -> u8 {
+    match priority_bit(vector) {
+        Some((register, bit)) => (bus.peek(register).unwrap_or(0) >> bit) & 1,
+        None => 0,
     }
 }
 
 impl Cpu {
-    pub(super) fn try_interrupt(&mut self) -> Result<()> {
-        if let Some(vector) = self.interrupt_controller.interrupt_requests.pop_front() {
-            self.interrupt(vector)?;
+    /// Changes the level of the NMI pin, e.g. by a switch on the board.
+    pub fn set_nmi_pin(&mut self, level: bool) {
+        let syscr = self.bus.syscr();
+        self.interrupt_controller.set_nmi_pin(level, syscr);
+    }
+
+    // Whether the interrupt is accepted by the masks of the ccr.
+    // UI is a mask of the priority 0 interrupts when UE of SYSCR is cleared, and NMI is not masked.
+    fn is_accepted(&self, vector: u8) -> bool {
+        if vector == NMI_VECTOR {
+            return true;
+        }
+        // software standby is left only by NMI
+        if self.sleep_mode == Some(SleepMode::SoftwareStandby) {
+            return false;
+        }
+        let (i, ui) = ((self.ccr >> CCR::I as u8) & 1 == 1, (self.ccr >> CCR::UI as u8) & 1 == 1);
+        if self.bus.syscr() & SYSCR_UE != 0 {
+            !i
+        } else {
+            !i || (!ui && priority(&self.bus, vector) == 1)
+        }
+    }
+
+    // Masks the interrupts at the exception handling, UI too when it is a mask
+    pub(super) fn mask_interrupts(&mut self) {
+        self.write_ccr(CCR::I, 1);
+        if self.bus.syscr() & SYSCR_UE == 0 {
+            self.write_ccr(CCR::UI, 1);
+        }
+    }
+
+    // Returns the states of the exception handling, 0 when no interrupt is accepted.
+    // NMI comes first, then the priority 1 interrupts, then the lower vectors.
+    pub(super) fn try_interrupt(&mut self) -> Result<u8> {
+        let requests = self.interrupt_controller.interrupt_requests;
+        if requests == 0 {
+            return Ok(0);
+        }
+        let accepted = (0..64)
+            .filter(|vector| requests & (1 << vector) != 0 && self.is_accepted(*vector))
+            .min_by_key(|vector| (*vector != NMI_VECTOR, 1 - priority(&self.bus, *vector), *vector));
+        match accepted {
+            Some(vector) => {
+                self.interrupt_controller.clear_interrupt(vector);
+                self.interrupt(vector)
+            }
+            None => Ok(0),
         }
-        Ok(())
     }
 
-    pub(super) fn interrupt(&mut self, vector: u8) -> Result<()> {
+    // Saves the pc and the ccr, and jumps to the vector in the states of trapa
+    pub(super) fn interrupt(&mut self, vector: u8) -> Result<u8> {
+        // the sleep instruction is left
+        if self.sleep_mode.take().is_some() {
+            self.pc += 2;
+        }
+        let stack_addr = (self.read_rn_l(7)? - 4) & ADDRESS_MASK;
         self.write_dec_ern_l(7, ((self.ccr as u32) << 24) | self.pc)?;
         let vec_addr: u32 = (4 * vector).into();
         let dest_addr = self.read_abs24_l(vec_addr)?;
         self.pc = dest_addr & ADDRESS_MASK;
-        self.write_ccr(crate::cpu::CCR::I, 1);
-        Ok(())
+        self.mask_interrupts();
+        self.trace_interrupt(vector)?;
+        let state = self.calc_state_with_addr(StateType::I, 2, self.pc)?
+            + self.calc_state_with_addr(StateType::J, 2, vec_addr)?
+            + self.calc_state_with_addr(StateType::K, 2, stack_addr)?
+            + self.calc_state(StateType::N, 4)?;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.on_interrupt(state, self.pc, self.er[7]);
+        }
+        if let Some(stack_usage) = &mut self.stack_usage {
+            stack_usage.on_interrupt(self.pc, self.er[7]);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NMI_VECTOR;
+    use crate::{
+        cpu::{Cpu, SleepMode},
+        memory::MEMORY_START_ADDR,
+        profiler::Profiler,
+        registers::{IPRB, SYSCR},
+    };
+
+    fn new_cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        for vector in [NMI_VECTOR, 12, 36, 39] {
+            cpu.bus
+                .load(vector as u32 * 4, &(MEMORY_START_ADDR + vector as u32 * 2).to_be_bytes())
+                .unwrap();
+        }
+        cpu.er[7] = MEMORY_START_ADDR + 0x100;
+        cpu
+    }
+
+    #[test]
+    fn test_interrupt_states() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.try_interrupt().unwrap(), 0);
+
+        // vector 36 to the on-chip memory, the stack in it too
+        cpu.bus.load(36 * 4, &MEMORY_START_ADDR.to_be_bytes()).unwrap();
+        cpu.er[7] = MEMORY_START_ADDR + 0x100;
+        cpu.interrupt_controller.request_interrupt(36);
+        assert_eq!(cpu.try_interrupt().unwrap(), 2 * (2 + 2 + 2) + 4);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR);
+        assert_eq!(cpu.er[7], MEMORY_START_ADDR + 0xfc);
+    }
+
+    #[test]
+    fn test_interrupt_mask() {
+        // I masks every interrupt while UE of SYSCR is set
+        let mut cpu = new_cpu();
+        cpu.ccr = 0x80;
+        cpu.interrupt_controller.request_interrupt(12);
+        cpu.interrupt_controller.request_interrupt(36);
+        assert_eq!(cpu.try_interrupt().unwrap(), 0);
+        cpu.ccr = 0;
+        assert_ne!(cpu.try_interrupt().unwrap(), 0);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + 12 * 2);
+        assert_eq!(cpu.ccr, 0x80);
+
+        // UI masks the priority 0 interrupts while UE is cleared, and both are set at the exception handling
+        let mut cpu = new_cpu();
+        cpu.bus.write(SYSCR, 0x01).unwrap();
+        cpu.bus.write(IPRB, 0x80).unwrap();
+        cpu.ccr = 0x80;
+        cpu.interrupt_controller.request_interrupt(12);
+        cpu.interrupt_controller.request_interrupt(36);
+        assert_ne!(cpu.try_interrupt().unwrap(), 0);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + 36 * 2);
+        assert_eq!(cpu.ccr, 0xc0);
+        assert_eq!(cpu.try_interrupt().unwrap(), 0);
+        assert!(cpu.interrupt_controller.has_requests());
+    }
+
+    #[test]
+    fn test_nmi() {
+        let mut cpu = new_cpu();
+        cpu.ccr = 0x80;
+        // the falling edge by default
+        cpu.set_nmi_pin(true);
+        assert!(!cpu.interrupt_controller.has_requests());
+        cpu.set_nmi_pin(false);
+        assert_ne!(cpu.try_interrupt().unwrap(), 0);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + NMI_VECTOR as u32 * 2);
+
+        // the rising edge by NMIEG
+        cpu.bus.write(SYSCR, 0x0d).unwrap();
+        cpu.set_nmi_pin(true);
+        assert!(cpu.interrupt_controller.has_requests());
+        cpu.set_nmi_pin(false);
+        cpu.try_interrupt().unwrap();
+        assert!(!cpu.interrupt_controller.has_requests());
+    }
+
+    #[test]
+    fn test_masked_matches() {
+        // compare match A of the 8-bit timer 0 every 80 states, at φ/8 and cleared by the match
+        let mut cpu = new_cpu();
+        cpu.ccr = 0x80;
+        cpu.bus.write(0xffff84, 9).unwrap();
+        cpu.bus.write(0xffff80, 0b0100_1001).unwrap();
+        for _ in 0..5 {
+            cpu.state_sum += 80;
+            cpu.update_modules();
+        }
+
+        // the matches while masked are taken as one request
+        cpu.ccr = 0;
+        assert_ne!(cpu.try_interrupt().unwrap(), 0);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + 36 * 2);
+        cpu.ccr = 0;
+        assert_eq!(cpu.try_interrupt().unwrap(), 0);
+
+        // the request is withdrawn when CMFA is cleared before it is accepted
+        cpu.ccr = 0x80;
+        cpu.state_sum += 80;
+        cpu.update_modules();
+        assert!(cpu.interrupt_controller.has_requests());
+        cpu.bus.cpu_state_sum = cpu.state_sum;
+        cpu.bus.read(0xffff82).unwrap();
+        cpu.bus.write(0xffff82, 0).unwrap();
+        cpu.update_modules();
+        assert!(!cpu.interrupt_controller.has_requests());
+    }
+
+    #[test]
+    fn test_sleep_counted_once() {
+        // sleep, and exits after the overflow of the 8-bit timer 0 at φ/64 wakes it up
+        let mut cpu = new_cpu();
+        cpu.ccr = 0;
+        cpu.set_option("deterministic", "on").unwrap();
+        cpu.set_option("clock", "1").unwrap();
+        cpu.bus.load(MEMORY_START_ADDR + 0x200, &[0x01, 0x80]).unwrap();
+        // rte
+        cpu.bus.load(MEMORY_START_ADDR + 39 * 2, &[0x56, 0x70]).unwrap();
+        cpu.bus.load(39 * 4, &(MEMORY_START_ADDR + 39 * 2).to_be_bytes()).unwrap();
+        cpu.bus.write(0xffff80, 0b0010_0010).unwrap();
+        cpu.er[2] = MEMORY_START_ADDR + 0x200;
+        cpu.exit_addr = MEMORY_START_ADDR + 0x202;
+        cpu.run().unwrap();
+
+        // the cpu waited more than 1msec of the guest in sleep, and only sleep and rte are counted
+        assert!(cpu.idle_states > cpu.states_per_msec());
+        assert_eq!(cpu.instruction_count, 2);
+    }
+
+    #[test]
+    fn test_profile_interrupt() {
+        // a countdown loop interrupted by the overflows of the 8-bit timer 0 at φ/8
+        let path = std::env::temp_dir().join(format!("profile_interrupt_{}.txt", std::process::id()));
+        let mut cpu = new_cpu();
+        cpu.ccr = 0;
+        cpu.set_option("deterministic", "on").unwrap();
+        cpu.profiler = Some(Profiler::new(&path.to_string_lossy(), MEMORY_START_ADDR + 0x200));
+        // dec.w #1, r3 / bne -4
+        cpu.bus.load(MEMORY_START_ADDR + 0x200, &[0x1b, 0x53, 0x46, 0xfc]).unwrap();
+        // rte
+        cpu.bus.load(MEMORY_START_ADDR + 39 * 2, &[0x56, 0x70]).unwrap();
+        cpu.bus.write(0xffff80, 0b0010_0001).unwrap();
+        cpu.er[2] = MEMORY_START_ADDR + 0x200;
+        cpu.er[3] = 0x1000;
+        cpu.exit_addr = MEMORY_START_ADDR + 0x204;
+        cpu.run().unwrap();
+
+        // the states of the exception handling are included in the profile
+        let report = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("{}.folded", path.to_string_lossy())).unwrap();
+        assert!(report.starts_with(&format!("Total states: {}\n", cpu.state_sum)));
+        assert!(report.contains(&format!("0x{:x}\n", MEMORY_START_ADDR + 39 * 2)));
+    }
+
+    #[test]
+    fn test_sleep() {
+        // sleep / nop
+        let mut cpu = new_cpu();
+        cpu.bus.load(MEMORY_START_ADDR + 0x200, &[0x01, 0x80]).unwrap();
+        cpu.pc = MEMORY_START_ADDR + 0x200;
+        let opcode = cpu.fetch();
+        cpu.exec(opcode).unwrap();
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + 0x200);
+        assert_eq!(cpu.sleep_mode, Some(SleepMode::Sleep));
+        assert!(cpu.wait_in_sleep(SleepMode::Sleep).is_err());
+
+        // the overflow of the 8-bit timer 0 at φ/8 wakes it up, and the handler returns to the next instruction
+        cpu.bus.write(0xffff80, 0b0010_0001).unwrap();
+        while !cpu.interrupt_controller.has_requests() {
+            let next_event = cpu.bus.module_manager.get_mut().next_event();
+            cpu.wait_in_sleep(SleepMode::Sleep).unwrap();
+            assert_eq!(cpu.state_sum, next_event);
+            cpu.update_modules();
+        }
+        assert!(cpu.state_sum <= 8 * 256);
+        assert_ne!(cpu.try_interrupt().unwrap(), 0);
+        assert_eq!(cpu.sleep_mode, None);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + 39 * 2);
+        assert_eq!(cpu.bus.read_l(cpu.er[7]).unwrap() & 0xffffff, MEMORY_START_ADDR + 0x202);
+
+        // only NMI leaves software standby
+        cpu.bus.write(SYSCR, 0x89).unwrap();
+        cpu.pc = MEMORY_START_ADDR + 0x200;
+        cpu.ccr = 0;
+        let opcode = cpu.fetch();
+        cpu.exec(opcode).unwrap();
+        assert_eq!(cpu.sleep_mode, Some(SleepMode::SoftwareStandby));
+        cpu.interrupt_controller.request_interrupt(36);
+        assert_eq!(cpu.try_interrupt().unwrap(), 0);
+        cpu.set_nmi_pin(false);
+        assert_ne!(cpu.try_interrupt().unwrap(), 0);
+        assert_eq!(cpu.pc, MEMORY_START_ADDR + NMI_VECTOR as u32 * 2);
     }
 }
@@ -0,0 +1,35 @@
+use super::Cpu;
+use crate::memcheck::{format_violation, MemCheck};
+use std::cell::RefCell;
+
+impl Cpu {
+    /// Enables the memory checker. Must be called before the program is loaded.
+    pub fn enable_memcheck(&mut self) {
+        self.bus.memcheck = Some(RefCell::new(MemCheck::new()));
+    }
+
+    pub(super) fn check_memory(&mut self, pc: u32) {
+        let Some(memcheck) = &self.bus.memcheck else {
+            return;
+        };
+        let violations = {
+            let mut memcheck = memcheck.borrow_mut();
+            memcheck.check_sp(self.er[7]);
+            memcheck.take_violations(pc)
+        };
+        for violation in violations {
+            log::warn!(
+                "[memcheck] {} at pc 0x{:x} ({})",
+                format_violation(&violation),
+                pc,
+                self.symbols.format_location(pc).unwrap_or_else(|| self.symbols.format_addr(pc))
+            );
+        }
+    }
+
+    pub(super) fn print_memcheck_summary(&self) {
+        if let Some(memcheck) = &self.bus.memcheck {
+            log::info!("[memcheck] {}", memcheck.borrow().summary());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{bus::Bus, setting};
+use crate::bus::Bus;
 
 use super::Cpu;
 use anyhow::Result;
@@ -35,15 +35,40 @@ impl Cpu {
         }
     }
 
-    pub fn send_message(&mut self, message: &String) -> Result<()> {
-        #[cfg(not(test))]
-        if let Some(socket) = &self.socket {
-            socket.send_message(message)?;
+    // nmi:<level>, the level of the NMI pin, 0 or 1
+    #[cfg(not(test))]
+    pub fn parse_nmi(&mut self, list: Vec<&str>) {
+        if list.len() != 2 {
+            return;
         }
-        if *setting::ENABLE_PRINT_MESSAGES.read().unwrap() {
-            println!("msg: {}", message);
+        match list[1] {
+            "0" => self.set_nmi_pin(false),
+            "1" => self.set_nmi_pin(true),
+            _ => log::warn!("Received invalid nmi level: {}", list[1]),
+        }
+    }
+
+    // config:<name>:<value>
+    #[cfg(not(test))]
+    pub fn parse_config(&mut self, list: Vec<&str>) {
+        if list.len() != 3 {
+            return;
+        }
+        if let Err(e) = self.set_option(list[1], list[2]) {
+            log::warn!("Received invalid config: {}", e);
         }
-        Ok(())
+    }
+
+    // stdin:<data>, escaped the same way as the sent messages
+    #[cfg(not(test))]
+    pub fn parse_stdin(&mut self, message: &str) {
+        let data = message.strip_prefix("stdin:").unwrap_or("");
+        self.mes2.stdin.push(unescape(data).as_bytes());
+    }
+
+    // The socket and the test runner receive messages by the sender of the bus
+    pub fn send_message(&mut self, message: &String) -> Result<()> {
+        self.bus.send_message(message)
     }
 
     pub fn send_ready_message(&mut self) -> Result<()> {
@@ -67,7 +92,7 @@ impl Bus {
         if let Some(tx) = &self.message_tx {
             tx.send(message.clone())?;
         }
-        if *setting::ENABLE_PRINT_MESSAGES.read().unwrap() {
+        if self.print_messages {
             println!("msg: {}", message);
         }
         Ok(())
@@ -85,3 +110,30 @@ impl Bus {
         Ok(())
     }
 }
+
+fn unescape(message: &str) -> String {
+    let mut result = String::new();
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unescape;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("12\\n34\\\\\\n"), "12\n34\\\n");
+    }
+}
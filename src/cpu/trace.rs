@@ -0,0 +1,58 @@
+use super::Cpu;
+use crate::trace::{group_accesses, TraceRecord, Tracer};
+use anyhow::Result;
+use std::cell::RefCell;
+
+impl Cpu {
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+        self.bus.access_log = Some(RefCell::new(Vec::new()));
+    }
+
+    pub(super) fn trace_begin(&mut self) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.begin(&self.er);
+            if let Some(log) = &self.bus.access_log {
+                log.borrow_mut().clear();
+            }
+        }
+    }
+
+    pub(super) fn trace_instruction(&mut self, pc: u32, state: u8) -> Result<()> {
+        let Some(tracer) = &mut self.tracer else {
+            return Ok(());
+        };
+
+        let registers = (0..8u8)
+            .filter(|i| tracer.registers[*i as usize] != self.er[*i as usize])
+            .map(|i| (i, self.er[i as usize]))
+            .collect();
+
+        // Exclude instruction fetches from the memory accesses
+        let fetch_range = pc..pc + 2 * tracer.words.len() as u32;
+        let log = match &self.bus.access_log {
+            Some(log) => std::mem::take(&mut *log.borrow_mut()),
+            None => Vec::new(),
+        };
+        let log: Vec<(u32, u8, bool)> = log
+            .into_iter()
+            .filter(|(addr, _, is_write)| *is_write || !fetch_range.contains(addr))
+            .collect();
+
+        tracer.record(TraceRecord::Instruction {
+            pc,
+            state,
+            ccr: self.ccr,
+            words: tracer.words.clone(),
+            registers,
+            accesses: group_accesses(&log),
+        })
+    }
+
+    pub(super) fn trace_interrupt(&mut self, vector: u8) -> Result<()> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(TraceRecord::Interrupt { vector, pc: self.pc })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{
+    bus::{Bus, IO_PORT_SIZE},
+    cpu::Cpu,
+    elf::Symbols,
+    ioport::{IO_PORT_1_DDR_ADDR, IO_PORT_1_DR_ADDR},
+    profiler::is_call,
+};
+use anyhow::Result;
+use command::Command;
+use std::io::{BufRead, Write};
+
+mod command;
+
+const OPCODE_RTS: u16 = 0x5470;
+const OPCODE_RTE: u16 = 0x5670;
+const MAX_BACKTRACE_DEPTH: usize = 64;
+
+#[derive(Clone)]
+struct Breakpoint {
+    id: usize,
+    addr: u32,
+}
+
+#[derive(Clone)]
+struct Watchpoint {
+    id: usize,
+    addr: u32,
+    len: u32,
+}
+
+#[derive(Clone)]
+enum RunMode {
+    Stop,
+    Continue,
+    Step(usize),
+    Next { return_addr: u32, sp: u32 },
+    Finish { sp: u32 },
+}
+
+pub enum DebugAction {
+    Run,
+    Quit,
+}
+
+#[derive(Clone)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+    mode: RunMode,
+    // pc of the breakpoint execution resumed from
+    resume_pc: Option<u32>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 1,
+            mode: RunMode::Stop,
+            resume_pc: None,
+        }
+    }
+
+    /// Called before the instruction at pc is executed. Reads commands from stdin while stopped.
+    pub fn before_exec(&mut self, cpu: &mut Cpu) -> Result<DebugAction> {
+        let pc = cpu.read_pc();
+        let resume_pc = self.resume_pc.take();
+
+        let should_stop = match self.mode {
+            RunMode::Stop | RunMode::Step(0) => true,
+            RunMode::Next { return_addr, sp } => pc == return_addr && cpu.er[7] >= sp,
+            _ => false,
+        };
+        if should_stop {
+            return self.prompt(cpu);
+        }
+
+        if resume_pc != Some(pc) {
+            if let Some(bp) = self.breakpoints.iter().find(|bp| bp.addr == pc) {
+                println!("Breakpoint {} at {}", bp.id, cpu.symbols.format_addr(pc));
+                return self.prompt(cpu);
+            }
+        }
+        Ok(DebugAction::Run)
+    }
+
+    /// Called after an instruction is executed.
+    pub fn after_exec(&mut self, cpu: &mut Cpu, opcode: u16) {
+        match self.mode {
+            RunMode::Step(ref mut count) => *count = count.saturating_sub(1),
+            RunMode::Finish { sp } if (opcode == OPCODE_RTS || opcode == OPCODE_RTE) && cpu.er[7] > sp => {
+                self.mode = RunMode::Stop;
+            }
+            _ => (),
+        }
+
+        if !cpu.bus.watch_hits.is_empty() {
+            for hit in cpu.bus.watch_hits.drain(..) {
+                let id = self
+                    .watchpoints
+                    .iter()
+                    .find(|w| hit.addr.saturating_add(hit.size as u32) > w.addr && hit.addr < w.addr.saturating_add(w.len))
+                    .map_or(0, |w| w.id);
+                println!(
+                    "Watchpoint {}: [{}] 0x{:0width$x} -> 0x{:0width$x}",
+                    id,
+                    cpu.symbols.format_addr(hit.addr),
+                    hit.old,
+                    hit.new,
+                    width = hit.size * 2
+                );
+            }
+            self.mode = RunMode::Stop;
+        }
+    }
+
+    fn prompt(&mut self, cpu: &mut Cpu) -> Result<DebugAction> {
+        self.mode = RunMode::Stop;
+        self.print_location(cpu);
+
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            print!("(h8db) ");
+            std::io::stdout().flush()?;
+
+            line.clear();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(DebugAction::Quit);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let command = match Command::parse(&line, &cpu.symbols) {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+
+            match command {
+                Command::Help => println!("{}", command::HELP),
+                Command::Break(addr) => {
+                    println!("Breakpoint {} at {}", self.next_id, cpu.symbols.format_addr(addr));
+                    self.breakpoints.push(Breakpoint { id: self.next_id, addr });
+                    self.next_id += 1;
+                }
+                Command::Delete(id) => {
+                    let len = self.breakpoints.len();
+                    self.breakpoints.retain(|bp| bp.id != id);
+                    if len == self.breakpoints.len() {
+                        println!("No breakpoint {}", id);
+                    }
+                }
+                Command::Watch(addr, len) => {
+                    println!("Watchpoint {} at {} ({} bytes)", self.next_id, cpu.symbols.format_addr(addr), len);
+                    self.watchpoints.push(Watchpoint {
+                        id: self.next_id,
+                        addr,
+                        len,
+                    });
+                    self.next_id += 1;
+                    self.update_bus_watchpoints(&mut cpu.bus);
+                }
+                Command::Unwatch(id) => {
+                    let len = self.watchpoints.len();
+                    self.watchpoints.retain(|w| w.id != id);
+                    if len == self.watchpoints.len() {
+                        println!("No watchpoint {}", id);
+                    }
+                    self.update_bus_watchpoints(&mut cpu.bus);
+                }
+                Command::Info => self.print_info(cpu),
+                Command::Continue => {
+                    self.mode = RunMode::Continue;
+                    break;
+                }
+                Command::Step(count) => {
+                    self.mode = RunMode::Step(count);
+                    break;
+                }
+                Command::Next => {
+                    self.mode = match Debugger::call_length(cpu) {
+                        Some(len) => RunMode::Next {
+                            return_addr: cpu.read_pc() + len,
+                            sp: cpu.er[7],
+                        },
+                        None => RunMode::Step(1),
+                    };
+                    break;
+                }
+                Command::Finish => {
+                    self.mode = RunMode::Finish { sp: cpu.er[7] };
+                    break;
+                }
+                Command::Registers => {
+                    println!("{}", cpu.format_er());
+                    println!("PC:[0x{:x}] {}", cpu.read_pc(), cpu.format_ccr());
+                }
+                Command::Examine(addr, len) => Debugger::print_memory(cpu, addr, len),
+                Command::Patch(addr, bytes) => {
+                    for (i, byte) in bytes.iter().enumerate() {
+                        if let Err(e) = cpu.bus.write(addr.wrapping_add(i as u32), *byte) {
+                            println!("{}", e);
+                            break;
+                        }
+                    }
+                }
+                Command::Backtrace => Debugger::print_backtrace(cpu),
+                Command::IoPort => Debugger::print_io_ports(&cpu.bus),
+                Command::Set(name, value) => {
+                    if let Err(e) = cpu.set_option(&name, &value) {
+                        println!("{}", e);
+                    }
+                }
+                Command::Config => println!("{}", cpu.config()),
+                Command::Quit => return Ok(DebugAction::Quit),
+            }
+        }
+
+        self.resume_pc = Some(cpu.read_pc());
+        Ok(DebugAction::Run)
+    }
+
+    fn update_bus_watchpoints(&self, bus: &mut Bus) {
+        bus.watch_ranges = self.watchpoints.iter().map(|w| w.addr..w.addr.saturating_add(w.len)).collect();
+    }
+
+    fn print_location(&self, cpu: &Cpu) {
+        let pc = cpu.read_pc();
+        let mut bytes = String::new();
+        for i in 0..2 {
+            if let Ok(byte) = cpu.bus.peek(pc.wrapping_add(i)) {
+                bytes += &format!("{:02x} ", byte);
+            }
+        }
+        println!("0x{:x} <{}>: {}", pc, cpu.symbols.format_addr(pc), bytes.trim_end());
+        if let Some(location) = cpu.symbols.format_location(pc) {
+            println!("    {}", location);
+        }
+    }
+
+    fn print_info(&self, cpu: &Cpu) {
+        if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+        }
+        for bp in &self.breakpoints {
+            println!("{:<4} breakpoint  0x{:x} <{}>", bp.id, bp.addr, cpu.symbols.format_addr(bp.addr));
+        }
+        for w in &self.watchpoints {
+            println!(
+                "{:<4} watchpoint  0x{:x} <{}> {} bytes",
+                w.id,
+                w.addr,
+                cpu.symbols.format_addr(w.addr),
+                w.len
+            );
+        }
+    }
+
+    // Reads with `peek`, so the dump does not count as accesses of the program
+    fn print_memory(cpu: &Cpu, addr: u32, len: u32) {
+        let end = addr.saturating_add(len);
+        for line_addr in (addr..end).step_by(16) {
+            let mut line = format!("{:06x}:", line_addr);
+            let mut ascii = String::new();
+            for a in line_addr..line_addr.saturating_add(16).min(end) {
+                match cpu.bus.peek(a) {
+                    Ok(byte) => {
+                        line += &format!(" {:02x}", byte);
+                        ascii.push(if byte.is_ascii_graphic() { byte as char } else { '.' });
+                    }
+                    Err(_) => {
+                        line += " ??";
+                        ascii.push('?');
+                    }
+                }
+            }
+            println!("{:<55} {}", line, ascii);
+        }
+    }
+
+    /// Walks the frames linked by er6, as set up by the `push er6; mov.l er7,er6` prologue.
+    fn print_backtrace(cpu: &Cpu) {
+        let pc = cpu.read_pc();
+        println!("#0  {}", format_code_addr(&cpu.symbols, pc));
+
+        let mut fp = cpu.er[6];
+        for depth in 1..MAX_BACKTRACE_DEPTH {
+            let (Some(next_fp), Some(ret)) = (read_u32(&cpu.bus, fp), read_u32(&cpu.bus, fp.wrapping_add(4))) else {
+                break;
+            };
+            if ret == 0 || cpu.symbols.find_by_addr(ret).is_none() {
+                break;
+            }
+            println!("#{:<2} {}", depth, format_code_addr(&cpu.symbols, ret));
+            if next_fp <= fp {
+                break;
+            }
+            fp = next_fp;
+        }
+    }
+
+    fn print_io_ports(bus: &Bus) {
+        println!("port  DDR  DR   in");
+        for port in 0..IO_PORT_SIZE as u32 {
+            let ddr = bus.peek(IO_PORT_1_DDR_ADDR + port).unwrap_or(0);
+            let dr = bus.peek(IO_PORT_1_DR_ADDR + port).unwrap_or(0);
+            println!("{:<5x} {:02x}   {:02x}   {:02x}", port + 1, ddr, dr, bus.io_port_in[port as usize]);
+        }
+    }
+
+    /// Returns the length of the instruction at pc if it is a call (JSR, BSR or TRAPA).
+    fn call_length(cpu: &Cpu) -> Option<u32> {
+        let pc = cpu.read_pc();
+        let opcode = u16::from_be_bytes([cpu.bus.peek(pc).ok()?, cpu.bus.peek(pc.wrapping_add(1)).ok()?]);
+        if !is_call(opcode) {
+            return None;
+        }
+        match (opcode >> 8) as u8 {
+            // JSR @aa:24, BSR d:16
+            0x5e | 0x5c => Some(4),
+            _ => Some(2),
+        }
+    }
+}
+
+// Formats `0x416918 <_main> at main.c:10 in main`
+fn format_code_addr(symbols: &Symbols, addr: u32) -> String {
+    let mut s = format!("0x{:x} <{}>", addr, symbols.format_addr(addr));
+    if let Some(location) = symbols.format_location(addr) {
+        s += &format!(" at {}", location);
+    }
+    s
+}
+
+fn read_u32(bus: &Bus, addr: u32) -> Option<u32> {
+    let mut value = 0;
+    for i in 0..4 {
+        value = (value << 8) | bus.peek(addr.wrapping_add(i)).ok()? as u32;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_inspect_without_access() {
+        let mut cpu = Cpu::new();
+        cpu.bus.access_log = Some(Default::default());
+        // the dump stops at the end of the address space
+        Debugger::print_memory(&cpu, 0xffffffff, 16);
+        Debugger::print_backtrace(&cpu);
+        Debugger::print_io_ports(&cpu.bus);
+        assert!(cpu.bus.access_log.as_ref().unwrap().borrow().is_empty());
+
+        // bsr d:8
+        cpu.bus.write(0, 0x55).unwrap();
+        assert_eq!(Debugger::call_length(&cpu), Some(2));
+        cpu.bus.write(0, 0x54).unwrap();
+        assert_eq!(Debugger::call_length(&cpu), None);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::elf::Symbols;
+use anyhow::{anyhow, bail, Context as _, Result};
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Break(u32),
+    Delete(usize),
+    Watch(u32, u32),
+    Unwatch(usize),
+    Info,
+    Continue,
+    Step(usize),
+    Next,
+    Finish,
+    Registers,
+    Examine(u32, u32),
+    Patch(u32, Vec<u8>),
+    Backtrace,
+    IoPort,
+    Set(String, String),
+    Config,
+    Quit,
+}
+
+pub const HELP: &str = "\
+break <addr|symbol>        set a breakpoint (b)
+delete <id>                delete a breakpoint (d)
+watch <addr|symbol> [len]  stop when the memory range is written (w)
+unwatch <id>               delete a watchpoint
+info                       list breakpoints and watchpoints (i)
+continue                   continue execution (c)
+step [count]               execute instructions (s)
+next                       execute an instruction, stepping over subroutine calls (n)
+finish                     run until the current function returns
+regs                       print registers and CCR (r)
+x <addr|symbol> [len]      hexdump memory
+patch <addr|symbol> <byte>..  write bytes to memory
+bt                         print backtrace
+io                         print I/O ports
+set <option> <value>       change an option of the emulator
+config                     print the options of the emulator
+quit                       stop the program (q)";
+
+impl Command {
+    pub fn parse(line: &str, symbols: &Symbols) -> Result<Command> {
+        let mut args = line.split_whitespace();
+        let Some(name) = args.next() else {
+            bail!("empty command");
+        };
+        let args: Vec<&str> = args.collect();
+
+        let command = match name {
+            "help" | "h" | "?" => Command::Help,
+            "break" | "b" => Command::Break(parse_addr(arg(&args, 0)?, symbols)?),
+            "delete" | "d" => Command::Delete(parse_number(arg(&args, 0)?)?),
+            "watch" | "w" => Command::Watch(parse_addr(arg(&args, 0)?, symbols)?, parse_len(&args, 1, 1)?),
+            "unwatch" => Command::Unwatch(parse_number(arg(&args, 0)?)?),
+            "info" | "i" => Command::Info,
+            "continue" | "c" => Command::Continue,
+            "step" | "s" => Command::Step(parse_len(&args, 0, 1)? as usize),
+            "next" | "n" => Command::Next,
+            "finish" => Command::Finish,
+            "regs" | "r" => Command::Registers,
+            "x" => Command::Examine(parse_addr(arg(&args, 0)?, symbols)?, parse_len(&args, 1, 16)?),
+            "patch" => {
+                let addr = parse_addr(arg(&args, 0)?, symbols)?;
+                if args.len() < 2 {
+                    bail!("patch requires at least one byte");
+                }
+                let bytes = args[1..]
+                    .iter()
+                    .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16).with_context(|| format!("invalid byte [{}]", b)))
+                    .collect::<Result<Vec<u8>>>()?;
+                Command::Patch(addr, bytes)
+            }
+            "bt" | "backtrace" => Command::Backtrace,
+            "io" => Command::IoPort,
+            "set" => Command::Set(arg(&args, 0)?.to_string(), arg(&args, 1)?.to_string()),
+            "config" => Command::Config,
+            "quit" | "q" => Command::Quit,
+            _ => bail!("unknown command [{}]. Type 'help' to list commands.", name),
+        };
+        Ok(command)
+    }
+}
+
+fn arg<'a>(args: &[&'a str], index: usize) -> Result<&'a str> {
+    args.get(index).copied().ok_or_else(|| anyhow!("missing argument"))
+}
+
+fn parse_number(s: &str) -> Result<usize> {
+    s.parse::<usize>().with_context(|| format!("invalid number [{}]", s))
+}
+
+fn parse_len(args: &[&str], index: usize, default: u32) -> Result<u32> {
+    match args.get(index) {
+        Some(s) => Ok(parse_number(s)? as u32),
+        None => Ok(default),
+    }
+}
+
+/// Parses `0x416918`, `416918`, `_main`, `main` or `main+0x10`.
+pub fn parse_addr(s: &str, symbols: &Symbols) -> Result<u32> {
+    let (base, offset) = match s.split_once('+') {
+        Some((base, offset)) => (base, parse_hex(offset)?),
+        None => (s, 0),
+    };
+
+    let symbol = symbols.find_by_name(base).or_else(|| symbols.find_by_name(&format!("_{}", base)));
+    let base = match symbol {
+        Some(symbol) => symbol.addr,
+        None => parse_hex(base)?,
+    };
+    base.checked_add(offset).with_context(|| format!("address out of range [{}]", s))
+}
+
+fn parse_hex(s: &str) -> Result<u32> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).with_context(|| format!("invalid address [{}]", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_addr, Command};
+    use crate::elf::{Symbol, Symbols};
+
+    fn create_symbols() -> Symbols {
+        let mut symbols = Symbols::new();
+        symbols.push(Symbol {
+            name: "_main".to_string(),
+            addr: 0x416918,
+            size: 0x20,
+        });
+        symbols
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        let symbols = create_symbols();
+        assert_eq!(parse_addr("0x416918", &symbols).unwrap(), 0x416918);
+        assert_eq!(parse_addr("ffbf20", &symbols).unwrap(), 0xffbf20);
+        assert_eq!(parse_addr("_main", &symbols).unwrap(), 0x416918);
+        assert_eq!(parse_addr("main", &symbols).unwrap(), 0x416918);
+        assert_eq!(parse_addr("main+0x10", &symbols).unwrap(), 0x416928);
+        assert!(parse_addr("foo", &symbols).is_err());
+        assert!(parse_addr("ffffffff+1", &symbols).is_err());
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let symbols = create_symbols();
+        assert_eq!(Command::parse("b main", &symbols).unwrap(), Command::Break(0x416918));
+        assert_eq!(Command::parse("step", &symbols).unwrap(), Command::Step(1));
+        assert_eq!(Command::parse("s 10", &symbols).unwrap(), Command::Step(10));
+        assert_eq!(Command::parse("watch ffbf20 4", &symbols).unwrap(), Command::Watch(0xffbf20, 4));
+        assert_eq!(Command::parse("x ffbf20", &symbols).unwrap(), Command::Examine(0xffbf20, 16));
+        assert_eq!(
+            Command::parse("patch ffbf20 01 ff", &symbols).unwrap(),
+            Command::Patch(0xffbf20, vec![0x01, 0xff])
+        );
+        assert!(Command::parse("patch ffbf20", &symbols).is_err());
+        assert_eq!(
+            Command::parse("set print_instruction on", &symbols).unwrap(),
+            Command::Set("print_instruction".to_string(), "on".to_string())
+        );
+        assert!(Command::parse("set print_instruction", &symbols).is_err());
+        assert!(Command::parse("foo", &symbols).is_err());
+    }
+}
@@ -1,90 +1,236 @@
-use crate::bus::AREA2_START_ADDR;
 use crate::cpu::Cpu;
+use crate::elf::header::{ElfClass, ElfData, ElfHeader32, ELF_MAGIC_SIGNATURE};
 use crate::elf::parse_symtab::parse_symbol_table32;
-use crate::elf::program_header::SegmentType;
-use std::io::Read;
+use crate::elf::program_header::{ProgramHeader32, SegmentType};
+use crate::elf::rela::Rela32;
+use crate::elf::section::{SectionHeader32, SectionType};
+use crate::memory::MEMORY_END_ADDR;
+use anyhow::{Context as _, Result};
+use std::ops::Range;
 
+mod dwarf;
+mod error;
 mod header;
 mod parse_header;
 mod parse_program_header;
+mod parse_rela;
 mod parse_section;
 mod parse_symtab;
 mod program_header;
+mod rela;
 mod section;
 mod string_table;
+mod symbols;
 mod symtab;
 
+pub use dwarf::LineTable;
+pub use error::ElfError;
+pub use symbols::Symbol;
+pub use symbols::Symbols;
+
+// Address position-independent programs are relocated to
 pub const PROGRAM_START_ADDR: usize = 0x416900;
 const SIZE_OF_TCB: usize = 88;
 
-fn read_elf(path: String) -> Vec<u8> {
-    let mut file = std::fs::File::open(path).expect("failed open elf");
-    let mut buf: Vec<u8> = Vec::new();
-    file.read_to_end(&mut buf).unwrap();
-    return buf;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_H8_300: u16 = 46;
+const EM_H8_300H: u16 = 47;
+const EF_H8_MACH: u32 = 0x00ff0000;
+const E_H8_MACH_H8300H: u32 = 0x00810000;
+
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+
+const R_H8_NONE: u8 = 0;
+const R_H8_DIR32: u8 = 1;
+const R_H8_DIR16: u8 = 6;
+const R_H8_DIR8: u8 = 10;
+const R_H8_DIR16A8: u8 = 59;
+const R_H8_DIR16R8: u8 = 60;
+const R_H8_DIR24A8: u8 = 61;
+const R_H8_DIR24R8: u8 = 62;
+const R_H8_DIR32A16: u8 = 63;
+const R_H8_PCREL16: u8 = 67;
+const R_H8_PCREL8: u8 = 68;
+
+/// Loaded segment, relocated to the address it is executed at.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub range: Range<u32>,
+    pub is_executable: bool,
+    pub is_writable: bool,
 }
 
-pub fn load(elf_path: String, cpu: &mut Cpu, args: String) {
-    let elf_binary = read_elf(elf_path);
-    let (_, hd) = parse_header::parse_elf_header32(&elf_binary).unwrap();
+struct ElfFile<'a> {
+    binary: &'a [u8],
+    header: ElfHeader32,
+    sht: Vec<SectionHeader32>,
+    pht: Vec<ProgramHeader32>,
+}
 
-    let (_, sht) = parse_section::parse_section_header_table32(hd.shnum as usize)(&elf_binary[hd.shoff as usize..]).unwrap();
+// Moves `addr` of the program by the base address
+fn relocate_addr(base_addr: u32, addr: u32, what: &'static str) -> Result<u32, ElfError> {
+    base_addr.checked_add(addr).ok_or(ElfError::AddressOverflow(what))
+}
 
-    let raw_section_names_offset = sht[hd.shstrndx as usize].offset;
-    let raw_section_names = &elf_binary[raw_section_names_offset as usize..];
-    let sections = sht
-        .iter()
-        .map(|header| section::Section32 {
-            name: string_table::parse_string_table_entry(&raw_section_names[header.name_idx as usize..])
-                .unwrap()
-                .1,
-            header,
-        })
-        .collect::<Vec<section::Section32>>();
+fn file_range<'a>(binary: &'a [u8], offset: u32, size: u32, what: &'static str) -> Result<&'a [u8], ElfError> {
+    binary
+        .get(offset as usize..offset as usize + size as usize)
+        .ok_or(ElfError::Truncated(what))
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parses the headers and checks the file is a 32-bit big-endian H8/300H executable.
+    fn parse(binary: &'a [u8]) -> Result<Self, ElfError> {
+        if !binary.starts_with(ELF_MAGIC_SIGNATURE) {
+            return Err(ElfError::NotElf);
+        }
+        let (_, header) = parse_header::parse_elf_header32(binary).map_err(|_| ElfError::Truncated("elf header"))?;
+        if header.ident.class != ElfClass::Bit32 {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if header.ident.data != ElfData::Msb {
+            return Err(ElfError::UnsupportedEndianness);
+        }
+        let mach = header.flags & EF_H8_MACH;
+        let is_h8300h = match header.machine {
+            EM_H8_300 => mach == E_H8_MACH_H8300H || mach == 0,
+            EM_H8_300H => true,
+            _ => false,
+        };
+        if !is_h8300h {
+            return Err(ElfError::UnsupportedMachine(header.machine, header.flags));
+        }
+        if header.e_type != ET_EXEC && header.e_type != ET_DYN {
+            return Err(ElfError::UnsupportedType(header.e_type));
+        }
+
+        let raw_sht = binary
+            .get(header.shoff as usize..)
+            .ok_or(ElfError::Truncated("section header table"))?;
+        let (_, sht) = parse_section::parse_section_header_table32(header.shnum as usize)(raw_sht)
+            .map_err(|_| ElfError::Truncated("section header table"))?;
+        let raw_pht = binary
+            .get(header.phoff as usize..)
+            .ok_or(ElfError::Truncated("program header table"))?;
+        let (_, pht) = parse_program_header::parse_program_header_table32(header.phnum as usize)(raw_pht)
+            .map_err(|_| ElfError::Truncated("program header table"))?;
+        Ok(ElfFile { binary, header, sht, pht })
+    }
 
-    let (_, pht) = parse_program_header::parse_program_header_table32(hd.phnum as usize)(&elf_binary[hd.phoff as usize..]).unwrap();
+    fn sections(&self) -> Result<Vec<section::Section32<'_>>, ElfError> {
+        match self.sht.get(self.header.shstrndx as usize) {
+            Some(names) => {
+                let raw_section_names = self
+                    .binary
+                    .get(names.offset as usize..)
+                    .ok_or(ElfError::Truncated("section names"))?;
+                Ok(name_sections(&self.sht, raw_section_names))
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Programs built for MES are linked at 0 and addressed through .got, so they are relocated to PROGRAM_START_ADDR
+    fn base_addr(&self, sections: &[section::Section32]) -> u32 {
+        if self.header.e_type == ET_DYN || sections.iter().any(|s| s.name == ".got") {
+            PROGRAM_START_ADDR as u32
+        } else {
+            0
+        }
+    }
+
+    fn symbols(&self) -> Result<Vec<symtab::SymbolTableWithName32>> {
+        match self.sht.iter().find(|header| header.ty == SectionType::SymTab) {
+            Some(header) => Ok(parse_symtab(self.binary, &self.sht, header)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
 
-    cpu.er[2] = PROGRAM_START_ADDR as u32;
+/// Loads an H8/300H elf executable.
+/// Position-independent programs are relocated to `PROGRAM_START_ADDR` and get the stack, the TCB and the args after the program.
+/// Absolutely linked programs are loaded at their own addresses and start with the stack at the end of the on-chip memory.
+pub fn load(elf_path: &str, cpu: &mut Cpu, args: String) -> Result<()> {
+    let elf_binary = std::fs::read(elf_path).with_context(|| format!("failed open elf [{}]", elf_path))?;
+    load_binary(&elf_binary, cpu, args)
+}
+
+fn load_binary(elf_binary: &[u8], cpu: &mut Cpu, args: String) -> Result<()> {
+    let elf = ElfFile::parse(elf_binary)?;
+    let sections = elf.sections()?;
+    let base_addr = elf.base_addr(&sections);
+    let is_position_independent = base_addr != 0;
+    cpu.load_base = base_addr;
+
+    cpu.er[2] = relocate_addr(base_addr, elf.header.entry, "entry point")?;
     log::trace!("Set er2 [0x{:x}]", cpu.er[2]);
 
     // load to memory
-    let program_dram_offset = PROGRAM_START_ADDR - AREA2_START_ADDR as usize;
-    for ph in &pht {
-        if ph.ty == SegmentType::Load {
-            cpu.bus.dram
-                [program_dram_offset + ph.virtual_addr as usize..program_dram_offset + (ph.virtual_addr + ph.size_in_file) as usize]
-                .copy_from_slice(&elf_binary[ph.offset as usize..(ph.offset + ph.size_in_file) as usize]);
-        }
+    let mut program_end = base_addr;
+    for ph in elf.pht.iter().filter(|ph| ph.ty == SegmentType::Load) {
+        let data = file_range(elf_binary, ph.offset, ph.size_in_file, "segment")?;
+        let start = relocate_addr(base_addr, ph.virtual_addr, "segment")?;
+        let end = relocate_addr(start, ph.size_in_mem, "segment")?;
+        cpu.bus
+            .load(start, data)
+            .with_context(|| format!("failed to load segment [0x{:x}..0x{:x}]", start, end))?;
+        cpu.segments.push(Segment {
+            range: start..end,
+            is_executable: ph.flags & PF_X != 0,
+            is_writable: ph.flags & PF_W != 0,
+        });
+        program_end = program_end.max(end);
+    }
+    if cpu.segments.is_empty() {
+        return Err(ElfError::NoLoadableSegment.into());
     }
 
-    for s in sections {
+    let symtabs = elf.symbols()?;
+    cpu.symbols = Symbols::from_symtabs(&symtabs, base_addr);
+    if let Some(exit) = symtabs.iter().find(|s| s.name == "___exit") {
+        cpu.exit_addr = exit.symtab.value.wrapping_add(base_addr);
+        log::trace!("Set ___exit address [0x{:x}]", cpu.exit_addr);
+    }
+
+    let relocated_sections = if is_position_independent {
+        relocate(&elf, &sections, cpu, base_addr)?
+    } else {
+        Vec::new()
+    };
+
+    // stack and the area written by the loader (TCB and args), checked by memcheck
+    let mut stack = 0..0;
+    let mut loader_data = 0..0;
+    for (i, s) in sections.iter().enumerate() {
         if s.name == ".got" {
             // set .got section address to er5
-            cpu.er[5] = s.header.addr + PROGRAM_START_ADDR as u32;
+            cpu.er[5] = relocate_addr(base_addr, s.header.addr, ".got")?;
             log::trace!("Set er5 [0x{:x}(0x{:x})]", cpu.er[5], s.header.addr);
 
-            // Add start address of program to Global Offset
-            for i in 0..(s.header.size / 4) {
-                let got_addr = program_dram_offset + (s.header.addr + 4 * i) as usize;
-                let mut global_off = ((cpu.bus.dram[got_addr] as u32) << 24)
-                    | ((cpu.bus.dram[got_addr + 1] as u32) << 16)
-                    | ((cpu.bus.dram[got_addr + 2] as u32) << 8)
-                    | (cpu.bus.dram[got_addr + 3] as u32);
-                global_off += PROGRAM_START_ADDR as u32;
-                cpu.bus.dram[got_addr..=got_addr + 3].copy_from_slice(&global_off.to_be_bytes());
+            // Without relocations, every global offset is relative to the start of the program
+            if is_position_independent && !relocated_sections.contains(&i) {
+                for addr in (cpu.er[5]..relocate_addr(cpu.er[5], s.header.size, ".got")?).step_by(4) {
+                    let global_off = read_u32(cpu, addr)?.wrapping_add(base_addr);
+                    cpu.bus.load(addr, &global_off.to_be_bytes())?;
+                }
             }
-        } else if s.name == ".stack" {
-            let program_size = pht[pht.len() - 1].size_in_mem + pht[pht.len() - 1].physical_addr;
+        } else if s.name == ".stack" && is_position_independent {
             let stack_size = s.header.addr;
-            let mut a = PROGRAM_START_ADDR + program_size as usize + stack_size as usize + 3;
-            a >>= 2;
-            a <<= 2;
+            let mut a = (program_end as usize + stack_size as usize + 3) & !3;
+            stack = program_end..a as u32;
             cpu.er[7] = a as u32 - 8;
             log::trace!("Set er7(stack pointer) [0x{:x}]", cpu.er[7]);
 
-            a += SIZE_OF_TCB + 3;
-            a >>= 2;
-            a <<= 2;
+            a = (a + SIZE_OF_TCB + 3) & !3;
 
             log::info!("args: [{}]", args);
             let mut args_list: Vec<&str> = args.split_whitespace().collect();
@@ -95,48 +241,571 @@ pub fn load(elf_path: String, cpu: &mut Cpu, args: String) {
             cpu.er[1] = a as u32;
             log::trace!("Set er1 [0x{:x}]", cpu.er[1]);
 
-            let mut argp = a;
-            a += 4 * (args_list.len() + 1);
+            let mut argp = a as u32;
+            let mut a = a as u32 + 4 * (args_list.len() as u32 + 1);
 
             for arg in args_list {
-                // Set args pointer
-                let argp_i = argp - AREA2_START_ADDR as usize;
-                cpu.bus.dram[argp_i..argp_i + 4].copy_from_slice(&(a as u32).to_be_bytes());
+                // Set args pointer and string
+                cpu.bus.load(argp, &a.to_be_bytes())?;
                 argp += 4;
-
-                // Set args string
-                for char in arg.as_bytes() {
-                    cpu.bus.dram[a - AREA2_START_ADDR as usize] = *char;
-                    a += 1;
-                }
-                cpu.bus.dram[a - AREA2_START_ADDR as usize] = b'\0';
+                cpu.bus.load(a, arg.as_bytes())?;
+                a += arg.len() as u32;
+                cpu.bus.load(a, b"\0")?;
                 a += 1;
             }
-        } else if s.name == ".symtab" {
-            let (_, symtabs) =
-                parse_symbol_table32((s.header.size / s.header.entry_size) as usize)(&elf_binary[s.header.offset as usize..]).unwrap();
-
-            let raw_symbol_names_offset = sht[s.header.link as usize].offset;
-            let raw_symbol_names = &elf_binary[raw_symbol_names_offset as usize..];
-            let symtabs_with_name = symtabs
-                .into_iter()
-                .map(|symtab| -> symtab::SymbolTableWithName32 {
-                    // nameの中にスペースがあるとエラー？
-                    symtab::SymbolTableWithName32 {
-                        name: string_table::parse_string_table_entry(&raw_symbol_names[symtab.name_idx as usize..])
-                            .unwrap_or_else(|_| (&raw_symbol_names, "Error".to_string()))
-                            .1,
-                        symtab,
-                    }
-                })
-                .collect::<Vec<symtab::SymbolTableWithName32>>();
-
-            for symtab in symtabs_with_name {
-                if symtab.name == "___exit" {
-                    cpu.exit_addr = symtab.symtab.value + PROGRAM_START_ADDR as u32;
-                    log::trace!("Set ___exit address [0x{:x}]", cpu.exit_addr);
-                }
+            loader_data = cpu.er[7]..a;
+        }
+    }
+    if !is_position_independent {
+        set_absolute_stack(cpu, &args);
+    }
+
+    load_debug_info(elf_binary, &sections, base_addr, &mut cpu.symbols);
+
+    if let Some(memcheck) = &cpu.bus.memcheck {
+        let mut memcheck = memcheck.borrow_mut();
+        for segment in &cpu.segments {
+            memcheck.mark_initialized(segment.range.clone());
+            if !segment.is_writable {
+                memcheck.protect(segment.range.clone());
+            }
+        }
+        memcheck.mark_initialized(loader_data);
+        memcheck.stack = stack;
+    }
+    Ok(())
+}
+
+/// Starts an absolutely linked program with the stack at the end of the on-chip memory.
+/// The args are not passed, as the program does not have the area for them.
+pub(crate) fn set_absolute_stack(cpu: &mut Cpu, args: &str) {
+    cpu.er[7] = (MEMORY_END_ADDR + 1) & !3;
+    log::trace!("Set er7(stack pointer) [0x{:x}]", cpu.er[7]);
+    if !args.is_empty() {
+        log::warn!("args are passed only to position-independent programs");
+    }
+}
+
+fn read_u32(cpu: &Cpu, addr: u32) -> Result<u32> {
+    let mut value = 0;
+    for i in 0..4 {
+        value = (value << 8) | cpu.bus.peek(addr + i)? as u32;
+    }
+    Ok(value)
+}
+
+/// Returns the 32-bit word at the offset of the relocation with `value` (S + A, moved by the base address) in its field,
+/// or None if the field does not depend on the load address.
+fn relocate_word(ty: u8, offset: u32, word: u32, value: u32) -> Result<Option<u32>, ElfError> {
+    let overflow = ElfError::RelocationOverflow { ty, offset };
+    match ty {
+        R_H8_NONE | R_H8_PCREL16 | R_H8_PCREL8 => Ok(None),
+        R_H8_DIR32 | R_H8_DIR32A16 => Ok(Some(value)),
+        R_H8_DIR24A8 | R_H8_DIR24R8 => {
+            if value > 0x00ffffff {
+                return Err(overflow);
             }
+            Ok(Some((word & 0xff000000) | value))
+        }
+        // 16 and 8-bit absolute addresses reach only the ends of the address space, e.g. the I/O registers
+        R_H8_DIR16 | R_H8_DIR16A8 | R_H8_DIR16R8 => {
+            if value > 0x7fff && !(0xff8000..=0xffffff).contains(&value) {
+                return Err(overflow);
+            }
+            Ok(Some((word & 0x0000ffff) | (value << 16)))
+        }
+        R_H8_DIR8 => {
+            if value > 0xff && !(0xffff00..=0xffffff).contains(&value) {
+                return Err(overflow);
+            }
+            Ok(Some((word & 0x00ffffff) | (value << 24)))
+        }
+        _ => Err(ElfError::UnsupportedRelocation { ty, offset }),
+    }
+}
+
+// S + A of the relocation. The addresses of the program move by the base address,
+// absolute symbols (e.g. I/O registers) do not, and relocations without a symbol are relative to the program.
+fn relocation_value(rela: &Rela32, symtabs: &[symtab::SymbolTableWithName32], base_addr: u32) -> Result<u32, ElfError> {
+    let symbol_value = match rela.symbol_idx() {
+        0 => base_addr,
+        index => {
+            let symbol = symtabs.get(index as usize).ok_or(ElfError::Truncated("relocation symbol"))?;
+            match symbol.symtab.shndx {
+                SHN_UNDEF => return Err(ElfError::UndefinedSymbol(symbol.name.clone())),
+                SHN_ABS => symbol.symtab.value,
+                _ => symbol.symtab.value.wrapping_add(base_addr),
+            }
+        }
+    };
+    Ok(symbol_value.wrapping_add(rela.addend as u32))
+}
+
+// Applies the .rela sections of the loaded sections, and returns the indexes of the relocated sections.
+// The dynamic relocations (.rela.dyn, sh_info 0) are not tied to a section, and relocate the sections their offsets are in.
+fn relocate(elf: &ElfFile, sections: &[section::Section32], cpu: &mut Cpu, base_addr: u32) -> Result<Vec<usize>> {
+    let mut relocated = Vec::new();
+    for s in sections.iter().filter(|s| s.header.ty == SectionType::Rela) {
+        let is_dynamic = s.header.info == 0;
+        if !is_dynamic {
+            match elf.sht.get(s.header.info as usize) {
+                Some(target) if target.flags & SHF_ALLOC != 0 => (),
+                _ => continue,
+            }
+        }
+        let raw = file_range(elf.binary, s.header.offset, s.header.size, "relocation table")?;
+        let (_, relas) = parse_rela::parse_rela_table32(raw.len() / 12)(raw).map_err(|_| ElfError::Truncated("relocation table"))?;
+        // .symtab for the relocations kept by --emit-relocs, .dynsym for .rela.dyn
+        let symtabs = match elf.sht.get(s.header.link as usize) {
+            Some(header) if header.ty == SectionType::SymTab || header.ty == SectionType::DynSym => {
+                parse_symtab(elf.binary, &elf.sht, header)?
+            }
+            _ => Vec::new(),
+        };
+
+        for rela in &relas {
+            let value = relocation_value(rela, &symtabs, base_addr)?;
+            let addr = relocate_addr(base_addr, rela.offset, "relocation")?;
+            if let Some(word) = relocate_word(rela.ty(), rela.offset, read_u32(cpu, addr)?, value)? {
+                cpu.bus.load(addr, &word.to_be_bytes())?;
+            }
+            if is_dynamic {
+                let target = elf.sht.iter().position(|header| {
+                    header.flags & SHF_ALLOC != 0 && (header.addr..header.addr.saturating_add(header.size)).contains(&rela.offset)
+                });
+                relocated.extend(target.filter(|i| !relocated.contains(i)));
+            }
+        }
+        log::trace!("Applied {} relocations of [{}]", relas.len(), s.name);
+        if !is_dynamic {
+            relocated.push(s.header.info as usize);
         }
     }
+    Ok(relocated)
+}
+
+fn name_sections<'a>(sht: &'a [SectionHeader32], raw_section_names: &[u8]) -> Vec<section::Section32<'a>> {
+    sht.iter()
+        .map(|header| section::Section32 {
+            name: raw_section_names
+                .get(header.name_idx as usize..)
+                .and_then(|raw| string_table::parse_string_table_entry(raw).ok())
+                .map(|(_, name)| name)
+                .unwrap_or_default(),
+            header,
+        })
+        .collect::<Vec<section::Section32>>()
+}
+
+fn section_data<'a>(elf_binary: &'a [u8], sections: &[section::Section32], name: &str) -> Option<&'a [u8]> {
+    let header = sections.iter().find(|s| s.name == name)?.header;
+    file_range(elf_binary, header.offset, header.size, "section").ok()
+}
+
+// Reads .debug_line and the function names of .debug_info. Programs without them keep only the symbols.
+fn load_debug_info(elf_binary: &[u8], sections: &[section::Section32], base_addr: u32, symbols: &mut Symbols) {
+    let Some(debug_line) = section_data(elf_binary, sections, ".debug_line") else {
+        return;
+    };
+    let dwarf_sections = dwarf::Sections {
+        str: section_data(elf_binary, sections, ".debug_str").unwrap_or(&[]),
+        line_str: section_data(elf_binary, sections, ".debug_line_str").unwrap_or(&[]),
+        str_offsets: section_data(elf_binary, sections, ".debug_str_offsets").unwrap_or(&[]),
+        addr: section_data(elf_binary, sections, ".debug_addr").unwrap_or(&[]),
+    };
+    let lines = match LineTable::parse(debug_line, &dwarf_sections, base_addr) {
+        Ok(lines) => lines,
+        Err(e) => {
+            log::warn!("Failed to parse .debug_line: {:#}", e);
+            return;
+        }
+    };
+
+    let functions = match (
+        section_data(elf_binary, sections, ".debug_info"),
+        section_data(elf_binary, sections, ".debug_abbrev"),
+    ) {
+        (Some(info), Some(abbrev)) => dwarf::parse_functions(info, abbrev, &dwarf_sections, base_addr).unwrap_or_else(|e| {
+            log::warn!("Failed to parse .debug_info: {:#}", e);
+            Vec::new()
+        }),
+        _ => Vec::new(),
+    };
+    symbols.set_debug_info(lines, functions);
+}
+
+fn parse_symtab(
+    elf_binary: &[u8],
+    sht: &[SectionHeader32],
+    symtab_header: &SectionHeader32,
+) -> Result<Vec<symtab::SymbolTableWithName32>, ElfError> {
+    let raw_symtabs = file_range(elf_binary, symtab_header.offset, symtab_header.size, "symbol table")?;
+    let entries = raw_symtabs.len().checked_div(symtab_header.entry_size as usize).unwrap_or(0);
+    let (_, symtabs) = parse_symbol_table32(entries)(raw_symtabs).map_err(|_| ElfError::Truncated("symbol table"))?;
+
+    let raw_symbol_names = sht
+        .get(symtab_header.link as usize)
+        .and_then(|header| elf_binary.get(header.offset as usize..))
+        .ok_or(ElfError::Truncated("symbol names"))?;
+    Ok(symtabs
+        .into_iter()
+        .map(|symtab| -> symtab::SymbolTableWithName32 {
+            // nameの中にスペースがあるとエラー？
+            symtab::SymbolTableWithName32 {
+                name: raw_symbol_names
+                    .get(symtab.name_idx as usize..)
+                    .and_then(|raw| string_table::parse_string_table_entry(raw).ok())
+                    .map(|(_, name)| name)
+                    .unwrap_or_else(|| "Error".to_string()),
+                symtab,
+            }
+        })
+        .collect::<Vec<symtab::SymbolTableWithName32>>())
+}
+
+/// Reads only the symbols and debug information of the elf, relocated the same way as `load`.
+pub fn load_symbols(elf_path: &str) -> Result<Symbols> {
+    let elf_binary = std::fs::read(elf_path).with_context(|| format!("failed open elf [{}]", elf_path))?;
+    let elf = ElfFile::parse(&elf_binary)?;
+    let sections = elf.sections()?;
+    let base_addr = elf.base_addr(&sections);
+
+    let mut symbols = Symbols::from_symtabs(&elf.symbols()?, base_addr);
+    load_debug_info(&elf_binary, &sections, base_addr, &mut symbols);
+    Ok(symbols)
+}
+
+/// Reads the code of the elf as functions: the function and label symbols in executable sections, relocated the same way as `load`.
+/// A symbol without size extends to the next symbol or the end of its section, and sections without symbols are one range.
+pub fn load_code_ranges(elf_path: &str) -> Result<Vec<Symbol>> {
+    let elf_binary = std::fs::read(elf_path).with_context(|| format!("failed open elf [{}]", elf_path))?;
+    code_ranges(&elf_binary)
+}
+
+fn code_ranges(elf_binary: &[u8]) -> Result<Vec<Symbol>> {
+    let elf = ElfFile::parse(elf_binary)?;
+    let sections = elf.sections()?;
+    let base_addr = elf.base_addr(&sections);
+    let symtabs = elf.symbols()?;
+
+    let mut ranges = Vec::new();
+    for (i, section) in sections.iter().enumerate() {
+        let header = section.header;
+        if header.flags & SHF_EXECINSTR == 0 || header.size == 0 {
+            continue;
+        }
+        let section_end = header.addr.checked_add(header.size).ok_or(ElfError::AddressOverflow("section"))?;
+        // every symbol of the section bounds the labels before it
+        let mut bounds: Vec<u32> = symtabs
+            .iter()
+            .filter(|s| s.symtab.shndx as usize == i)
+            .map(|s| s.symtab.value)
+            .collect();
+        bounds.sort();
+
+        let mut code_symbols: Vec<&symtab::SymbolTableWithName32> = symtabs
+            .iter()
+            .filter(|s| s.symtab.shndx as usize == i && !s.name.is_empty())
+            .filter(|s| matches!(s.symtab.info & 0xf, STT_NOTYPE | STT_FUNC))
+            .collect();
+        code_symbols.sort_by_key(|s| s.symtab.value);
+        if code_symbols.is_empty() {
+            ranges.push(Symbol {
+                name: section.name.clone(),
+                addr: relocate_addr(base_addr, header.addr, "section")?,
+                size: header.size,
+            });
+            continue;
+        }
+        for s in code_symbols {
+            let start = s.symtab.value;
+            let end = match s.symtab.size {
+                0 => bounds.iter().copied().find(|&addr| addr > start).unwrap_or(section_end),
+                size => start.checked_add(size).ok_or(ElfError::AddressOverflow("symbol"))?,
+            };
+            ranges.push(Symbol {
+                name: s.name.clone(),
+                addr: relocate_addr(base_addr, start, "symbol")?,
+                size: end.min(section_end).saturating_sub(start),
+            });
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        code_ranges, load_binary, load_symbols, relocate_word, relocation_value, ElfError, PROGRAM_START_ADDR, R_H8_DIR16, R_H8_DIR24R8,
+        R_H8_DIR32, R_H8_PCREL8, SHN_ABS, SHN_UNDEF,
+    };
+    use crate::{
+        cpu::Cpu,
+        elf::{
+            rela::Rela32,
+            symtab::{SymbolTable32, SymbolTableWithName32},
+        },
+    };
+
+    // Absolutely linked elf with one segment and no sections
+    fn absolute_elf(addr: u32, data: &[u8]) -> Vec<u8> {
+        let mut elf = b"\x7fELF\x01\x02\x01".to_vec();
+        elf.resize(16, 0);
+        for half in [2u16, 46] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        for word in [1u32, addr, 52, 0, 0x810000] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        for half in [52u16, 32, 1, 40, 0, 0] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        for word in [1u32, 84, addr, addr, data.len() as u32, data.len() as u32, 5, 1] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        elf.extend_from_slice(data);
+        elf
+    }
+
+    // name, type, flags, addr, size, info and contents of a section
+    type SectionSpec<'a> = (&'a str, u32, u32, u32, u32, u32, &'a [u8]);
+
+    fn push_header(headers: &mut Vec<u8>, words: [u32; 10]) {
+        for word in words {
+            headers.extend_from_slice(&word.to_be_bytes());
+        }
+    }
+
+    // Elf of `e_type` with the segment at 0 and the sections. The sections without contents are in the segment.
+    fn sectioned_elf(e_type: u16, segment: &[u8], sections: &[SectionSpec]) -> Vec<u8> {
+        let mut body = segment.to_vec();
+        let mut names = b"\0".to_vec();
+        let mut headers = vec![0; 40];
+        for (name, ty, flags, addr, size, info, contents) in sections {
+            let offset = if contents.is_empty() {
+                84 + addr
+            } else {
+                body.extend_from_slice(contents);
+                (84 + body.len() - contents.len()) as u32
+            };
+            let entry_size = if *ty == 4 { 12 } else { 0 };
+            push_header(
+                &mut headers,
+                [names.len() as u32, *ty, *flags, *addr, offset, *size, 0, *info, 1, entry_size],
+            );
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        let name_idx = names.len() as u32;
+        names.extend_from_slice(b".shstrtab\0");
+        push_header(
+            &mut headers,
+            [name_idx, 3, 0, 0, (84 + body.len()) as u32, names.len() as u32, 0, 0, 1, 0],
+        );
+        body.extend_from_slice(&names);
+
+        let mut elf = b"\x7fELF\x01\x02\x01".to_vec();
+        elf.resize(16, 0);
+        for half in [e_type, 46] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        for word in [1u32, 0, 52, (84 + body.len()) as u32, 0x810000] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        for half in [52u16, 32, 1, 40, sections.len() as u16 + 2, sections.len() as u16 + 1] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        for word in [1u32, 84, 0, 0, segment.len() as u32, segment.len() as u32, 7, 1] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        elf.extend_from_slice(&body);
+        elf.extend_from_slice(&headers);
+        elf
+    }
+
+    fn load_error(elf_binary: &[u8]) -> ElfError {
+        let mut cpu = Cpu::new();
+        load_binary(elf_binary, &mut cpu, String::new())
+            .unwrap_err()
+            .downcast::<ElfError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_load_position_independent() {
+        let elf_binary = std::fs::read("example/printf.elf").unwrap();
+        let mut cpu = Cpu::new();
+        load_binary(&elf_binary, &mut cpu, "a b".to_string()).unwrap();
+        let base = PROGRAM_START_ADDR as u32;
+        assert_eq!(cpu.load_base, base);
+        assert_eq!(cpu.er[2], base);
+        assert_eq!(cpu.er[5], base + 0x1278);
+        assert_eq!(cpu.er[0], 3);
+        // global offsets are relocated
+        let got = (0..4).fold(0, |v, i| (v << 8) | cpu.bus.peek(cpu.er[5] + i).unwrap() as u32);
+        assert!(got >= base);
+    }
+
+    #[test]
+    fn test_load_dynamic_relocations() {
+        // .rela.dyn relocates a word of .got and one of .data by their offsets
+        let mut relas = Vec::new();
+        for word in [8u32, R_H8_DIR32 as u32, 4, 12, R_H8_DIR32 as u32, 0x10] {
+            relas.extend_from_slice(&word.to_be_bytes());
+        }
+        let mut segment = vec![0; 16];
+        segment[..2].copy_from_slice(&[0x54, 0x70]);
+        let elf_binary = sectioned_elf(
+            3,
+            &segment,
+            &[
+                (".got", 1, 3, 8, 4, 0, &[]),
+                (".data", 1, 3, 12, 4, 0, &[]),
+                (".rela.dyn", 4, 2, 0, 24, 0, &relas),
+            ],
+        );
+        let mut cpu = Cpu::new();
+        load_binary(&elf_binary, &mut cpu, String::new()).unwrap();
+        let base = PROGRAM_START_ADDR as u32;
+        let word = |addr: u32| (0..4).fold(0, |v, i| (v << 8) | cpu.bus.peek(addr + i).unwrap() as u32);
+        // the relocated .got is not moved by the base address again
+        assert_eq!(cpu.er[5], base + 8);
+        assert_eq!(word(base + 8), base + 4);
+        assert_eq!(word(base + 12), base + 0x10);
+    }
+
+    #[test]
+    fn test_load_absolute() {
+        let mut cpu = Cpu::new();
+        load_binary(&absolute_elf(0x400000, &[0x01, 0x00, 0x54, 0x70]), &mut cpu, String::new()).unwrap();
+        assert_eq!(cpu.load_base, 0);
+        assert_eq!(cpu.er[2], 0x400000);
+        assert_eq!(cpu.er[7], 0xffff20);
+        assert_eq!(cpu.bus.peek(0x400002).unwrap(), 0x54);
+        assert_eq!(cpu.segments[0].range, 0x400000..0x400004);
+    }
+
+    #[test]
+    fn test_validate() {
+        let elf_binary = absolute_elf(0x400000, &[0; 4]);
+        assert_eq!(load_error(b"\x5e\x00\x01\x00"), ElfError::NotElf);
+
+        let mut elf = elf_binary.clone();
+        elf[4] = 2;
+        assert_eq!(load_error(&elf), ElfError::UnsupportedClass);
+
+        let mut elf = elf_binary.clone();
+        elf[5] = 1;
+        assert_eq!(load_error(&elf), ElfError::UnsupportedEndianness);
+
+        // EM_ARM
+        let mut elf = elf_binary.clone();
+        elf[19] = 40;
+        assert_eq!(load_error(&elf), ElfError::UnsupportedMachine(40, 0x810000));
+
+        // H8S
+        let mut elf = elf_binary.clone();
+        elf[37] = 0x82;
+        assert_eq!(load_error(&elf), ElfError::UnsupportedMachine(46, 0x820000));
+
+        // ET_REL
+        let mut elf = elf_binary.clone();
+        elf[17] = 1;
+        assert_eq!(load_error(&elf), ElfError::UnsupportedType(1));
+
+        assert_eq!(load_error(&elf_binary[..70]), ElfError::Truncated("program header table"));
+        assert_eq!(load_error(&elf_binary[..86]), ElfError::Truncated("segment"));
+
+        assert_eq!(load_error(&absolute_elf(0xfffffffe, &[0; 4])), ElfError::AddressOverflow("segment"));
+    }
+
+    #[test]
+    fn test_relocation_value() {
+        let base = PROGRAM_START_ADDR as u32;
+        let symbol = |value, shndx| SymbolTableWithName32 {
+            name: "sym".to_string(),
+            symtab: SymbolTable32 {
+                name_idx: 0,
+                value,
+                size: 0,
+                info: 0,
+                other: 0,
+                shndx,
+            },
+        };
+        let symtabs = vec![
+            symbol(0, SHN_UNDEF),
+            symbol(0x100, 1),
+            symbol(0xffff20, SHN_ABS),
+            symbol(0, SHN_UNDEF),
+        ];
+        let rela = |symbol_idx: u32, addend| Rela32 {
+            offset: 0,
+            info: (symbol_idx << 8) | R_H8_DIR32 as u32,
+            addend,
+        };
+        // .rela.dyn entries hold the whole value in the addend
+        assert_eq!(relocation_value(&rela(0, 0x40), &symtabs, base), Ok(base + 0x40));
+        assert_eq!(relocation_value(&rela(1, 4), &symtabs, base), Ok(base + 0x104));
+        assert_eq!(relocation_value(&rela(1, -4), &symtabs, base), Ok(base + 0xfc));
+        assert_eq!(relocation_value(&rela(2, 2), &symtabs, base), Ok(0xffff22));
+        assert_eq!(
+            relocation_value(&rela(3, 0), &symtabs, base),
+            Err(ElfError::UndefinedSymbol("sym".to_string()))
+        );
+        assert_eq!(
+            relocation_value(&rela(4, 0), &symtabs, base),
+            Err(ElfError::Truncated("relocation symbol"))
+        );
+    }
+
+    #[test]
+    fn test_relocate_word() {
+        let base = PROGRAM_START_ADDR as u32;
+        assert_eq!(relocate_word(R_H8_DIR32, 0, 0, base + 0x100), Ok(Some(base + 0x100)));
+        // the opcode byte is kept
+        assert_eq!(
+            relocate_word(R_H8_DIR24R8, 0, 0x5a000000, base + 0x100),
+            Ok(Some(0x5a000100 + base))
+        );
+        assert_eq!(relocate_word(R_H8_PCREL8, 0, 0x40fe0000, base), Ok(None));
+        // 16-bit absolute address of an I/O register, the following bytes are kept
+        assert_eq!(relocate_word(R_H8_DIR16, 0, 0x00005470, 0xffff20), Ok(Some(0xff205470)));
+        assert_eq!(
+            relocate_word(R_H8_DIR16, 0x10, 0, base),
+            Err(ElfError::RelocationOverflow {
+                ty: R_H8_DIR16,
+                offset: 0x10
+            })
+        );
+        assert_eq!(
+            relocate_word(200, 0x10, 0, base),
+            Err(ElfError::UnsupportedRelocation { ty: 200, offset: 0x10 })
+        );
+    }
+
+    #[test]
+    fn test_load_debug_info() {
+        let symbols = load_symbols("example/printf.elf").unwrap();
+        let strlen = PROGRAM_START_ADDR as u32 + 0x1134;
+        assert_eq!(symbols.format_location(strlen).unwrap(), "strlen.c:59 in strlen");
+        assert_eq!(symbols.format_location(strlen + 0xe).unwrap(), "strlen.c:77 in strlen");
+        // no line information
+        assert_eq!(symbols.format_location(PROGRAM_START_ADDR as u32), None);
+    }
+    #[test]
+    fn test_code_ranges() {
+        let ranges = code_ranges(&std::fs::read("example/printf.elf").unwrap()).unwrap();
+        let strlen = ranges.iter().find(|s| s.name == "_strlen").unwrap();
+        assert_eq!(strlen.addr, PROGRAM_START_ADDR as u32 + 0x1134);
+        assert!(strlen.size > 0);
+        assert!(ranges.iter().all(|s| s.addr >= PROGRAM_START_ADDR as u32));
+
+        // a section passing the end of the address space
+        let elf_binary = sectioned_elf(2, &[0; 4], &[(".text", 1, 6, 0xfffffff0, 0x20, 0, &[0; 4])]);
+        assert_eq!(
+            code_ranges(&elf_binary).unwrap_err().downcast::<ElfError>().unwrap(),
+            ElfError::AddressOverflow("section")
+        );
+    }
 }
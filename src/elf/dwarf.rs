@@ -0,0 +1,130 @@
+use anyhow::{bail, Result};
+
+mod info;
+mod line;
+
+pub use info::parse_functions;
+pub use line::LineTable;
+
+/// Sections the attributes refer to by offset, or by index since DWARF 5.
+#[derive(Clone, Copy, Default)]
+pub struct Sections<'a> {
+    pub str: &'a [u8],
+    pub line_str: &'a [u8],
+    pub str_offsets: &'a [u8],
+    pub addr: &'a [u8],
+}
+
+// Cursor over a big-endian DWARF section
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos.min(self.data.len()) < len {
+            bail!("unexpected end of dwarf data at 0x{:x}", self.pos);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    // Reads an unsigned big-endian value of 1, 2 or 4 bytes
+    fn addr(&mut self, size: usize) -> Result<u32> {
+        Ok(match size {
+            1 => self.u8()? as u32,
+            2 => self.u16()? as u32,
+            4 => self.u32()?,
+            _ => bail!("unsupported address size [{}]", size),
+        })
+    }
+
+    fn uleb128(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Result<i64> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Result<&'a str> {
+        let Some(len) = self.data[self.pos.min(self.data.len())..].iter().position(|b| *b == 0) else {
+            bail!("unterminated string at 0x{:x}", self.pos);
+        };
+        let s = std::str::from_utf8(&self.data[self.pos..self.pos + len])?;
+        self.pos += len + 1;
+        Ok(s)
+    }
+
+    // Reads the 32-bit DWARF unit length
+    fn unit_length(&mut self) -> Result<usize> {
+        let len = self.u32()?;
+        if len >= 0xfffffff0 {
+            bail!("64-bit dwarf is not supported");
+        }
+        Ok(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reader;
+
+    #[test]
+    fn test_leb128() {
+        let mut reader = Reader::new(&[0xe5, 0x8e, 0x26, 0x7f, 0x80, 0x7f, 0x02]);
+        assert_eq!(reader.uleb128().unwrap(), 624485);
+        assert_eq!(reader.sleb128().unwrap(), -1);
+        assert_eq!(reader.sleb128().unwrap(), -128);
+        assert_eq!(reader.sleb128().unwrap(), 2);
+        assert!(reader.is_empty());
+        assert!(reader.u8().is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use super::{Reader, Sections};
+use crate::elf::symbols::Symbol;
+use anyhow::{bail, Context as _, Result};
+use std::collections::HashMap;
+
+const DW_UT_COMPILE: u8 = 0x01;
+const DW_UT_PARTIAL: u8 = 0x03;
+
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+const DW_AT_ADDR_BASE: u64 = 0x73;
+
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_FLAG: u64 = 0x0c;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF_ADDR: u64 = 0x10;
+const DW_FORM_REF1: u64 = 0x11;
+const DW_FORM_REF2: u64 = 0x12;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_REF8: u64 = 0x14;
+const DW_FORM_REF_UDATA: u64 = 0x15;
+const DW_FORM_INDIRECT: u64 = 0x16;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_EXPRLOC: u64 = 0x18;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+// DWARF 5
+const DW_FORM_STRX: u64 = 0x1a;
+const DW_FORM_ADDRX: u64 = 0x1b;
+const DW_FORM_REF_SUP4: u64 = 0x1c;
+const DW_FORM_STRP_SUP: u64 = 0x1d;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_REF_SIG8: u64 = 0x20;
+const DW_FORM_IMPLICIT_CONST: u64 = 0x21;
+const DW_FORM_LOCLISTX: u64 = 0x22;
+const DW_FORM_RNGLISTX: u64 = 0x23;
+const DW_FORM_REF_SUP8: u64 = 0x24;
+const DW_FORM_STRX1: u64 = 0x25;
+const DW_FORM_STRX4: u64 = 0x28;
+const DW_FORM_ADDRX1: u64 = 0x29;
+const DW_FORM_ADDRX4: u64 = 0x2c;
+
+struct Abbrev {
+    tag: u64,
+    // (attribute, form, value of DW_FORM_implicit_const)
+    attrs: Vec<(u64, u64, i64)>,
+}
+
+pub(super) enum Value<'a> {
+    Addr(u32),
+    Const(u64),
+    Str(&'a str),
+    // indexes of .debug_str_offsets and .debug_addr, resolved by `Unit::resolve`
+    StrIndex(u64),
+    AddrIndex(u64),
+    None,
+}
+
+fn parse_abbrevs(section: &[u8], offset: usize) -> Result<HashMap<u64, Abbrev>> {
+    let mut reader = Reader::new(section);
+    reader.pos = offset;
+    let mut abbrevs = HashMap::new();
+    loop {
+        let code = reader.uleb128()?;
+        if code == 0 {
+            return Ok(abbrevs);
+        }
+        let tag = reader.uleb128()?;
+        // has_children
+        reader.u8()?;
+        let mut attrs = Vec::new();
+        loop {
+            let attr = reader.uleb128()?;
+            let form = reader.uleb128()?;
+            if attr == 0 && form == 0 {
+                break;
+            }
+            let implicit_const = if form == DW_FORM_IMPLICIT_CONST { reader.sleb128()? } else { 0 };
+            attrs.push((attr, form, implicit_const));
+        }
+        abbrevs.insert(code, Abbrev { tag, attrs });
+    }
+}
+
+fn string_at(section: &[u8], offset: usize) -> Result<&str> {
+    let mut strings = Reader::new(section);
+    strings.pos = offset;
+    strings.cstr()
+}
+
+// Reads an unsigned big-endian index of 1 to 4 bytes
+fn index(reader: &mut Reader, size: usize) -> Result<u64> {
+    Ok(reader.bytes(size)?.iter().fold(0, |value, byte| (value << 8) | *byte as u64))
+}
+
+pub(super) struct Unit<'a> {
+    pub(super) version: u16,
+    pub(super) addr_size: usize,
+    pub(super) sections: Sections<'a>,
+    // set by the attributes of the compile unit
+    str_offsets_base: usize,
+    addr_base: usize,
+}
+
+impl<'a> Unit<'a> {
+    pub(super) fn new(version: u16, addr_size: usize, sections: Sections<'a>) -> Self {
+        Unit {
+            version,
+            addr_size,
+            sections,
+            str_offsets_base: 0,
+            addr_base: 0,
+        }
+    }
+
+    /// Looks up the strings and the addresses given by index.
+    pub(super) fn resolve(&self, value: Value<'a>) -> Result<Value<'a>> {
+        Ok(match value {
+            Value::StrIndex(index) => {
+                let mut offsets = Reader::new(self.sections.str_offsets);
+                offsets.pos = (index as usize)
+                    .checked_mul(4)
+                    .and_then(|offset| offset.checked_add(self.str_offsets_base))
+                    .context("string index out of range")?;
+                Value::Str(string_at(self.sections.str, offsets.u32()? as usize)?)
+            }
+            Value::AddrIndex(index) => {
+                let mut addrs = Reader::new(self.sections.addr);
+                addrs.pos = (index as usize)
+                    .checked_mul(self.addr_size)
+                    .and_then(|offset| offset.checked_add(self.addr_base))
+                    .context("address index out of range")?;
+                Value::Addr(addrs.addr(self.addr_size)?)
+            }
+            value => value,
+        })
+    }
+}
+
+pub(super) fn read_value<'a>(reader: &mut Reader<'a>, form: u64, unit: &Unit<'a>) -> Result<Value<'a>> {
+    Ok(match form {
+        DW_FORM_ADDR => Value::Addr(reader.addr(unit.addr_size)?),
+        DW_FORM_DATA1 | DW_FORM_REF1 | DW_FORM_FLAG => Value::Const(reader.u8()? as u64),
+        DW_FORM_DATA2 | DW_FORM_REF2 => Value::Const(reader.u16()? as u64),
+        DW_FORM_DATA4 | DW_FORM_REF4 | DW_FORM_SEC_OFFSET => Value::Const(reader.u32()? as u64),
+        DW_FORM_DATA8 | DW_FORM_REF8 | DW_FORM_REF_SIG8 | DW_FORM_REF_SUP8 => {
+            reader.bytes(8)?;
+            Value::None
+        }
+        DW_FORM_DATA16 => {
+            reader.bytes(16)?;
+            Value::None
+        }
+        DW_FORM_REF_SUP4 | DW_FORM_STRP_SUP => {
+            reader.u32()?;
+            Value::None
+        }
+        DW_FORM_UDATA | DW_FORM_REF_UDATA | DW_FORM_LOCLISTX | DW_FORM_RNGLISTX => Value::Const(reader.uleb128()?),
+        DW_FORM_SDATA => Value::Const(reader.sleb128()? as u64),
+        DW_FORM_FLAG_PRESENT => Value::Const(1),
+        DW_FORM_STRING => Value::Str(reader.cstr()?),
+        DW_FORM_STRP => Value::Str(string_at(unit.sections.str, reader.u32()? as usize)?),
+        DW_FORM_LINE_STRP => Value::Str(string_at(unit.sections.line_str, reader.u32()? as usize)?),
+        DW_FORM_STRX => Value::StrIndex(reader.uleb128()?),
+        DW_FORM_STRX1..=DW_FORM_STRX4 => Value::StrIndex(index(reader, (form - DW_FORM_STRX1 + 1) as usize)?),
+        DW_FORM_ADDRX => Value::AddrIndex(reader.uleb128()?),
+        DW_FORM_ADDRX1..=DW_FORM_ADDRX4 => Value::AddrIndex(index(reader, (form - DW_FORM_ADDRX1 + 1) as usize)?),
+        DW_FORM_REF_ADDR => {
+            // The size is the address size in DWARF 2 and the offset size since DWARF 3
+            reader.bytes(if unit.version == 2 { unit.addr_size } else { 4 })?;
+            Value::None
+        }
+        DW_FORM_BLOCK1 | DW_FORM_BLOCK2 | DW_FORM_BLOCK4 | DW_FORM_BLOCK | DW_FORM_EXPRLOC => {
+            let len = match form {
+                DW_FORM_BLOCK1 => reader.u8()? as usize,
+                DW_FORM_BLOCK2 => reader.u16()? as usize,
+                DW_FORM_BLOCK4 => reader.u32()? as usize,
+                _ => reader.uleb128()? as usize,
+            };
+            reader.bytes(len)?;
+            Value::None
+        }
+        DW_FORM_INDIRECT => {
+            let form = reader.uleb128()?;
+            read_value(reader, form, unit)?
+        }
+        _ => bail!("unsupported attribute form [0x{:x}]", form),
+    })
+}
+
+/// Reads the functions (`DW_TAG_subprogram` with an address range) from `.debug_info`.
+/// Units of unknown versions are skipped with a warning.
+pub fn parse_functions(info: &[u8], abbrev: &[u8], sections: &Sections, base_addr: u32) -> Result<Vec<Symbol>> {
+    let mut functions = Vec::new();
+    let mut reader = Reader::new(info);
+    while !reader.is_empty() {
+        let len = reader.unit_length()?;
+        let mut unit_reader = Reader::new(reader.bytes(len)?);
+        let version = unit_reader.u16()?;
+        let (abbrev_offset, addr_size) = match version {
+            2..=4 => {
+                let abbrev_offset = unit_reader.u32()?;
+                (abbrev_offset, unit_reader.u8()?)
+            }
+            5 => {
+                let unit_type = unit_reader.u8()?;
+                let addr_size = unit_reader.u8()?;
+                let abbrev_offset = unit_reader.u32()?;
+                // type and split units do not have the functions of the program
+                if unit_type != DW_UT_COMPILE && unit_type != DW_UT_PARTIAL {
+                    continue;
+                }
+                (abbrev_offset, addr_size)
+            }
+            _ => {
+                log::warn!("Skipped a .debug_info unit of unsupported version [{}]", version);
+                continue;
+            }
+        };
+        let abbrevs = parse_abbrevs(abbrev, abbrev_offset as usize)?;
+        let mut unit = Unit::new(version, addr_size as usize, *sections);
+
+        while !unit_reader.is_empty() {
+            let code = unit_reader.uleb128()?;
+            if code == 0 {
+                continue;
+            }
+            let Some(abbrev) = abbrevs.get(&code) else {
+                bail!("unknown abbreviation code [{}]", code);
+            };
+
+            let mut name = None;
+            let mut low_pc = None;
+            let mut high_pc = None;
+            for (attr, form, implicit_const) in &abbrev.attrs {
+                let value = match *form {
+                    DW_FORM_IMPLICIT_CONST => Value::Const(*implicit_const as u64),
+                    _ => read_value(&mut unit_reader, *form, &unit)?,
+                };
+                match (*attr, value) {
+                    (DW_AT_STR_OFFSETS_BASE, Value::Const(base)) => unit.str_offsets_base = base as usize,
+                    (DW_AT_ADDR_BASE, Value::Const(base)) => unit.addr_base = base as usize,
+                    (DW_AT_NAME, value) => name = Some(value),
+                    (DW_AT_LOW_PC, value) => low_pc = Some(value),
+                    (DW_AT_HIGH_PC, value) => high_pc = Some(value),
+                    _ => (),
+                }
+            }
+
+            if abbrev.tag != DW_TAG_SUBPROGRAM {
+                continue;
+            }
+            // the bases are attributes of the compile unit, so the indexes are resolved here
+            let (Some(name), Some(low_pc)) = (name, low_pc) else {
+                continue;
+            };
+            let (Value::Str(name), Value::Addr(low_pc)) = (unit.resolve(name)?, unit.resolve(low_pc)?) else {
+                continue;
+            };
+            // Since DWARF 4 high_pc can be the size of the function
+            let size = match high_pc.map(|high_pc| unit.resolve(high_pc)).transpose()? {
+                Some(Value::Addr(high_pc)) => high_pc.saturating_sub(low_pc),
+                Some(Value::Const(size)) => size as u32,
+                _ => 0,
+            };
+            functions.push(Symbol {
+                name: name.to_string(),
+                addr: low_pc.wrapping_add(base_addr),
+                size,
+            });
+        }
+    }
+    Ok(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_functions;
+    use crate::elf::{dwarf::Sections, symbols::Symbol};
+
+    #[test]
+    fn test_parse_functions() {
+        let abbrev = [
+            0x01, 0x11, 0x01, // compile_unit, children
+            0x03, 0x08, 0x00, 0x00, // name: string
+            0x02, 0x2e, 0x00, // subprogram
+            0x03, 0x0e, // name: strp
+            0x11, 0x01, // low_pc: addr
+            0x12, 0x01, // high_pc: addr
+            0x3f, 0x0c, // external: flag
+            0x00, 0x00, 0x03, 0x34, 0x00, // variable
+            0x03, 0x08, // name: string
+            0x02, 0x0a, // location: block1
+            0x00, 0x00, 0x00,
+        ];
+        let strings = b"\0main\0";
+
+        let mut unit = vec![0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04];
+        unit.extend(b"\x01a.c\0");
+        unit.extend([0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x20, 0x01]);
+        unit.extend(b"\x03var\0\x02\x03\x00");
+        unit.push(0x00);
+        let mut info = (unit.len() as u32).to_be_bytes().to_vec();
+        info.extend(unit);
+
+        let sections = Sections {
+            str: strings,
+            ..Default::default()
+        };
+        let functions = parse_functions(&info, &abbrev, &sections, 0x1000).unwrap();
+        assert_eq!(
+            functions,
+            vec![Symbol {
+                name: "main".to_string(),
+                addr: 0x1100,
+                size: 0x20,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_functions_v5() {
+        let abbrev = [
+            0x01, 0x11, 0x01, // compile_unit, children
+            0x72, 0x17, // str_offsets_base: sec_offset
+            0x73, 0x17, // addr_base: sec_offset
+            0x00, 0x00, 0x02, 0x2e, 0x00, // subprogram
+            0x03, 0x25, // name: strx1
+            0x11, 0x1b, // low_pc: addrx
+            0x12, 0x06, // high_pc: data4
+            0x3a, 0x21, 0x01, // decl_file: implicit_const 1
+            0x00, 0x00, 0x00,
+        ];
+        // the bases point after the 8 byte headers of the contributions
+        let mut str_offsets = vec![0; 8];
+        str_offsets.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let mut addr = vec![0; 8];
+        addr.extend([0x00, 0x00, 0x01, 0x00]);
+        let sections = Sections {
+            str: b"\0main\0",
+            str_offsets: &str_offsets,
+            addr: &addr,
+            ..Default::default()
+        };
+
+        // version 5, compile unit, address size 4, abbrev offset 0
+        let mut unit = vec![0x00, 0x05, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00];
+        unit.extend([0x01, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x08]);
+        unit.extend([0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00]);
+        let mut info = (unit.len() as u32).to_be_bytes().to_vec();
+        info.extend(unit);
+        // a unit of an unknown version is skipped
+        info.extend([0x00, 0x00, 0x00, 0x02, 0x00, 0x06]);
+
+        let functions = parse_functions(&info, &abbrev, &sections, 0x1000).unwrap();
+        assert_eq!(
+            functions,
+            vec![Symbol {
+                name: "main".to_string(),
+                addr: 0x1100,
+                size: 0x20,
+            }]
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use super::{
+    info::{read_value, Unit, Value},
+    Reader, Sections,
+};
+use anyhow::{bail, Result};
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineRow {
+    pub addr: u32,
+    // index of `LineTable::files`
+    pub file: usize,
+    // 0 marks the end of a sequence
+    pub line: u32,
+}
+
+/// Address to source line mapping read from `.debug_line`, relocated to the addresses the program is executed at.
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    // sorted by address
+    rows: Vec<LineRow>,
+}
+
+// State of the line number program
+struct Registers {
+    addr: u32,
+    file: u64,
+    line: i64,
+}
+
+impl Registers {
+    fn new() -> Self {
+        Registers { addr: 0, file: 1, line: 1 }
+    }
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        LineTable {
+            files: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Units of unknown versions are skipped with a warning.
+    pub fn parse(section: &[u8], sections: &Sections, base_addr: u32) -> Result<Self> {
+        let mut table = LineTable::new();
+        let mut reader = Reader::new(section);
+        while !reader.is_empty() {
+            let len = reader.unit_length()?;
+            let unit = reader.bytes(len)?;
+            table.parse_unit(unit, sections, base_addr)?;
+        }
+        // End markers sort before rows starting at the same address
+        table.rows.sort_by_key(|row| (row.addr, row.line != 0));
+        Ok(table)
+    }
+
+    fn parse_unit(&mut self, unit: &[u8], sections: &Sections, base_addr: u32) -> Result<()> {
+        let mut reader = Reader::new(unit);
+        let version = reader.u16()?;
+        if !(2..=5).contains(&version) {
+            log::warn!("Skipped a .debug_line unit of unsupported version [{}]", version);
+            return Ok(());
+        }
+        let mut addr_size = 4;
+        if version >= 5 {
+            addr_size = reader.u8()? as usize;
+            // segment_selector_size
+            reader.u8()?;
+        }
+        let header_len = reader.u32()? as usize;
+        let program_start = reader.pos + header_len;
+        let min_inst_len = reader.u8()? as u32;
+        if version >= 4 {
+            // maximum_operations_per_instruction
+            reader.u8()?;
+        }
+        let _default_is_stmt = reader.u8()?;
+        let line_base = reader.u8()? as i8 as i64;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            bail!("invalid .debug_line header");
+        }
+        let opcode_lengths = reader.bytes(opcode_base as usize - 1)?;
+
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        if version >= 5 {
+            // Directory 0 is the compilation directory and file 0 the primary source file
+            let unit = Unit::new(version, addr_size, *sections);
+            dirs = read_entries(&mut reader, &unit)?.into_iter().map(|(dir, _)| dir).collect();
+            for (name, dir) in read_entries(&mut reader, &unit)? {
+                files.push(Some(self.add_file(dirs.get(dir).copied(), name)));
+            }
+        } else {
+            loop {
+                let dir = reader.cstr()?;
+                if dir.is_empty() {
+                    break;
+                }
+                dirs.push(dir);
+            }
+
+            // Index 0 of `files` and of the directories is unused before DWARF 5
+            files.push(None);
+            loop {
+                let name = reader.cstr()?;
+                if name.is_empty() {
+                    break;
+                }
+                let dir = reader.uleb128()? as usize;
+                reader.uleb128()?;
+                reader.uleb128()?;
+                files.push(Some(self.add_file(dir_before_v5(&dirs, dir), name)));
+            }
+        }
+
+        reader.pos = program_start;
+        let mut regs = Registers::new();
+        while !reader.is_empty() {
+            let opcode = reader.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = opcode - opcode_base;
+                regs.addr = regs.addr.wrapping_add((adjusted / line_range) as u32 * min_inst_len);
+                regs.line = regs.line.wrapping_add(line_base + (adjusted % line_range) as i64);
+                self.push_row(&regs, &files, base_addr);
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let len = reader.uleb128()? as usize;
+                    let mut extended = Reader::new(reader.bytes(len)?);
+                    match extended.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            self.rows.push(LineRow {
+                                addr: regs.addr.wrapping_add(base_addr),
+                                file: 0,
+                                line: 0,
+                            });
+                            regs = Registers::new();
+                        }
+                        DW_LNE_SET_ADDRESS => regs.addr = extended.addr(len - 1)?,
+                        DW_LNE_DEFINE_FILE => {
+                            let name = extended.cstr()?;
+                            let dir = extended.uleb128()? as usize;
+                            files.push(Some(self.add_file(dir_before_v5(&dirs, dir), name)));
+                        }
+                        _ => (),
+                    }
+                }
+                DW_LNS_COPY => self.push_row(&regs, &files, base_addr),
+                DW_LNS_ADVANCE_PC => regs.addr = regs.addr.wrapping_add(reader.uleb128()? as u32 * min_inst_len),
+                DW_LNS_ADVANCE_LINE => regs.line = regs.line.wrapping_add(reader.sleb128()?),
+                DW_LNS_SET_FILE => regs.file = reader.uleb128()?,
+                DW_LNS_CONST_ADD_PC => regs.addr = regs.addr.wrapping_add(((255 - opcode_base) / line_range) as u32 * min_inst_len),
+                DW_LNS_FIXED_ADVANCE_PC => regs.addr = regs.addr.wrapping_add(reader.u16()? as u32),
+                _ => {
+                    // Opcodes without effect on the table (set_column, negate_stmt, ...)
+                    for _ in 0..opcode_lengths[opcode as usize - 1] {
+                        reader.uleb128()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, dir: Option<&str>, name: &str) -> usize {
+        let path = match dir {
+            Some(dir) if !name.starts_with('/') => format!("{}/{}", dir, name),
+            _ => name.to_string(),
+        };
+        match self.files.iter().position(|f| *f == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path);
+                self.files.len() - 1
+            }
+        }
+    }
+
+    fn push_row(&mut self, regs: &Registers, files: &[Option<usize>], base_addr: u32) {
+        let Some(Some(file)) = files.get(regs.file as usize) else {
+            return;
+        };
+        if regs.line <= 0 {
+            return;
+        }
+        self.rows.push(LineRow {
+            addr: regs.addr.wrapping_add(base_addr),
+            file: *file,
+            line: regs.line as u32,
+        });
+    }
+
+    /// Rows in address order, including the end markers of sequences.
+    pub fn rows(&self) -> &[LineRow] {
+        &self.rows
+    }
+
+    /// Returns the source file and line of `addr`.
+    pub fn find(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = self.rows.partition_point(|row| row.addr <= addr);
+        let row = self.rows[..index].last()?;
+        if row.line == 0 {
+            return None;
+        }
+        Some((&self.files[row.file], row.line))
+    }
+}
+
+// Directory `dir` of a header before DWARF 5, 0 is the compilation directory
+fn dir_before_v5<'a>(dirs: &[&'a str], dir: usize) -> Option<&'a str> {
+    dir.checked_sub(1).and_then(|i| dirs.get(i)).copied()
+}
+
+// Reads the directory or file name entries of a DWARF 5 header as (path, directory index)
+fn read_entries<'a>(reader: &mut Reader<'a>, unit: &Unit<'a>) -> Result<Vec<(&'a str, usize)>> {
+    let format_count = reader.u8()?;
+    let mut formats = Vec::new();
+    for _ in 0..format_count {
+        formats.push((reader.uleb128()?, reader.uleb128()?));
+    }
+    let count = reader.uleb128()?;
+    // each entry takes a byte at least
+    if count > 0 && (formats.is_empty() || count > reader.data.len() as u64) {
+        bail!("invalid .debug_line entries");
+    }
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let (mut path, mut dir) = ("", 0);
+        for (content, form) in &formats {
+            match (*content, unit.resolve(read_value(reader, *form, unit)?)?) {
+                (DW_LNCT_PATH, Value::Str(s)) => path = s,
+                (DW_LNCT_DIRECTORY_INDEX, Value::Const(index)) => dir = index as usize,
+                _ => (),
+            }
+        }
+        entries.push((path, dir));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineTable;
+    use crate::elf::dwarf::Sections;
+
+    // .debug_line_str of the DWARF 5 header
+    const LINE_STR: &[u8] = b"dir\0";
+
+    // Line number program of
+    //   dir/a.c:10 at 0x100, :12 at 0x104, :11 at 0x10a, end at 0x110
+    fn create_section(version: u8) -> Vec<u8> {
+        let mut header = vec![0x00, version];
+        if version >= 5 {
+            // address size, segment selector size
+            header.extend([0x04, 0x00]);
+        }
+        let header_len_pos = header.len();
+        header.extend([0x00, 0x00, 0x00, 0x00]); // header length (patched)
+        header.push(0x02); // minimum instruction length
+        if version >= 4 {
+            header.push(0x01); // maximum operations per instruction
+        }
+        header.extend([
+            0x01, // default is_stmt
+            0xfb, // line base -5
+            0x0e, // line range 14
+            0x0a, // opcode base
+            0x00, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01,
+        ]);
+        if version >= 5 {
+            // directories: path by line_strp, the compilation directory
+            header.extend([0x01, 0x01, 0x1f, 0x01, 0x00, 0x00, 0x00, 0x00]);
+            // files: path by string and directory by udata, file 1 is a.c
+            header.extend([0x02, 0x01, 0x08, 0x02, 0x0f, 0x02]);
+            header.extend(b"x.c\0\x00a.c\0\x00");
+        } else {
+            header.extend(b"dir\0\0");
+            header.extend(b"a.c\0\x01\x00\x00\0");
+        }
+        let header_len = (header.len() - header_len_pos - 4) as u32;
+        header[header_len_pos..header_len_pos + 4].copy_from_slice(&header_len.to_be_bytes());
+
+        let program = [
+            0x00,
+            0x05,
+            0x02,
+            0x00,
+            0x00,
+            0x01,
+            0x00, // set_address 0x100
+            0x03,
+            0x09,              // advance_line 9
+            0x01,              // copy
+            0x0a + 7 + 2 * 14, // special: line +2, addr +2*2
+            0x03,
+            0x7f, // advance_line -1
+            0x02,
+            0x03, // advance_pc 3*2
+            0x01, // copy
+            0x02,
+            0x03, // advance_pc 3*2
+            0x00,
+            0x01,
+            0x01, // end_sequence
+        ];
+        header.extend(program);
+
+        let mut section = (header.len() as u32).to_be_bytes().to_vec();
+        section.extend(header);
+        section
+    }
+
+    #[test]
+    fn test_parse() {
+        let sections = Sections {
+            line_str: LINE_STR,
+            ..Default::default()
+        };
+        for version in [2, 4, 5] {
+            let table = LineTable::parse(&create_section(version), &sections, 0x1000).unwrap();
+            assert!(table.files.contains(&"dir/a.c".to_string()));
+            assert_eq!(table.find(0x10ff), None);
+            assert_eq!(table.find(0x1100), Some(("dir/a.c", 10)));
+            assert_eq!(table.find(0x1103), Some(("dir/a.c", 10)));
+            assert_eq!(table.find(0x1104), Some(("dir/a.c", 12)));
+            assert_eq!(table.find(0x110a), Some(("dir/a.c", 11)));
+            assert_eq!(table.find(0x1110), None);
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let section = create_section(2);
+        assert!(LineTable::parse(&section[..section.len() - 3], &Sections::default(), 0).is_err());
+
+        // units of unknown versions are skipped
+        let mut section = create_section(2);
+        section[5] = 6;
+        assert!(LineTable::parse(&section, &Sections::default(), 0).unwrap().rows().is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// Reasons an elf file can not be loaded.
+#[derive(Debug, PartialEq)]
+pub enum ElfError {
+    NotElf,
+    // a table or data referenced by the headers lies outside the file
+    Truncated(&'static str),
+    UnsupportedClass,
+    UnsupportedEndianness,
+    // (e_machine, e_flags)
+    UnsupportedMachine(u16, u32),
+    UnsupportedType(u16),
+    NoLoadableSegment,
+    // an address of the program moved by the base address passes the end of the address space
+    AddressOverflow(&'static str),
+    UnsupportedRelocation { ty: u8, offset: u32 },
+    // the relocated address does not fit in the field
+    RelocationOverflow { ty: u8, offset: u32 },
+    UndefinedSymbol(String),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "not an elf file"),
+            ElfError::Truncated(what) => write!(f, "{} is out of the file", what),
+            ElfError::UnsupportedClass => write!(f, "not a 32-bit elf"),
+            ElfError::UnsupportedEndianness => write!(f, "not a big-endian elf"),
+            ElfError::UnsupportedMachine(machine, flags) => {
+                write!(f, "not an H8/300H elf [e_machine: {}, e_flags: 0x{:x}]", machine, flags)
+            }
+            ElfError::UnsupportedType(ty) => write!(f, "not an executable elf [e_type: {}]", ty),
+            ElfError::NoLoadableSegment => write!(f, "no loadable segment"),
+            ElfError::AddressOverflow(what) => write!(f, "{} is out of the address space", what),
+            ElfError::UnsupportedRelocation { ty, offset } => {
+                write!(f, "unsupported relocation type [{}] at [0x{:x}]", ty, offset)
+            }
+            ElfError::RelocationOverflow { ty, offset } => {
+                write!(f, "relocation type [{}] at [0x{:x}] can not hold the relocated address", ty, offset)
+            }
+            ElfError::UndefinedSymbol(name) => write!(f, "relocation refers to the undefined symbol [{}]", name),
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
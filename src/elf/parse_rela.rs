@@ -0,0 +1,18 @@
+use crate::elf::rela::Rela32;
+use nom::{
+    multi::count,
+    number::complete::{be_i32, be_u32},
+    IResult, Parser,
+};
+
+pub fn parse_rela_table32<'a>(entries: usize) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Vec<Rela32>> {
+    move |raw: &'a [u8]| count(parse_rela32, entries).parse(raw)
+}
+
+fn parse_rela32(raw: &[u8]) -> IResult<&[u8], Rela32> {
+    let (r, offset) = be_u32(raw)?;
+    let (r, info) = be_u32(r)?;
+    let (r, addend) = be_i32(r)?;
+
+    Ok((r, Rela32 { offset, info, addend }))
+}
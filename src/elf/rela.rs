@@ -0,0 +1,16 @@
+#[derive(PartialEq, Eq, Debug)]
+pub struct Rela32 {
+    pub offset: u32,
+    pub info: u32,
+    pub addend: i32,
+}
+
+impl Rela32 {
+    pub fn symbol_idx(&self) -> u32 {
+        self.info >> 8
+    }
+
+    pub fn ty(&self) -> u8 {
+        self.info as u8
+    }
+}
@@ -0,0 +1,151 @@
+use crate::elf::{dwarf::LineTable, symtab::SymbolTableWithName32};
+
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xff00;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+}
+
+impl Symbol {
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.addr && addr - self.addr < self.size
+    }
+}
+
+/// Symbols of the loaded program, relocated to the addresses they are executed at.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    // sorted by address
+    entries: Vec<Symbol>,
+    pub lines: LineTable,
+    // functions of .debug_info, sorted by address
+    functions: Vec<Symbol>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols {
+            entries: Vec::new(),
+            lines: LineTable::new(),
+            functions: Vec::new(),
+        }
+    }
+
+    pub fn from_symtabs(symtabs: &[SymbolTableWithName32], base_addr: u32) -> Self {
+        let mut symbols = Symbols::new();
+        for s in symtabs {
+            let ty = s.symtab.info & 0xf;
+            if s.name.is_empty() || ty == STT_SECTION || ty == STT_FILE {
+                continue;
+            }
+            if s.symtab.shndx == SHN_UNDEF || s.symtab.shndx >= SHN_LORESERVE {
+                continue;
+            }
+            symbols.push(Symbol {
+                name: s.name.clone(),
+                addr: s.symtab.value + base_addr,
+                size: s.symtab.size,
+            });
+        }
+        symbols
+    }
+
+    pub fn push(&mut self, symbol: Symbol) {
+        let index = self.entries.partition_point(|s| s.addr <= symbol.addr);
+        self.entries.insert(index, symbol);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.entries.iter()
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&Symbol> {
+        self.entries.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the symbol that contains `addr`.
+    /// Symbols without size are treated as extending to the next symbol.
+    pub fn find_by_addr(&self, addr: u32) -> Option<&Symbol> {
+        let index = self.entries.partition_point(|s| s.addr <= addr);
+        let candidates = &self.entries[..index];
+        candidates.iter().rev().find(|s| s.size != 0 && s.contains(addr)).or_else(|| {
+            let last = candidates.last()?;
+            (last.size == 0).then_some(last)
+        })
+    }
+
+    pub fn set_debug_info(&mut self, lines: LineTable, mut functions: Vec<Symbol>) {
+        functions.sort_by_key(|f| f.addr);
+        self.lines = lines;
+        self.functions = functions;
+    }
+
+    /// Formats the source location of `addr` as `file.c:123 in function`.
+    pub fn format_location(&self, addr: u32) -> Option<String> {
+        let (file, line) = self.lines.find(addr)?;
+        let index = self.functions.partition_point(|f| f.addr <= addr);
+        let function = self.functions[..index]
+            .iter()
+            .rev()
+            .find(|f| f.contains(addr))
+            .or_else(|| self.find_by_addr(addr));
+        let file = file.rsplit(['/', '\\']).next().unwrap_or(file);
+        Some(match function {
+            Some(f) => format!("{}:{} in {}", file, line, f.name),
+            None => format!("{}:{}", file, line),
+        })
+    }
+
+    /// Formats `addr` as `name+0xoffset` if a symbol contains it.
+    pub fn format_addr(&self, addr: u32) -> String {
+        match self.find_by_addr(addr) {
+            Some(s) if s.addr == addr => s.name.clone(),
+            Some(s) => format!("{}+0x{:x}", s.name, addr - s.addr),
+            None => format!("0x{:x}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Symbol, Symbols};
+
+    fn create_symbols() -> Symbols {
+        let mut symbols = Symbols::new();
+        symbols.push(Symbol {
+            name: "_main".to_string(),
+            addr: 0x416918,
+            size: 0x20,
+        });
+        symbols.push(Symbol {
+            name: "___exit".to_string(),
+            addr: 0x41694a,
+            size: 0x26,
+        });
+        symbols
+    }
+
+    #[test]
+    fn test_find_by_addr() {
+        let symbols = create_symbols();
+        assert_eq!(symbols.find_by_addr(0x416918).unwrap().name, "_main");
+        assert_eq!(symbols.find_by_addr(0x416937).unwrap().name, "_main");
+        assert!(symbols.find_by_addr(0x416938).is_none());
+        assert_eq!(symbols.find_by_addr(0x41694c).unwrap().name, "___exit");
+        assert!(symbols.find_by_addr(0x416900).is_none());
+    }
+
+    #[test]
+    fn test_format_addr() {
+        let symbols = create_symbols();
+        assert_eq!(symbols.format_addr(0x416918), "_main");
+        assert_eq!(symbols.format_addr(0x41691c), "_main+0x4");
+        assert_eq!(symbols.format_addr(0x416900), "0x416900");
+    }
+}
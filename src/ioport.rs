@@ -62,17 +62,11 @@ impl Bus {
 #[cfg(test)]
 mod tests {
     use super::{IO_PORT_1_DDR_ADDR, IO_PORT_1_DR_ADDR};
-    use crate::{
-        bus::{Bus, IO_REGISTERS1_START_ADDR, IO_REGISTERS2_EMC1_START_ADDR},
-        modules::ModuleManager,
-    };
-    use std::{cell::RefCell, rc::Rc};
+    use crate::bus::{Bus, IO_REGISTERS1_START_ADDR, IO_REGISTERS2_EMC1_START_ADDR};
 
     #[test]
     fn test_write_port() {
-        let module_manager = Rc::new(RefCell::new(ModuleManager::new()));
-
-        let mut bus = Bus::new(Rc::downgrade(&module_manager));
+        let mut bus = Bus::new();
         bus.io_port_in[0] = 0;
         bus.io_registrs1[(IO_PORT_1_DDR_ADDR - IO_REGISTERS1_START_ADDR) as usize] = 0xf0;
         bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize] = 0;
@@ -80,7 +74,7 @@ mod tests {
         assert_eq!(bus.io_port_in[0], 0xff);
         assert_eq!(bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize], 0x0f);
 
-        let mut bus = Bus::new(Rc::downgrade(&module_manager));
+        let mut bus = Bus::new();
         bus.io_port_in[0] = 0xff;
         bus.io_registrs1[(IO_PORT_1_DDR_ADDR - IO_REGISTERS1_START_ADDR) as usize] = 0xf0;
         bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize] = 0xff;
@@ -91,9 +85,7 @@ mod tests {
 
     #[test]
     fn test_on_write_ddr() {
-        let module_manager = Rc::new(RefCell::new(ModuleManager::new()));
-
-        let mut bus = Bus::new(Rc::downgrade(&module_manager));
+        let mut bus = Bus::new();
         bus.io_port_in[0] = 0xff;
         bus.io_registrs1[(IO_PORT_1_DDR_ADDR - IO_REGISTERS1_START_ADDR) as usize] = 0xff;
         bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize] = 0;
@@ -102,7 +94,7 @@ mod tests {
         assert_eq!(bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize], 0x0f);
         assert_eq!(bus.io_registrs1[(IO_PORT_1_DDR_ADDR - IO_REGISTERS1_START_ADDR) as usize], 0xf0);
 
-        let mut bus = Bus::new(Rc::downgrade(&module_manager));
+        let mut bus = Bus::new();
         bus.io_port_in[4] = 0xff;
         bus.io_registrs1[(IO_PORT_1_DDR_ADDR + 4 - IO_REGISTERS1_START_ADDR) as usize] = 0xff;
         bus.io_registrs2[(IO_PORT_1_DR_ADDR + 4 - IO_REGISTERS2_EMC1_START_ADDR) as usize] = 0;
@@ -117,9 +109,7 @@ mod tests {
 
     #[test]
     fn test_on_write_dr() {
-        let module_manager = Rc::new(RefCell::new(ModuleManager::new()));
-
-        let mut bus = Bus::new(Rc::downgrade(&module_manager));
+        let mut bus = Bus::new();
         bus.io_port_in[0] = 0;
         bus.io_registrs1[(IO_PORT_1_DDR_ADDR - IO_REGISTERS1_START_ADDR) as usize] = 0x0f;
         bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize] = 0;
@@ -127,7 +117,7 @@ mod tests {
         assert_eq!(bus.io_port_in[0], 0);
         assert_eq!(bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize], 0x0f);
 
-        let mut bus = Bus::new(Rc::downgrade(&module_manager));
+        let mut bus = Bus::new();
         bus.io_port_in[0] = 0xf0;
         bus.io_registrs1[(IO_PORT_1_DDR_ADDR - IO_REGISTERS1_START_ADDR) as usize] = 0x0f;
         bus.io_registrs2[(IO_PORT_1_DR_ADDR - IO_REGISTERS2_EMC1_START_ADDR) as usize] = 0;
@@ -0,0 +1,24 @@
+//! Emulator of the H8/3069F microcontroller. The `Cpu` runs programs loaded by `elf` or `loader`,
+//! and tests can call functions of a loaded program with `Cpu::call_function`.
+
+mod bus;
+mod bus_controller;
+pub mod check;
+pub mod config;
+pub mod coverage;
+pub mod cpu;
+pub mod debugger;
+pub mod elf;
+mod ioport;
+pub mod loader;
+mod memcheck;
+pub mod memory;
+pub mod mes2;
+mod modules;
+pub mod profiler;
+mod registers;
+mod socket;
+pub mod stack_usage;
+pub mod test_report;
+pub mod test_runner;
+pub mod trace;
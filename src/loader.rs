@@ -0,0 +1,185 @@
+use crate::{
+    bus::{ROM_END_ADDR, VENCTOR_START_ADDR},
+    cpu::Cpu,
+    elf::{self, Segment},
+};
+use anyhow::{bail, Context as _, Result};
+
+mod ihex;
+mod srec;
+
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Elf,
+    SRecord,
+    IntelHex,
+    Binary,
+}
+
+pub struct LoadOptions {
+    // address of raw binaries
+    pub load_addr: u32,
+    pub entry: Option<u32>,
+}
+
+/// Memory image of the S-record, Intel HEX and raw binary formats.
+#[derive(Debug, Default)]
+pub struct Image {
+    // (address, data), contiguous data are merged
+    pub chunks: Vec<(u32, Vec<u8>)>,
+    pub entry: Option<u32>,
+}
+
+impl Image {
+    pub fn new() -> Self {
+        Image {
+            chunks: Vec::new(),
+            entry: None,
+        }
+    }
+
+    pub fn push(&mut self, addr: u32, data: &[u8]) {
+        if let Some((last_addr, last_data)) = self.chunks.last_mut() {
+            if last_addr.checked_add(last_data.len() as u32) == Some(addr) {
+                last_data.extend_from_slice(data);
+                return;
+            }
+        }
+        self.chunks.push((addr, data.to_vec()));
+    }
+}
+
+fn parse_hex_bytes(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        bail!("invalid hex string");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).context("invalid hex string"))
+        .collect()
+}
+
+pub fn detect(raw: &[u8]) -> Format {
+    if raw.starts_with(b"\x7fELF") {
+        return Format::Elf;
+    }
+    let Ok(text) = std::str::from_utf8(raw) else {
+        return Format::Binary;
+    };
+    let first_line = text.lines().map(|l| l.trim()).find(|l| !l.is_empty()).unwrap_or("");
+    let is_hex = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    match first_line.as_bytes() {
+        [b'S', ty, ..] if ty.is_ascii_digit() && is_hex(&first_line[2..]) => Format::SRecord,
+        [b':', ..] if is_hex(&first_line[1..]) => Format::IntelHex,
+        _ => Format::Binary,
+    }
+}
+
+/// Loads the program, detecting the format from the content.
+pub fn load(path: &str, cpu: &mut Cpu, args: String, options: &LoadOptions) -> Result<()> {
+    let raw = std::fs::read(path).with_context(|| format!("failed to read [{}]", path))?;
+    let format = detect(&raw);
+    log::info!("Load [{}] as {:?}", path, format);
+
+    let image = match format {
+        Format::Elf => {
+            elf::load(path, cpu, args)?;
+            if let Some(entry) = options.entry {
+                cpu.er[2] = entry;
+            }
+            return Ok(());
+        }
+        Format::SRecord => srec::parse(&String::from_utf8_lossy(&raw))?,
+        Format::IntelHex => ihex::parse(&String::from_utf8_lossy(&raw))?,
+        Format::Binary => Image {
+            chunks: vec![(options.load_addr, raw)],
+            entry: None,
+        },
+    };
+    load_image(cpu, &image, &args, options)
+}
+
+// The images are absolutely linked, and start like the absolutely linked elf files
+fn load_image(cpu: &mut Cpu, image: &Image, args: &str, options: &LoadOptions) -> Result<()> {
+    for (addr, data) in &image.chunks {
+        cpu.bus.load(*addr, data)?;
+        let end = addr + data.len() as u32;
+        cpu.segments.push(Segment {
+            range: *addr..end,
+            is_executable: true,
+            is_writable: *addr > ROM_END_ADDR,
+        });
+        log::trace!("Loaded [0x{:x}..0x{:x}]", addr, end);
+    }
+
+    // The reset vector is used when neither the option nor the image gives the entry point
+    let reset_vector = u32::from_be_bytes([0, 1, 2, 3].map(|i| cpu.bus.peek(VENCTOR_START_ADDR + i).unwrap_or(0)));
+    let entry = match (options.entry, image.entry) {
+        (Some(entry), _) | (None, Some(entry)) => entry,
+        _ if reset_vector != 0 => reset_vector,
+        _ => bail!("entry point is unknown, specify it with --entry"),
+    };
+    cpu.er[2] = entry;
+    log::trace!("Set entry point [0x{:x}]", entry);
+    elf::set_absolute_stack(cpu, args);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, load_image, Format, Image, LoadOptions};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"\x7fELF\x01\x02"), Format::Elf);
+        assert_eq!(detect(b"S00600004844521B\r\nS9030000FC\r\n"), Format::SRecord);
+        assert_eq!(detect(b"\n:00000001FF\n"), Format::IntelHex);
+        assert_eq!(detect(b"\x5e\x00\x01\x00"), Format::Binary);
+        assert_eq!(detect(b"Start"), Format::Binary);
+    }
+
+    #[test]
+    fn test_image_push() {
+        let mut image = Image::new();
+        image.push(0x100, &[1, 2]);
+        image.push(0x102, &[3]);
+        image.push(0x200, &[4]);
+        assert_eq!(image.chunks, vec![(0x100, vec![1, 2, 3]), (0x200, vec![4])]);
+    }
+
+    #[test]
+    fn test_load_image() {
+        let mut cpu = Cpu::new();
+        let mut image = Image::new();
+        image.push(0x000000, &[0x00, 0x00, 0x01, 0x00]);
+        image.push(0x000100, &[0x01, 0x00]);
+        image.push(0x400000, &[0xff]);
+        let options = LoadOptions { load_addr: 0, entry: None };
+        load_image(&mut cpu, &image, "a b", &options).unwrap();
+        assert_eq!(cpu.er[2], 0x100);
+        assert_eq!(cpu.er[7], 0xffff20);
+        assert_eq!(cpu.bus.peek(0x100).unwrap(), 0x01);
+        assert_eq!(cpu.bus.peek(0x400000).unwrap(), 0xff);
+        assert!(!cpu.segments[1].is_writable);
+        assert!(cpu.segments[2].is_writable);
+
+        let options = LoadOptions {
+            load_addr: 0,
+            entry: Some(0x400000),
+        };
+        load_image(&mut cpu, &image, "", &options).unwrap();
+        assert_eq!(cpu.er[2], 0x400000);
+
+        let mut cpu = Cpu::new();
+        let options = LoadOptions { load_addr: 0, entry: None };
+        assert!(load_image(&mut cpu, &Image::new(), "", &options).is_err());
+
+        // a record at the end of the address space
+        let mut image = Image::new();
+        image.push(0xffffffff, &[1, 2]);
+        image.push(0x000001, &[3]);
+        assert_eq!(image.chunks.len(), 2);
+        assert!(load_image(&mut cpu, &image, "", &options).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use super::{parse_hex_bytes, Image};
+use anyhow::{bail, Context as _, Result};
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+const RECORD_EXTENDED_SEGMENT_ADDR: u8 = 0x02;
+const RECORD_START_SEGMENT_ADDR: u8 = 0x03;
+const RECORD_EXTENDED_LINEAR_ADDR: u8 = 0x04;
+const RECORD_START_LINEAR_ADDR: u8 = 0x05;
+
+/// Parses an Intel HEX file.
+pub fn parse(raw: &str) -> Result<Image> {
+    let mut image = Image::new();
+    let mut base = 0u32;
+    for (i, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let is_eof = parse_record(line, &mut base, &mut image).with_context(|| format!("Intel HEX line {}", i + 1))?;
+        if is_eof {
+            break;
+        }
+    }
+    Ok(image)
+}
+
+// Returns true at the end of file record
+fn parse_record(line: &str, base: &mut u32, image: &mut Image) -> Result<bool> {
+    let Some(record) = line.strip_prefix(':') else {
+        bail!("record does not start with ':'");
+    };
+    let bytes = parse_hex_bytes(record)?;
+    if bytes.len() < 5 || bytes[0] as usize != bytes.len() - 5 {
+        bail!("invalid byte count");
+    }
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        bail!("checksum mismatch");
+    }
+    let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+    let ty = bytes[3];
+    let data = &bytes[4..bytes.len() - 1];
+    let value = || data.iter().fold(0u32, |value, b| (value << 8) | *b as u32);
+
+    match ty {
+        RECORD_DATA => image.push(base.wrapping_add(offset), data),
+        RECORD_EOF => return Ok(true),
+        RECORD_EXTENDED_SEGMENT_ADDR => *base = value() << 4,
+        RECORD_START_SEGMENT_ADDR => {
+            let value = value();
+            image.entry = Some(((value >> 16) << 4) + (value & 0xffff));
+        }
+        RECORD_EXTENDED_LINEAR_ADDR => *base = value() << 16,
+        RECORD_START_LINEAR_ADDR => image.entry = Some(value()),
+        _ => bail!("unknown record type [{:02x}]", ty),
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn test_parse() {
+        let image = parse(
+            ":020000040040BA\n\
+             :0400000001020304F2\n\
+             :020004000506EF\n\
+             :0400000500400000B7\n\
+             :00000001FF\n",
+        )
+        .unwrap();
+        assert_eq!(image.chunks, vec![(0x400000, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06])]);
+        assert_eq!(image.entry, Some(0x400000));
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert!(parse(":0400000001020304F3\n").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use super::{parse_hex_bytes, Image};
+use anyhow::{bail, Context as _, Result};
+
+/// Parses a Motorola S-record file.
+pub fn parse(raw: &str) -> Result<Image> {
+    let mut image = Image::new();
+    for (i, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        parse_record(line, &mut image).with_context(|| format!("S-record line {}", i + 1))?;
+    }
+    Ok(image)
+}
+
+fn parse_record(line: &str, image: &mut Image) -> Result<()> {
+    if !line.is_ascii() {
+        bail!("record is not ASCII");
+    }
+    let Some(record) = line.strip_prefix('S') else {
+        bail!("record does not start with 'S'");
+    };
+    let Some(ty) = record.chars().next() else {
+        bail!("missing record type");
+    };
+    let bytes = parse_hex_bytes(&record[1..])?;
+    if bytes.is_empty() || bytes[0] as usize != bytes.len() - 1 {
+        bail!("invalid byte count");
+    }
+    let sum = bytes[..bytes.len() - 1].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if !sum != bytes[bytes.len() - 1] {
+        bail!("checksum mismatch");
+    }
+    let body = &bytes[1..bytes.len() - 1];
+
+    let addr_len = match ty {
+        '0' | '1' | '5' | '9' => 2,
+        '2' | '6' | '8' => 3,
+        '3' | '7' => 4,
+        _ => bail!("unknown record type [S{}]", ty),
+    };
+    if body.len() < addr_len {
+        bail!("record too short");
+    }
+    let addr = body[..addr_len].iter().fold(0u32, |addr, b| (addr << 8) | *b as u32);
+    let data = &body[addr_len..];
+
+    match ty {
+        '1' | '2' | '3' => image.push(addr, data),
+        '7' | '8' | '9' => image.entry = Some(addr),
+        // header and record counts
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn test_parse() {
+        let image = parse(
+            "S00600004844521B\n\
+             S2080001000102030AE6\n\
+             S2060001040405EB\n\
+             S5030002FA\n\
+             S804000100FA\n",
+        )
+        .unwrap();
+        assert_eq!(image.chunks, vec![(0x100, vec![0x01, 0x02, 0x03, 0x0a, 0x04, 0x05])]);
+        assert_eq!(image.entry, Some(0x100));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let err = parse("S00600004844521B\nS2080001000102030AE7\n").unwrap_err();
+        assert_eq!(format!("{:#}", err), "S-record line 2: checksum mismatch");
+        assert!(parse("Sé0600004844521B\n").is_err());
+    }
+}
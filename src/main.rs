@@ -1,32 +1,49 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-mod bus;
-mod cpu;
-mod elf;
-mod ioport;
-mod memory;
-mod modules;
-mod registers;
-mod setting;
-mod socket;
-
-use clap::Parser;
+use anyhow::Result;
+use clap::{Parser, Subcommand};
 use log::error;
+use std::{path::PathBuf, rc::Rc};
 
-use crate::cpu::Cpu;
+use koge29_h8_3069f_emulator::{
+    check,
+    config::EmulatorConfig,
+    coverage::Coverage,
+    cpu::Cpu,
+    debugger::Debugger,
+    elf,
+    loader::{self, LoadOptions},
+    mes2::{GuestStdin, StdinSource},
+    profiler::Profiler,
+    stack_usage::StackUsage,
+    test_report::TestReport,
+    test_runner,
+    trace::{self, dump::DumpFilter, Tracer},
+};
 
 #[derive(Parser)]
-#[command(version)]
+#[command(version, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// path of the elf file to execute
-    #[arg(short, long)]
-    elf: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// path of the program to execute (elf, S-record, Intel HEX or raw binary)
+    #[arg(short, long, required = true)]
+    elf: Option<String>,
+
+    /// Address to load raw binaries at (hex)
+    #[arg(long, value_parser = parse_hex, default_value = "0")]
+    load_addr: u32,
+
+    /// Entry point (hex), used when the program does not have one
+    #[arg(long, value_parser = parse_hex)]
+    entry: Option<u32>,
 
     #[arg(short, long, default_value = "")]
     args: String,
 
     /// log level (error, warn, info, debug, trace, off)
-    #[arg(long, default_value = "info")]
+    #[arg(long, default_value = "info", global = true)]
     log: String,
 
     /// Print executed opcode
@@ -43,12 +60,121 @@ struct Args {
     #[arg(short = 'w', long)]
     wait_start: bool,
 
+    /// CPU clock in MHz (e.g. 20 or 25)
+    #[arg(long, default_value = "20")]
+    clock: String,
+
+    /// Real-time speed multiplier (e.g. 0.1, 1 or 10), or unlimited
+    #[arg(long, default_value = "1")]
+    speed: String,
+
+    /// Run every iteration of the idle loops instead of fast-forwarding them
+    #[arg(long)]
+    no_idle_skip: bool,
+
+    /// Report accesses to the DRAM before its refresh is configured
+    #[arg(long)]
+    refresh_check: bool,
+
     /// The address the socket listens on
     #[arg(short = 'l', long, default_value = "127.0.0.1")]
     listen_address: String,
 
     #[arg(short, long, default_value = "12345")]
     port: u16,
+
+    /// Start the interactive debugger
+    #[arg(short, long)]
+    debug: bool,
+
+    /// Record an execution trace to the file
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// Keep only the last N instructions of the trace, written at exit or on error
+    #[arg(long, requires = "trace")]
+    trace_ring: Option<usize>,
+
+    /// Write a per-function profile to the file and collapsed stacks to <file>.folded
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Write lcov coverage to the file, merged with the coverage already in it
+    #[arg(long)]
+    coverage: Option<String>,
+
+    /// Report uninitialized reads, writes to read-only segments and stack overflows
+    #[arg(long)]
+    memcheck: bool,
+
+    /// Print the maximum stack depth of each function and interrupt handler at exit
+    #[arg(long)]
+    stack_usage: bool,
+
+    /// Write the results of the test control calls to the file as JSON
+    #[arg(long)]
+    test_summary: Option<String>,
+
+    /// File read by the program as stdin, instead of the stdin of the emulator or `stdin:` socket messages
+    #[arg(long)]
+    stdin: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execution trace tools
+    Trace {
+        #[command(subcommand)]
+        command: TraceCommand,
+    },
+    /// Run programs with expectation files (<name>.expect, <name>.stdout) and compare the results
+    Test {
+        /// elf files or directories to search for them
+        #[arg(default_value = "example")]
+        paths: Vec<PathBuf>,
+
+        /// Number of programs run in parallel (default: number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Write the results to the file as JUnit XML
+        #[arg(long)]
+        junit: Option<String>,
+    },
+    /// List the instructions of the program the emulator does not implement, without running it
+    Check {
+        /// path of the elf file
+        elf: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TraceCommand {
+    /// Decode a trace file
+    Dump {
+        /// path of the trace file
+        file: String,
+
+        /// elf file to read symbols from
+        #[arg(short, long)]
+        elf: Option<String>,
+
+        /// Only show instructions at or after this address (hex)
+        #[arg(long, value_parser = parse_hex)]
+        from: Option<u32>,
+
+        /// Only show instructions before this address (hex)
+        #[arg(long, value_parser = parse_hex)]
+        to: Option<u32>,
+
+        /// Only show instructions in this function
+        #[arg(short, long)]
+        function: Option<String>,
+    },
+}
+
+fn parse_hex(s: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16)
 }
 
 fn main() {
@@ -56,20 +182,124 @@ fn main() {
 
     init_logger(args.log);
 
-    *setting::ENABLE_PRINT_OPCODE.write().unwrap() = args.print_instruction;
-    *setting::ENABLE_PRINT_MESSAGES.write().unwrap() = args.print_messages;
-    *setting::ENABLE_WAIT_START.write().unwrap() = args.socket && args.wait_start;
+    if let Some(command) = args.command {
+        if let Err(e) = run_command(command) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let mut cpu = Cpu::new();
+    let mut config = EmulatorConfig {
+        print_instruction: args.print_instruction,
+        print_messages: args.print_messages,
+        wait_start: args.socket && args.wait_start,
+        idle_skip: !args.no_idle_skip,
+        refresh_check: args.refresh_check,
+        ..Default::default()
+    };
+    if let Err(e) = config.set("clock", &args.clock).and_then(|_| config.set("speed", &args.speed)) {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+    cpu.set_config(config);
 
     #[cfg(not(test))]
     if args.socket {
         cpu.connect_socket(&format!("{}:{}", args.listen_address, args.port)).unwrap();
     }
 
-    elf::load(args.elf, &mut cpu, args.args);
+    if args.memcheck {
+        cpu.enable_memcheck();
+    }
+
+    cpu.mes2.stdin = match args.stdin {
+        Some(path) => match std::fs::File::open(&path) {
+            Ok(file) => GuestStdin::new(StdinSource::File(Rc::new(file))),
+            Err(e) => {
+                error!("failed to open [{}]: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None if args.socket => GuestStdin::new(StdinSource::Socket),
+        None => GuestStdin::new(StdinSource::Host),
+    };
+
+    let elf_path = args.elf.unwrap();
+    let load_options = LoadOptions {
+        load_addr: args.load_addr,
+        entry: args.entry,
+    };
+    if let Err(e) = loader::load(&elf_path, &mut cpu, args.args, &load_options) {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(path) = args.trace {
+        match Tracer::new(&path, args.trace_ring) {
+            Ok(tracer) => cpu.set_tracer(tracer),
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = args.profile {
+        cpu.profiler = Some(Profiler::new(&path, cpu.er[2]));
+    }
+
+    if args.stack_usage {
+        cpu.stack_usage = Some(StackUsage::new(cpu.er[2], cpu.er[7]));
+    }
+
+    if let Some(path) = args.coverage {
+        cpu.coverage = Some(Coverage::new(&path, &elf_path, &cpu.segments));
+    }
+
+    cpu.test_report = TestReport::new(args.test_summary);
+
+    if args.debug {
+        cpu.debugger = Some(Debugger::new());
+    }
 
     cpu.run().unwrap();
+
+    let exit_code = cpu.test_report.process_exit_code();
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+fn run_command(command: Command) -> Result<()> {
+    match command {
+        Command::Trace {
+            command:
+                TraceCommand::Dump {
+                    file,
+                    elf,
+                    from,
+                    to,
+                    function,
+                },
+        } => {
+            let symbols = match elf {
+                Some(path) => elf::load_symbols(&path)?,
+                None => elf::Symbols::new(),
+            };
+            let range = match (from, to) {
+                (None, None) => None,
+                (from, to) => Some(from.unwrap_or(0)..to.unwrap_or(u32::MAX)),
+            };
+            trace::dump::dump(&file, &symbols, &DumpFilter { range, function })
+        }
+        Command::Test { paths, jobs, junit } => {
+            let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            test_runner::run(&paths, jobs, junit.as_deref())
+        }
+        Command::Check { elf } => check::run(&elf),
+    }
 }
 
 fn _init_logger(level: &str) {
@@ -0,0 +1,272 @@
+use crate::{
+    bus::{AREA2_END_ADDR, AREA2_SIZE, AREA2_START_ADDR},
+    memory::{MEMORY_END_ADDR, MEMORY_SIZE, MEMORY_START_ADDR},
+};
+use std::{collections::HashSet, ops::Range};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ViolationKind {
+    UninitializedRead,
+    ReadOnlyWrite,
+    StackOverflow,
+}
+
+impl ViolationKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            ViolationKind::UninitializedRead => "read of uninitialized memory",
+            ViolationKind::ReadOnlyWrite => "write to read-only segment",
+            ViolationKind::StackOverflow => "stack overflow",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub addr: u32,
+    pub len: u32,
+}
+
+// One bit per byte
+#[derive(Clone)]
+struct Shadow {
+    bits: Vec<u64>,
+}
+
+impl Shadow {
+    fn new(size: usize) -> Self {
+        Shadow {
+            bits: vec![0; size.div_ceil(64)],
+        }
+    }
+
+    fn set(&mut self, index: usize) {
+        self.bits[index / 64] |= 1 << (index % 64);
+    }
+
+    fn get(&self, index: usize) -> bool {
+        self.bits[index / 64] & (1 << (index % 64)) != 0
+    }
+}
+
+/// Opt-in checker of memory accesses.
+/// Tracks initialized bytes of DRAM and on-chip memory, and protects the read-only segments of the program.
+#[derive(Clone)]
+pub struct MemCheck {
+    dram: Shadow,
+    memory: Shadow,
+    read_only: Vec<Range<u32>>,
+    pub stack: Range<u32>,
+    // violations of the current instruction
+    violations: Vec<Violation>,
+    // (pc, kind) already reported
+    reported: HashSet<(u32, ViolationKind)>,
+    counts: [u64; 3],
+    is_overflowed: bool,
+}
+
+impl MemCheck {
+    pub fn new() -> Self {
+        MemCheck {
+            dram: Shadow::new(AREA2_SIZE),
+            memory: Shadow::new(MEMORY_SIZE),
+            read_only: Vec::new(),
+            stack: 0..0,
+            violations: Vec::new(),
+            reported: HashSet::new(),
+            counts: [0; 3],
+            is_overflowed: false,
+        }
+    }
+
+    /// Marks bytes written by the loader as initialized.
+    pub fn mark_initialized(&mut self, range: Range<u32>) {
+        for addr in range {
+            self.mark(addr);
+        }
+    }
+
+    pub fn protect(&mut self, range: Range<u32>) {
+        self.read_only.push(range);
+    }
+
+    fn mark(&mut self, addr: u32) {
+        match addr {
+            AREA2_START_ADDR..=AREA2_END_ADDR => self.dram.set((addr - AREA2_START_ADDR) as usize),
+            MEMORY_START_ADDR..=MEMORY_END_ADDR => self.memory.set((addr - MEMORY_START_ADDR) as usize),
+            _ => (),
+        }
+    }
+
+    fn is_initialized(&self, addr: u32) -> bool {
+        match addr {
+            AREA2_START_ADDR..=AREA2_END_ADDR => self.dram.get((addr - AREA2_START_ADDR) as usize),
+            MEMORY_START_ADDR..=MEMORY_END_ADDR => self.memory.get((addr - MEMORY_START_ADDR) as usize),
+            // I/O registers and vectors are not tracked
+            _ => true,
+        }
+    }
+
+    pub fn on_read(&mut self, addr: u32) {
+        if !self.is_initialized(addr) {
+            self.push(ViolationKind::UninitializedRead, addr);
+        }
+    }
+
+    pub fn on_write(&mut self, addr: u32) {
+        if self.read_only.iter().any(|range| range.contains(&addr)) {
+            self.push(ViolationKind::ReadOnlyWrite, addr);
+        }
+        self.mark(addr);
+    }
+
+    /// Called after each instruction with the stack pointer.
+    pub fn check_sp(&mut self, sp: u32) {
+        let is_overflowed = !self.stack.is_empty() && sp < self.stack.start;
+        // Report only when the stack pointer leaves the stack
+        if is_overflowed && !self.is_overflowed {
+            self.violations.push(Violation {
+                kind: ViolationKind::StackOverflow,
+                addr: sp,
+                len: self.stack.start - sp,
+            });
+        }
+        self.is_overflowed = is_overflowed;
+    }
+
+    // Contiguous bytes of an access are merged into one violation
+    fn push(&mut self, kind: ViolationKind, addr: u32) {
+        if let Some(last) = self.violations.last_mut() {
+            if last.kind == kind && last.addr + last.len == addr {
+                last.len += 1;
+                return;
+            }
+        }
+        self.violations.push(Violation { kind, addr, len: 1 });
+    }
+
+    /// Returns the violations of the instruction at `pc` which are not reported yet.
+    pub fn take_violations(&mut self, pc: u32) -> Vec<Violation> {
+        let mut violations = std::mem::take(&mut self.violations);
+        for v in &violations {
+            self.counts[v.kind as usize] += 1;
+        }
+        violations.retain(|v| !self.reported.contains(&(pc, v.kind)));
+        for v in &violations {
+            self.reported.insert((pc, v.kind));
+        }
+        violations
+    }
+
+    pub fn summary(&self) -> String {
+        [
+            ViolationKind::UninitializedRead,
+            ViolationKind::ReadOnlyWrite,
+            ViolationKind::StackOverflow,
+        ]
+        .iter()
+        .map(|kind| format!("{}: {}", kind.describe(), self.counts[*kind as usize]))
+        .collect::<Vec<String>>()
+        .join(", ")
+    }
+}
+
+pub fn format_violation(violation: &Violation) -> String {
+    match violation.kind {
+        ViolationKind::StackOverflow => format!(
+            "{} [sp: 0x{:x}, {} bytes below the stack]",
+            violation.kind.describe(),
+            violation.addr,
+            violation.len
+        ),
+        _ => format!(
+            "{} [addr: 0x{:x}, {} bytes]",
+            violation.kind.describe(),
+            violation.addr,
+            violation.len
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemCheck, Violation, ViolationKind};
+
+    const DRAM: u32 = 0x400000;
+    const MEMORY: u32 = 0xffbf20;
+
+    #[test]
+    fn test_uninitialized_read() {
+        let mut memcheck = MemCheck::new();
+        memcheck.mark_initialized(DRAM..DRAM + 2);
+        memcheck.on_write(MEMORY);
+
+        memcheck.on_read(DRAM + 1);
+        memcheck.on_read(MEMORY);
+        assert!(memcheck.take_violations(0x1000).is_empty());
+
+        memcheck.on_read(DRAM + 2);
+        memcheck.on_read(DRAM + 3);
+        memcheck.on_read(MEMORY + 1);
+        // I/O registers are not tracked
+        memcheck.on_read(0xfee000);
+        assert_eq!(
+            memcheck.take_violations(0x1000),
+            vec![
+                Violation {
+                    kind: ViolationKind::UninitializedRead,
+                    addr: DRAM + 2,
+                    len: 2,
+                },
+                Violation {
+                    kind: ViolationKind::UninitializedRead,
+                    addr: MEMORY + 1,
+                    len: 1,
+                },
+            ]
+        );
+
+        // Reported once per pc
+        memcheck.on_read(DRAM + 4);
+        assert!(memcheck.take_violations(0x1000).is_empty());
+        memcheck.on_read(DRAM + 4);
+        assert_eq!(memcheck.take_violations(0x1002).len(), 1);
+        assert_eq!(memcheck.counts[ViolationKind::UninitializedRead as usize], 4);
+    }
+
+    #[test]
+    fn test_read_only_write() {
+        let mut memcheck = MemCheck::new();
+        memcheck.protect(DRAM..DRAM + 0x100);
+        memcheck.on_write(DRAM + 0x100);
+        assert!(memcheck.take_violations(0x1000).is_empty());
+        memcheck.on_write(DRAM + 0xff);
+        assert_eq!(
+            memcheck.take_violations(0x1000),
+            vec![Violation {
+                kind: ViolationKind::ReadOnlyWrite,
+                addr: DRAM + 0xff,
+                len: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut memcheck = MemCheck::new();
+        memcheck.stack = 0x417000..0x417400;
+        memcheck.check_sp(0x417000);
+        assert!(memcheck.take_violations(0x1000).is_empty());
+        memcheck.check_sp(0x416ffc);
+        memcheck.check_sp(0x416ff8);
+        assert_eq!(
+            memcheck.take_violations(0x1002),
+            vec![Violation {
+                kind: ViolationKind::StackOverflow,
+                addr: 0x416ffc,
+                len: 4,
+            }]
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::{bail, Result};
+
+mod stdin;
+
+pub use stdin::{GuestStdin, StdinSource};
+
+// Bytes a read returns at most, the length is given by the guest. Callers see a short read as with read(2).
+const MAX_READ_LEN: usize = 0x10000;
+
+/// Host side state of the MES2 system calls: the console input.
+#[derive(Clone)]
+pub struct Mes2 {
+    pub stdin: GuestStdin,
+}
+
+impl Default for Mes2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mes2 {
+    pub fn new() -> Self {
+        Mes2 {
+            stdin: GuestStdin::new(StdinSource::Host),
+        }
+    }
+
+    /// Reads up to `len` bytes of the console, or returns None if the read of stdin has to wait.
+    pub fn read(&mut self, fd: u32, len: usize) -> Result<Option<Vec<u8>>> {
+        match fd {
+            0 => self.stdin.read(len),
+            _ => bail!("fd {} is not readable", fd),
+        }
+    }
+}
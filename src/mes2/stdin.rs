@@ -0,0 +1,85 @@
+use super::MAX_READ_LEN;
+use anyhow::Result;
+use std::{collections::VecDeque, fs::File, io::Read, rc::Rc};
+
+#[derive(Clone)]
+pub enum StdinSource {
+    // stdin of the emulator
+    Host,
+    File(Rc<File>),
+    // `stdin:` messages of the socket
+    Socket,
+    // always at the end of file
+    Empty,
+}
+
+/// Input of fd 0 of the program.
+#[derive(Clone)]
+pub struct GuestStdin {
+    source: StdinSource,
+    buffer: VecDeque<u8>,
+    // a read is blocked until data arrives from the socket
+    is_waiting: bool,
+}
+
+impl GuestStdin {
+    pub fn new(source: StdinSource) -> Self {
+        GuestStdin {
+            source,
+            buffer: VecDeque::new(),
+            is_waiting: false,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend(data);
+        self.is_waiting = false;
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.is_waiting
+    }
+
+    /// Reads up to `len` bytes, or returns None if the read has to wait for the socket.
+    /// Reading the host stdin blocks the emulator, so the guest time stops while waiting in both cases.
+    pub fn read(&mut self, len: usize) -> Result<Option<Vec<u8>>> {
+        let len = len.min(MAX_READ_LEN);
+        let mut buf = vec![0; len];
+        let read_len = match &self.source {
+            StdinSource::Host => std::io::stdin().lock().read(&mut buf)?,
+            StdinSource::File(file) => (&**file).read(&mut buf)?,
+            StdinSource::Empty => 0,
+            StdinSource::Socket => {
+                if self.buffer.is_empty() && len > 0 {
+                    self.is_waiting = true;
+                    return Ok(None);
+                }
+                let read_len = len.min(self.buffer.len());
+                for (dest, value) in buf.iter_mut().zip(self.buffer.drain(..read_len)) {
+                    *dest = value;
+                }
+                read_len
+            }
+        };
+        buf.truncate(read_len);
+        Ok(Some(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuestStdin, StdinSource};
+
+    #[test]
+    fn test_socket() {
+        let mut stdin = GuestStdin::new(StdinSource::Socket);
+        assert_eq!(stdin.read(4).unwrap(), None);
+        assert!(stdin.is_waiting());
+
+        stdin.push(b"12 34\n");
+        assert!(!stdin.is_waiting());
+        assert_eq!(stdin.read(4).unwrap(), Some(b"12 3".to_vec()));
+        assert_eq!(stdin.read(4).unwrap(), Some(b"4\n".to_vec()));
+        assert_eq!(stdin.read(4).unwrap(), None);
+    }
+}
@@ -1,39 +1,187 @@
-use anyhow::Result;
-use timer8::{Timer8_0, TCR0_8};
+use refresh::RefreshController;
+use std::ops::RangeInclusive;
+use timer8::Timer8_0;
 
-use crate::{bus::Bus, cpu::interrupt_controller::InterruptController};
+use crate::cpu::interrupt_controller::InterruptController;
 
+pub mod refresh;
 mod timer8;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ModuleId {
+    Timer8_0,
+    Refresh,
+}
+
+#[derive(Clone)]
 struct Modules {
     timer8_0: Timer8_0,
+    refresh: RefreshController,
 }
 
 impl Modules {
     pub fn new() -> Self {
-        Modules { timer8_0: Timer8_0::new() }
+        Modules {
+            timer8_0: Timer8_0::new(),
+            refresh: RefreshController::new(),
+        }
     }
 }
 
+/// Peripherals are not updated after every instruction. Each one reports the state count of its next event,
+/// the cpu updates them when it reaches the earliest one, and register accesses count them up to the access.
+///
+/// Each module claims the address ranges of its registers in the register map,
+/// and the bus passes the reads and writes of them to the module instead of its memory.
+#[derive(Clone)]
 pub struct ModuleManager {
     modules: Modules,
+    register_map: Vec<(RangeInclusive<u32>, ModuleId)>,
+    // interrupts requested (true) or withdrawn by clearing the flag (false) since the last update, passed on at the next update
+    pending_interrupts: Vec<(u8, bool)>,
+    // state count of the earliest event of the modules
+    next_event: usize,
 }
 
 impl ModuleManager {
     pub fn new() -> Self {
-        ModuleManager { modules: Modules::new() }
+        let mut register_map = Vec::new();
+        for range in Timer8_0::registers() {
+            register_map.push((range, ModuleId::Timer8_0));
+        }
+        for range in RefreshController::registers() {
+            register_map.push((range, ModuleId::Refresh));
+        }
+        ModuleManager {
+            modules: Modules::new(),
+            register_map,
+            pending_interrupts: Vec::new(),
+            next_event: usize::MAX,
+        }
+    }
+
+    fn module_of(&self, addr: u32) -> Option<ModuleId> {
+        self.register_map.iter().find(|(range, _)| range.contains(&addr)).map(|(_, id)| *id)
     }
 
-    pub fn write_registers(&mut self, addr: u32, value: u8) {
-        match addr {
-            TCR0_8 => self.modules.timer8_0.update_tcr(value),
-            _ => (),
+    /// Whether a module claims the register at `addr`.
+    pub fn is_claimed(&self, addr: u32) -> bool {
+        self.module_of(addr).is_some()
+    }
+
+    pub fn next_event(&self) -> usize {
+        self.next_event
+    }
+
+    fn update_next_event(&mut self) {
+        self.next_event = if self.pending_interrupts.is_empty() {
+            [self.modules.timer8_0.next_event(), self.modules.refresh.next_event()]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(usize::MAX)
+        } else {
+            0
+        };
+    }
+
+    /// Reads a register of the modules at the state count `now`, with the side effects of the read.
+    /// None if no module claims the address.
+    pub fn read_registers(&mut self, addr: u32, now: usize) -> Option<u8> {
+        match self.module_of(addr)? {
+            ModuleId::Timer8_0 => Some(self.modules.timer8_0.read_register(addr, now, &mut self.pending_interrupts)),
+            ModuleId::Refresh => Some(self.modules.refresh.read_register(addr, now, &mut self.pending_interrupts)),
         }
     }
 
-    pub fn update_modules(&mut self, bus: &mut Bus, state: u8, interrupt_controller: &mut InterruptController) -> Result<()> {
-        self.modules.timer8_0.update_timer8_0(bus, state, interrupt_controller)?;
+    /// Value of a register of the modules at the state count `now`, without side effects.
+    pub fn peek_registers(&self, addr: u32, now: usize) -> Option<u8> {
+        match self.module_of(addr)? {
+            ModuleId::Timer8_0 => Some(self.modules.timer8_0.peek_register(addr, now)),
+            ModuleId::Refresh => Some(self.modules.refresh.peek_register(addr, now)),
+        }
+    }
+
+    /// Writes the bytes of an access from `addr` at the state count `now`, each module once with the bytes of its registers.
+    /// Returns the bytes the modules claim, bit i for the byte at `addr + i`.
+    pub fn write_registers(&mut self, addr: u32, values: &[u8], now: usize) -> u8 {
+        let mut claimed = 0;
+        let mut start = 0;
+        while start < values.len() {
+            let module = self.module_of(addr + start as u32);
+            let mut end = start + 1;
+            while end < values.len() && self.module_of(addr + end as u32) == module {
+                end += 1;
+            }
+            let (register, bytes) = (addr + start as u32, &values[start..end]);
+            match module {
+                Some(ModuleId::Timer8_0) => self
+                    .modules
+                    .timer8_0
+                    .write_register(register, bytes, now, &mut self.pending_interrupts),
+                Some(ModuleId::Refresh) => self
+                    .modules
+                    .refresh
+                    .write_register(register, bytes, now, &mut self.pending_interrupts),
+                None => (),
+            }
+            if module.is_some() {
+                claimed |= ((1 << (end - start)) - 1) << start;
+            }
+            start = end;
+        }
+        claimed
+    }
+
+    /// Whether the refresh controller refreshes the DRAM.
+    pub fn is_refreshing(&self) -> bool {
+        self.modules.refresh.is_refreshing()
+    }
+
+    /// Reschedules the modules after an access of the cpu, once for the bytes of a word or a longword.
+    pub fn finish_access(&mut self) {
+        self.update_next_event();
+    }
+
+    /// Counts the modules up to the state count `now`, and requests or withdraws their interrupts.
+    pub(crate) fn update_modules(&mut self, now: usize, interrupt_controller: &mut InterruptController) {
+        self.modules.timer8_0.sync(now, &mut self.pending_interrupts);
+        self.modules.refresh.sync(now, &mut self.pending_interrupts);
+        for (vector, requested) in self.pending_interrupts.drain(..) {
+            if requested {
+                interrupt_controller.request_interrupt(vector);
+            } else {
+                interrupt_controller.clear_interrupt(vector);
+            }
+        }
+        self.update_next_event();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{refresh::RTCOR, timer8::TCNT0_8, ModuleManager};
+    use crate::registers::{ABWCR, DRCRA};
+
+    #[test]
+    fn test_register_map() {
+        let mut module_manager = ModuleManager::new();
+        assert_eq!(module_manager.write_registers(TCNT0_8, &[0x12], 0), 1);
+        assert_eq!(module_manager.read_registers(TCNT0_8, 100), Some(0x12));
+        assert_eq!(module_manager.peek_registers(TCNT0_8, 100), Some(0x12));
+
+        // timer 1 and the bus controller are kept by the bus
+        assert_eq!(module_manager.write_registers(TCNT0_8 + 1, &[0x12], 0), 0);
+        assert_eq!(module_manager.read_registers(ABWCR, 0), None);
+
+        // the refresh controller claims DRCRA to RTCOR
+        assert_eq!(module_manager.write_registers(DRCRA, &[0x20], 0), 1);
+        assert_eq!(module_manager.peek_registers(DRCRA, 0), Some(0x30));
+        assert_eq!(module_manager.peek_registers(RTCOR, 0), Some(0xff));
 
-        Ok(())
+        // a word access is split into the bytes each module claims
+        assert_eq!(module_manager.write_registers(TCNT0_8, &[0x34, 0x56], 0), 0b01);
+        assert_eq!(module_manager.peek_registers(TCNT0_8, 0), Some(0x34));
+        assert_eq!(module_manager.write_registers(DRCRA, &[0x20, 0x00], 0), 0b11);
     }
 }
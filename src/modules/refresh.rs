@@ -0,0 +1,251 @@
+use std::ops::RangeInclusive;
+
+use crate::registers::DRCRA;
+
+pub const DRCRB: u32 = 0xfee027;
+pub const RTMCSR: u32 = 0xfee028;
+pub const RTCNT: u32 = 0xfee029;
+pub const RTCOR: u32 = 0xfee02a;
+
+// DRCRB, CAS-before-RAS refresh cycles
+pub const DRCRB_RCYCE: u8 = 0b0001_0000;
+
+// RTMCSR
+const RTMCSR_CMF: u8 = 0b1000_0000;
+const RTMCSR_CMIE: u8 = 0b0100_0000;
+const RTMCSR_CKS: u8 = 0b0011_1000;
+
+pub const CMI_VECTOR: u8 = 21;
+
+/// Refresh controller of the DRAM interface.
+/// RTCNT counts up to RTCOR and is cleared at the next count, which requests a refresh cycle of the DRAM,
+/// and the compare match can request CMI as an interval timer. The bus cycles of the refreshes are not counted.
+#[derive(Clone)]
+pub struct RefreshController {
+    // states counted toward the next tick
+    state: usize,
+    // state count the counter is synced to
+    synced: usize,
+    prescaler: usize,
+
+    drcra: u8,
+    drcrb: u8,
+    rtmcsr: u8,
+    // CMF read as 1
+    cmf_read: bool,
+    rtcnt: u8,
+    rtcor: u8,
+}
+
+impl RefreshController {
+    pub fn new() -> Self {
+        RefreshController {
+            state: 0,
+            synced: 0,
+            prescaler: 0,
+            drcra: 0x10,
+            drcrb: 0x08,
+            rtmcsr: 0x07,
+            cmf_read: false,
+            rtcnt: 0,
+            rtcor: 0xff,
+        }
+    }
+
+    /// Counts up to the state count `now`, and pushes the interrupts requested on the way as (vector, true).
+    pub fn sync(&mut self, now: usize, interrupts: &mut Vec<(u8, bool)>) {
+        let elapsed = now.saturating_sub(self.synced);
+        self.synced = now;
+        if self.prescaler == 0 {
+            return;
+        }
+        self.state += elapsed;
+        let mut count = self.state / self.prescaler;
+        self.state %= self.prescaler;
+
+        while count >= self.ticks_to_match() {
+            count -= self.ticks_to_match();
+            self.rtcnt = self.rtcor;
+            self.rtmcsr |= RTMCSR_CMF;
+            if self.rtmcsr & RTMCSR_CMIE != 0 {
+                log::trace!("interrupt {}", CMI_VECTOR);
+                interrupts.push((CMI_VECTOR, true));
+            } else {
+                // only the flag is left by the other matches
+                count %= self.rtcor as usize + 1;
+            }
+        }
+        self.advance(count);
+    }
+
+    // Ticks until RTCNT matches RTCOR, after it is cleared if it matches now (1 to 256)
+    fn ticks_to_match(&self) -> usize {
+        if self.rtcnt == self.rtcor {
+            self.rtcor as usize + 1
+        } else {
+            self.rtcor.wrapping_sub(self.rtcnt) as usize
+        }
+    }
+
+    // Counts less ticks than the next match
+    fn advance(&mut self, ticks: usize) {
+        if ticks == 0 {
+            return;
+        }
+        self.rtcnt = if self.rtcnt == self.rtcor {
+            (ticks - 1) as u8
+        } else {
+            self.rtcnt.wrapping_add(ticks as u8)
+        };
+    }
+
+    /// State count of the next compare match, None if CMI is not allowed.
+    pub fn next_event(&self) -> Option<usize> {
+        if self.prescaler == 0 || self.rtmcsr & RTMCSR_CMIE == 0 {
+            return None;
+        }
+        Some(self.synced + self.ticks_to_match() * self.prescaler - self.state)
+    }
+
+    /// Whether the DRAM is refreshed: the refresh cycles are enabled and RTCNT counts.
+    pub fn is_refreshing(&self) -> bool {
+        self.drcrb & DRCRB_RCYCE != 0 && self.prescaler != 0
+    }
+
+    /// Registers claimed in the register map.
+    pub fn registers() -> Vec<RangeInclusive<u32>> {
+        vec![DRCRA..=RTCOR]
+    }
+
+    fn register(&self, addr: u32) -> u8 {
+        match addr {
+            DRCRA => self.drcra,
+            DRCRB => self.drcrb,
+            RTMCSR => self.rtmcsr,
+            RTCNT => self.rtcnt,
+            RTCOR => self.rtcor,
+            _ => 0,
+        }
+    }
+
+    /// Reads the register at the state count `now`, after counting up to it.
+    /// CMF read as 1 can be cleared by the next write.
+    pub fn read_register(&mut self, addr: u32, now: usize, interrupts: &mut Vec<(u8, bool)>) -> u8 {
+        self.sync(now, interrupts);
+        if addr == RTMCSR && self.rtmcsr & RTMCSR_CMF != 0 {
+            self.cmf_read = true;
+        }
+        self.register(addr)
+    }
+
+    /// Value of the register at the state count `now`, without updating the counter.
+    pub fn peek_register(&self, addr: u32, now: usize) -> u8 {
+        let mut controller = self.clone();
+        controller.sync(now, &mut Vec::new());
+        controller.register(addr)
+    }
+
+    /// Writes the registers from `addr` with the bytes of one access at the state count `now`, after counting up to it.
+    /// The reserved bits are read as 1.
+    pub fn write_register(&mut self, addr: u32, values: &[u8], now: usize, interrupts: &mut Vec<(u8, bool)>) {
+        self.sync(now, interrupts);
+        for (addr, value) in (addr..).zip(values.iter().copied()) {
+            self.write_byte(addr, value, interrupts);
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8, interrupts: &mut Vec<(u8, bool)>) {
+        match addr {
+            DRCRA => self.drcra = value | 0b0001_0000,
+            DRCRB => self.drcrb = value | 0b0000_1000,
+            RTMCSR => {
+                // CMF is cleared by writing 0 after reading 1
+                let cmf = if self.cmf_read && value & RTMCSR_CMF == 0 {
+                    self.cmf_read = false;
+                    interrupts.push((CMI_VECTOR, false));
+                    0
+                } else {
+                    self.rtmcsr & RTMCSR_CMF
+                };
+                self.rtmcsr = cmf | (value & (RTMCSR_CMIE | RTMCSR_CKS)) | 0b0000_0111;
+                self.prescaler = match (self.rtmcsr & RTMCSR_CKS) >> 3 {
+                    0 => 0,
+                    1 => 2,
+                    2 => 8,
+                    3 => 32,
+                    4 => 128,
+                    5 => 512,
+                    6 => 2048,
+                    _ => 4096,
+                };
+            }
+            RTCNT => self.rtcnt = value,
+            RTCOR => self.rtcor = value,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RefreshController, CMI_VECTOR, RTCNT, RTCOR, RTMCSR};
+
+    // CMI allowed, φ/8
+    fn setup(rtcor: u8) -> RefreshController {
+        let mut controller = RefreshController::new();
+        let mut interrupts = Vec::new();
+        controller.write_register(RTCOR, &[rtcor], 0, &mut interrupts);
+        controller.write_register(RTMCSR, &[0b0101_0000], 0, &mut interrupts);
+        controller
+    }
+
+    #[test]
+    fn test_interval() {
+        // RTCNT matches 38 and is cleared at the next count, every 39 ticks
+        let mut controller = setup(38);
+        assert_eq!(controller.next_event(), Some(38 * 8));
+        let mut interrupts = Vec::new();
+        controller.sync(38 * 8 - 1, &mut interrupts);
+        assert!(interrupts.is_empty());
+        controller.sync(38 * 8, &mut interrupts);
+        assert_eq!(interrupts, vec![(CMI_VECTOR, true)]);
+        assert_eq!(controller.next_event(), Some(38 * 8 + 39 * 8));
+        assert_eq!(controller.peek_register(RTCNT, 38 * 8 + 8 * 5), 4);
+    }
+
+    #[test]
+    fn test_sync_matches_ticks() {
+        for rtmcsr in [0b0101_0000, 0b0001_0000] {
+            let mut bulk = setup(100);
+            bulk.write_register(RTMCSR, &[rtmcsr], 0, &mut Vec::new());
+            let mut stepped = bulk.clone();
+            let (mut bulk_interrupts, mut stepped_interrupts) = (Vec::new(), Vec::new());
+            bulk.sync(100_003, &mut bulk_interrupts);
+            for now in (0..=100_003).step_by(3) {
+                stepped.sync(now, &mut stepped_interrupts);
+            }
+            assert_eq!(bulk.rtcnt, stepped.rtcnt);
+            assert_eq!(bulk.rtmcsr, stepped.rtmcsr);
+            assert_eq!(bulk_interrupts, stepped_interrupts);
+        }
+    }
+
+    #[test]
+    fn test_register_access() {
+        let mut controller = setup(10);
+        let mut interrupts = Vec::new();
+        controller.sync(8 * 10, &mut interrupts);
+        // CMF is not cleared before it is read
+        controller.write_register(RTMCSR, &[0b0101_0000], 8 * 10, &mut interrupts);
+        assert_eq!(controller.peek_register(RTMCSR, 8 * 10), 0b1101_0111);
+        assert_eq!(controller.read_register(RTMCSR, 8 * 10, &mut interrupts), 0b1101_0111);
+        controller.write_register(RTMCSR, &[0b0101_0000], 8 * 10, &mut interrupts);
+        assert_eq!(controller.peek_register(RTMCSR, 8 * 10), 0b0101_0111);
+
+        // stopped by CKS
+        controller.write_register(RTMCSR, &[0], 8 * 10, &mut interrupts);
+        assert!(!controller.is_refreshing());
+        assert_eq!(controller.next_event(), None);
+        assert_eq!(controller.peek_register(RTCNT, 8 * 100), 10);
+    }
+}
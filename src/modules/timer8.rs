@@ -1,8 +1,4 @@
-use crate::{
-    bus::{Bus, IO_REGISTERS2_EMC1_START_ADDR},
-    cpu::interrupt_controller::InterruptController,
-};
-use anyhow::Result;
+use std::ops::RangeInclusive;
 
 // Timer 0
 pub const TCR0_8: u32 = 0xffff80;
@@ -32,6 +28,7 @@ pub const TCORA3: u32 = 0xffff95;
 pub const TCORB3: u32 = 0xffff97;
 pub const TCNT3_8: u32 = 0xffff99;
 
+#[derive(Clone, Copy)]
 enum CounterClear {
     Forbidden,
     CompareA,
@@ -39,16 +36,31 @@ enum CounterClear {
     InputB,
 }
 
+/// 8-bit timer channel 0.
+/// The registers are kept here and counted up lazily: to the state count of a register access,
+/// or to the next event when the cpu reaches it.
+#[derive(Clone)]
 pub struct Timer8_0 {
     is_active: bool,
-    state: u16,
+    // states counted toward the next tick
+    state: usize,
+    // state count the counter is synced to
+    synced: usize,
     // Interrupt
     is_allowed_cmib: bool,
     is_allowed_cmia: bool,
     is_allowed_ovi: bool,
 
     is_cleared_by: CounterClear,
-    prescaler: u16,
+    prescaler: usize,
+
+    tcr: u8,
+    tcsr: u8,
+    // flags of TCSR read as 1
+    tcsr_read: u8,
+    tcora: u8,
+    tcorb: u8,
+    tcnt: u8,
 }
 
 impl Timer8_0 {
@@ -56,77 +68,169 @@ impl Timer8_0 {
         Timer8_0 {
             is_active: false,
             state: 0,
+            synced: 0,
             is_allowed_cmib: false,
             is_allowed_cmia: false,
             is_allowed_ovi: false,
             is_cleared_by: CounterClear::Forbidden, // TODO: 8TCNT1 および 8TCNT3 のカウンタクリア要因を、インプットキャプチャ B に設定した場合、8TCNT0および 8TCNT2 はコンぺアマッチ B によりクリアされません。
             prescaler: 0,
+            tcr: 0,
+            tcsr: 0,
+            tcsr_read: 0,
+            tcora: 0xff,
+            tcorb: 0xff,
+            tcnt: 0,
         }
     }
 
-    pub fn update_timer8_0(&mut self, bus: &mut Bus, state: u8, interrupt_controller: &mut InterruptController) -> Result<()> {
+    /// Counts up to the state count `now`, and pushes the interrupts requested on the way as (vector, true).
+    pub fn sync(&mut self, now: usize, interrupts: &mut Vec<(u8, bool)>) {
+        let elapsed = now.saturating_sub(self.synced);
+        self.synced = now;
         if self.prescaler == 0 {
-            return Ok(());
+            return;
         }
-        self.state += u16::from(state);
+        self.state += elapsed;
         let mut count = self.state / self.prescaler;
-        self.state -= self.prescaler * count;
+        self.state %= self.prescaler;
 
         while count != 0 {
-            // count
-            let tcnt = bus.read(TCNT0_8)?;
-            let (mut tcnt, is_overflowed) = tcnt.overflowing_add(1);
+            // the flags do not change until the counter reaches an event
+            let skip = (self.ticks_to_event() - 1).min(count);
+            self.tcnt = self.tcnt.wrapping_add(skip as u8);
+            count -= skip;
+            if count == 0 {
+                break;
+            }
+            self.tick(interrupts);
+            count -= 1;
+        }
+    }
 
-            let tcora = bus.read(TCORA0)?;
-            let tcorb = bus.read(TCORB0)?;
+    // Ticks until the counter matches TCORA or TCORB, or overflows (1 to 256)
+    fn ticks_to_event(&self) -> usize {
+        let ticks_to = |target: u8| (target.wrapping_sub(self.tcnt) as usize + 255) % 256 + 1;
+        ticks_to(self.tcora).min(ticks_to(self.tcorb)).min(256 - self.tcnt as usize)
+    }
 
-            let mut tcsr = bus.read(TCSR0_8)?;
+    fn tick(&mut self, interrupts: &mut Vec<(u8, bool)>) {
+        // count
+        let (mut tcnt, is_overflowed) = self.tcnt.overflowing_add(1);
 
-            // CMFA, Compare match on TCORA0
-            if tcnt == tcora {
-                tcsr |= 0b0100_0000;
-                if let CounterClear::CompareA = self.is_cleared_by {
-                    tcnt = 0;
-                }
-                if self.is_allowed_cmia {
-                    log::trace!("interrupt 36");
-                    interrupt_controller.request_interrupt(36);
-                }
+        // CMFA, Compare match on TCORA0
+        if tcnt == self.tcora {
+            self.tcsr |= 0b0100_0000;
+            if let CounterClear::CompareA = self.is_cleared_by {
+                tcnt = 0;
+            }
+            if self.is_allowed_cmia {
+                log::trace!("interrupt 36");
+                interrupts.push((36, true));
             }
+        }
 
-            // CMFB, Compare match on TCORB0
-            if tcnt == tcorb {
-                tcsr |= 0b1000_0000;
-                if let CounterClear::CompareInputB = self.is_cleared_by {
-                    tcnt = 0;
-                }
-                if self.is_allowed_cmib {
-                    log::trace!("interrupt 37");
-                    interrupt_controller.request_interrupt(37);
-                }
+        // CMFB, Compare match on TCORB0
+        if tcnt == self.tcorb {
+            self.tcsr |= 0b1000_0000;
+            if let CounterClear::CompareInputB = self.is_cleared_by {
+                tcnt = 0;
+            }
+            if self.is_allowed_cmib {
+                log::trace!("interrupt 37");
+                interrupts.push((37, true));
             }
+        }
 
-            // OVF, Overflow TCNT
-            if is_overflowed {
-                tcsr |= 0b0010_0000;
-                if self.is_allowed_ovi {
-                    log::trace!("interrupt 39");
-                    interrupt_controller.request_interrupt(39);
-                }
+        // OVF, Overflow TCNT
+        if is_overflowed {
+            self.tcsr |= 0b0010_0000;
+            if self.is_allowed_ovi {
+                log::trace!("interrupt 39");
+                interrupts.push((39, true));
             }
+        }
 
-            // Don't use bus.write()
-            bus.io_registrs2[(TCNT0_8 - IO_REGISTERS2_EMC1_START_ADDR) as usize] = tcnt;
-            bus.io_registrs2[(TCSR0_8 - IO_REGISTERS2_EMC1_START_ADDR) as usize] = tcsr;
+        self.tcnt = tcnt;
+    }
 
-            count -= 1;
+    /// State count of the next tick that can request an interrupt, None if no interrupt is allowed.
+    pub fn next_event(&self) -> Option<usize> {
+        if self.prescaler == 0 || !(self.is_allowed_cmia || self.is_allowed_cmib || self.is_allowed_ovi) {
+            return None;
+        }
+        // A clear by a compare match only delays the other events, so the earliest one is never late
+        Some(self.synced + self.ticks_to_event() * self.prescaler - self.state)
+    }
+
+    /// Registers claimed in the register map.
+    pub fn registers() -> Vec<RangeInclusive<u32>> {
+        [TCR0_8, TCSR0_8, TCORA0, TCORB0, TCNT0_8]
+            .iter()
+            .map(|addr| *addr..=*addr)
+            .collect()
+    }
+
+    fn register(&self, addr: u32) -> u8 {
+        match addr {
+            TCR0_8 => self.tcr,
+            TCSR0_8 => self.tcsr,
+            TCORA0 => self.tcora,
+            TCORB0 => self.tcorb,
+            TCNT0_8 => self.tcnt,
+            _ => 0,
+        }
+    }
+
+    /// Reads the register at the state count `now`, after counting up to it.
+    /// The flags of TCSR read as 1 can be cleared by the next write.
+    pub fn read_register(&mut self, addr: u32, now: usize, interrupts: &mut Vec<(u8, bool)>) -> u8 {
+        self.sync(now, interrupts);
+        if addr == TCSR0_8 {
+            self.tcsr_read |= self.tcsr & 0b1110_0000;
         }
+        self.register(addr)
+    }
+
+    /// Value of the register at the state count `now`, without updating the timer.
+    pub fn peek_register(&self, addr: u32, now: usize) -> u8 {
+        let mut timer = self.clone();
+        timer.sync(now, &mut Vec::new());
+        timer.register(addr)
+    }
 
-        Ok(())
+    /// Writes the registers from `addr` with the bytes of one access at the state count `now`, after counting up to it.
+    pub fn write_register(&mut self, addr: u32, values: &[u8], now: usize, interrupts: &mut Vec<(u8, bool)>) {
+        self.sync(now, interrupts);
+        for (addr, value) in (addr..).zip(values.iter().copied()) {
+            self.write_byte(addr, value, interrupts);
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8, interrupts: &mut Vec<(u8, bool)>) {
+        match addr {
+            TCR0_8 => self.update_tcr(value),
+            TCSR0_8 => {
+                // a flag is cleared by writing 0 after reading 1
+                let cleared = self.tcsr_read & !value;
+                self.tcsr = (self.tcsr & !cleared & 0b1110_0000) | (value & 0b0001_1111);
+                self.tcsr_read &= !cleared;
+                // the requests of the cleared flags are withdrawn
+                for (flag, vector) in [(0b1000_0000, 37), (0b0100_0000, 36), (0b0010_0000, 39)] {
+                    if cleared & flag != 0 {
+                        interrupts.push((vector, false));
+                    }
+                }
+            }
+            TCORA0 => self.tcora = value,
+            TCORB0 => self.tcorb = value,
+            TCNT0_8 => self.tcnt = value,
+            _ => (),
+        }
     }
 
     pub fn update_tcr(&mut self, tcr: u8) {
         log::trace!("start timer");
+        self.tcr = tcr;
         self.is_allowed_cmib = tcr & 0b1000_0000 != 0;
         self.is_allowed_cmia = tcr & 0b0100_0000 != 0;
         self.is_allowed_ovi = tcr & 0b0010_0000 != 0;
@@ -152,3 +256,77 @@ impl Timer8_0 {
         self.is_active = self.prescaler != 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Timer8_0, TCNT0_8, TCORA0, TCORB0, TCR0_8, TCSR0_8};
+
+    fn setup(tcr: u8, tcora: u8, tcorb: u8) -> Timer8_0 {
+        let mut timer = Timer8_0::new();
+        let mut interrupts = Vec::new();
+        timer.write_register(TCORA0, &[tcora], 0, &mut interrupts);
+        timer.write_register(TCORB0, &[tcorb], 0, &mut interrupts);
+        timer.write_register(TCR0_8, &[tcr], 0, &mut interrupts);
+        timer
+    }
+
+    #[test]
+    fn test_sync_matches_ticks() {
+        // clear on compare match A, interrupts of A, B and overflow, φ/8
+        for tcr in [0b1110_1001, 0b1111_0001, 0b1110_0001] {
+            let mut bulk = setup(tcr, 100, 40);
+            let mut stepped = bulk.clone();
+            let (mut bulk_interrupts, mut stepped_interrupts) = (Vec::new(), Vec::new());
+            bulk.sync(100_003, &mut bulk_interrupts);
+            for now in (0..=100_003).step_by(3) {
+                stepped.sync(now, &mut stepped_interrupts);
+            }
+            stepped.sync(100_003, &mut stepped_interrupts);
+            assert_eq!(bulk.tcnt, stepped.tcnt);
+            assert_eq!(bulk.tcsr, stepped.tcsr);
+            assert_eq!(bulk.state, stepped.state);
+            assert_eq!(bulk_interrupts, stepped_interrupts);
+        }
+    }
+
+    #[test]
+    fn test_next_event() {
+        let mut timer = setup(0b0100_1001, 100, 0xff);
+        // compare match A when the counter reaches 100, every 8 states
+        assert_eq!(timer.next_event(), Some(800));
+
+        let mut interrupts = Vec::new();
+        timer.sync(799, &mut interrupts);
+        assert!(interrupts.is_empty());
+        assert_eq!(timer.next_event(), Some(800));
+        timer.sync(800, &mut interrupts);
+        assert_eq!(interrupts, vec![(36, true)]);
+        assert_eq!(timer.tcnt, 0);
+        assert_eq!(timer.next_event(), Some(1600));
+
+        // no interrupt is allowed
+        assert_eq!(setup(0b0000_1001, 100, 0xff).next_event(), None);
+    }
+
+    #[test]
+    fn test_register_access() {
+        let mut timer = setup(0b0000_0010, 0xff, 0xff);
+        // counted up to the access without updating the timer
+        assert_eq!(timer.peek_register(TCNT0_8, 64 * 10 + 5), 10);
+        assert_eq!(timer.tcnt, 0);
+
+        // compare match A and B at 0xff, then the overflow
+        let mut interrupts = Vec::new();
+        timer.sync(64 * 256, &mut interrupts);
+        assert_eq!(timer.tcsr, 0b1110_0000);
+        // the flags are not cleared before they are read
+        timer.write_register(TCSR0_8, &[0b0001_1111], 64 * 256, &mut interrupts);
+        assert_eq!(timer.peek_register(TCSR0_8, 64 * 256), 0b1111_1111);
+
+        assert_eq!(timer.read_register(TCSR0_8, 64 * 256, &mut interrupts), 0b1111_1111);
+        timer.write_register(TCSR0_8, &[0b0101_1111], 64 * 256, &mut interrupts);
+        assert_eq!(timer.peek_register(TCSR0_8, 64 * 256), 0b0101_1111);
+        // no interrupt is allowed, and the cleared flags withdraw theirs
+        assert_eq!(interrupts, vec![(37, false), (39, false)]);
+    }
+}
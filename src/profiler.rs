@@ -0,0 +1,327 @@
+use crate::elf::Symbols;
+use anyhow::{Context as _, Result};
+use std::{collections::HashMap, fs::File, io::Write};
+
+const OPCODE_RTS: u16 = 0x5470;
+const OPCODE_RTE: u16 = 0x5670;
+
+/// Returns true for instructions which push the return address and jump.
+pub fn is_call(opcode: u16) -> bool {
+    match (opcode >> 8) as u8 {
+        // JSR, BSR
+        0x5d | 0x5e | 0x5f | 0x55 | 0x5c => true,
+        // TRAPA #1..#3 (#0 is emulated as a MES2 system call)
+        0x57 => (opcode >> 4) & 0x3 != 0,
+        _ => false,
+    }
+}
+
+pub fn is_return(opcode: u16) -> bool {
+    opcode == OPCODE_RTS || opcode == OPCODE_RTE
+}
+
+// Node of the call tree
+#[derive(Clone)]
+struct Node {
+    func: u32,
+    parent: usize,
+    children: HashMap<u32, usize>,
+    exclusive: u64,
+    calls: u64,
+}
+
+#[derive(Clone)]
+struct Frame {
+    node: usize,
+    // er7 after returning from the frame
+    return_sp: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionProfile {
+    pub func: u32,
+    pub exclusive: u64,
+    pub inclusive: u64,
+    pub calls: u64,
+}
+
+/// Attributes execution states to functions by tracking subroutine calls, returns and interrupts.
+#[derive(Clone)]
+pub struct Profiler {
+    path: String,
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new(path: &str, entry_addr: u32) -> Self {
+        Profiler {
+            path: path.to_string(),
+            nodes: vec![Node {
+                func: entry_addr,
+                parent: 0,
+                children: HashMap::new(),
+                exclusive: 0,
+                calls: 1,
+            }],
+            stack: vec![Frame {
+                node: 0,
+                return_sp: u32::MAX,
+            }],
+            total: 0,
+        }
+    }
+
+    /// Called after an instruction is executed.
+    /// `pc` and `sp` are the values after the execution.
+    pub fn on_instruction(&mut self, opcode: u16, state: u8, pc: u32, sp: u32) {
+        let current = self.stack.last().map_or(0, |f| f.node);
+        self.nodes[current].exclusive += state as u64;
+        self.total += state as u64;
+
+        if is_call(opcode) {
+            self.enter(pc, sp);
+        } else if is_return(opcode) {
+            self.leave(sp);
+        }
+    }
+
+    /// Called after the exception handling jumped to the handler.
+    /// The states of the exception handling are attributed to the handler.
+    pub fn on_interrupt(&mut self, state: u8, pc: u32, sp: u32) {
+        self.enter(pc, sp);
+        let current = self.stack.last().map_or(0, |f| f.node);
+        self.nodes[current].exclusive += state as u64;
+        self.total += state as u64;
+    }
+
+    fn enter(&mut self, func: u32, sp: u32) {
+        let parent = self.stack.last().map_or(0, |f| f.node);
+        let node = match self.nodes[parent].children.get(&func) {
+            Some(node) => *node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(Node {
+                    func,
+                    parent,
+                    children: HashMap::new(),
+                    exclusive: 0,
+                    calls: 0,
+                });
+                self.nodes[parent].children.insert(func, node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.stack.push(Frame {
+            node,
+            return_sp: sp.wrapping_add(4),
+        });
+    }
+
+    fn leave(&mut self, sp: u32) {
+        while self.stack.len() > 1 && self.stack.last().is_some_and(|f| f.return_sp <= sp) {
+            self.stack.pop();
+        }
+    }
+
+    fn path(&self, mut node: usize) -> Vec<u32> {
+        let mut path = vec![self.nodes[node].func];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            path.push(self.nodes[node].func);
+        }
+        path.reverse();
+        path
+    }
+
+    fn subtree_total(&self, node: usize, totals: &mut Vec<Option<u64>>) -> u64 {
+        if let Some(total) = totals[node] {
+            return total;
+        }
+        let mut total = self.nodes[node].exclusive;
+        for child in self.nodes[node].children.values() {
+            total += self.subtree_total(*child, totals);
+        }
+        totals[node] = Some(total);
+        total
+    }
+
+    /// Returns profiles sorted by exclusive states.
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut totals = vec![None; self.nodes.len()];
+        let mut functions: HashMap<u32, FunctionProfile> = HashMap::new();
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let subtree_total = self.subtree_total(i, &mut totals);
+            let path = self.path(i);
+            // Count inclusive states only at the outermost call of recursive functions
+            let is_outermost = !path[..path.len() - 1].contains(&node.func);
+
+            let profile = functions.entry(node.func).or_insert(FunctionProfile {
+                func: node.func,
+                exclusive: 0,
+                inclusive: 0,
+                calls: 0,
+            });
+            profile.exclusive += node.exclusive;
+            profile.calls += node.calls;
+            if is_outermost {
+                profile.inclusive += subtree_total;
+            }
+        }
+
+        let mut functions: Vec<FunctionProfile> = functions.into_values().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.func.cmp(&b.func)));
+        functions
+    }
+
+    /// Returns lines in the collapsed stack format (`main;foo;bar states`).
+    pub fn folded(&self, symbols: &Symbols) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.exclusive != 0)
+            .map(|(i, node)| {
+                let names: Vec<String> = self.path(i).iter().map(|func| function_name(symbols, *func)).collect();
+                format!("{} {}", names.join(";"), node.exclusive)
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut report = format!("Total states: {}\n", self.total);
+        report += &format!(
+            "{:>12} {:>7} {:>12} {:>7} {:>8}  {}\n",
+            "exclusive", "%", "inclusive", "%", "calls", "function"
+        );
+        let percent = |states: u64| {
+            if self.total == 0 {
+                0.0
+            } else {
+                states as f64 * 100.0 / self.total as f64
+            }
+        };
+        for f in self.functions() {
+            report += &format!(
+                "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>8}  {}\n",
+                f.exclusive,
+                percent(f.exclusive),
+                f.inclusive,
+                percent(f.inclusive),
+                f.calls,
+                function_name(symbols, f.func)
+            );
+        }
+        report
+    }
+
+    /// Writes the flat report to the file and the collapsed stacks to `<file>.folded`.
+    pub fn finish(&self, symbols: &Symbols) -> Result<()> {
+        let mut file = File::create(&self.path).with_context(|| format!("failed to create profile [{}]", self.path))?;
+        file.write_all(self.report(symbols).as_bytes())?;
+
+        let folded_path = format!("{}.folded", self.path);
+        let mut file = File::create(&folded_path).with_context(|| format!("failed to create profile [{}]", folded_path))?;
+        for line in self.folded(symbols) {
+            writeln!(file, "{}", line)?;
+        }
+        log::info!("Wrote profile [{}] [{}]", self.path, folded_path);
+        Ok(())
+    }
+}
+
+fn function_name(symbols: &Symbols, func: u32) -> String {
+    match symbols.find_by_addr(func) {
+        Some(symbol) if symbol.addr == func => symbol.name.clone(),
+        _ => format!("0x{:x}", func),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FunctionProfile, Profiler};
+    use crate::elf::{Symbol, Symbols};
+
+    const MAIN: u32 = 0x1000;
+    const FUNC: u32 = 0x2000;
+    const HANDLER: u32 = 0x3000;
+    const SP: u32 = 0xffff00;
+
+    fn create_symbols() -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, addr) in [("_main", MAIN), ("_func", FUNC), ("_handler", HANDLER)] {
+            symbols.push(Symbol {
+                name: name.to_string(),
+                addr,
+                size: 0x100,
+            });
+        }
+        symbols
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let mut profiler = Profiler::new("", MAIN);
+        profiler.on_instruction(0x0000, 2, MAIN + 2, SP);
+        // jsr @func
+        profiler.on_instruction(0x5e00, 8, FUNC, SP - 4);
+        profiler.on_instruction(0x0000, 4, FUNC + 2, SP - 4);
+        // rts
+        profiler.on_instruction(0x5470, 8, MAIN + 6, SP);
+        profiler.on_instruction(0x0000, 2, MAIN + 8, SP);
+
+        let functions = profiler.functions();
+        assert_eq!(
+            functions,
+            vec![
+                FunctionProfile {
+                    func: MAIN,
+                    exclusive: 12,
+                    inclusive: 24,
+                    calls: 1,
+                },
+                FunctionProfile {
+                    func: FUNC,
+                    exclusive: 12,
+                    inclusive: 12,
+                    calls: 1,
+                },
+            ]
+        );
+        assert_eq!(profiler.folded(&create_symbols()), vec!["_main 12", "_main;_func 12"]);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let mut profiler = Profiler::new("", MAIN);
+        profiler.on_instruction(0x5500, 2, FUNC, SP - 4);
+        profiler.on_instruction(0x5500, 2, FUNC, SP - 8);
+        profiler.on_instruction(0x5470, 2, FUNC + 2, SP - 4);
+        profiler.on_instruction(0x5470, 2, MAIN + 2, SP);
+
+        let functions = profiler.functions();
+        let func = functions.iter().find(|f| f.func == FUNC).unwrap();
+        assert_eq!(func.exclusive, 6);
+        assert_eq!(func.inclusive, 6);
+        assert_eq!(func.calls, 2);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut profiler = Profiler::new("", MAIN);
+        profiler.on_instruction(0x0000, 2, MAIN + 2, SP);
+        profiler.on_interrupt(16, HANDLER, SP - 4);
+        profiler.on_instruction(0x0000, 4, HANDLER + 2, SP - 4);
+        // rte
+        profiler.on_instruction(0x5670, 10, MAIN + 2, SP);
+        profiler.on_instruction(0x0000, 2, MAIN + 4, SP);
+
+        assert_eq!(profiler.folded(&create_symbols()), vec!["_main 4", "_main;_handler 30"]);
+        assert!(profiler.report(&create_symbols()).starts_with("Total states: 34\n"));
+    }
+}
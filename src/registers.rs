@@ -1,5 +1,14 @@
+pub const P8DDR: u32 = 0xfee007;
+pub const MDCR: u32 = 0xfee011;
+pub const SYSCR: u32 = 0xfee012;
+pub const BRCR: u32 = 0xfee013;
+pub const IPRA: u32 = 0xfee018;
+pub const IPRB: u32 = 0xfee019;
+pub const ADRCR: u32 = 0xfee01e;
+pub const CSCR: u32 = 0xfee01f;
 pub const ABWCR: u32 = 0xfee020;
 pub const ASTCR: u32 = 0xfee021;
 pub const WCRH: u32 = 0xfee022;
 pub const WCRL: u32 = 0xfee023;
+pub const BCR: u32 = 0xfee024;
 pub const DRCRA: u32 = 0xfee026;
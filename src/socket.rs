@@ -74,9 +74,4 @@ impl Socket {
     pub fn pop_messages(&self) -> Result<Vec<String>> {
         Ok(self.message_rx.try_iter().collect())
     }
-
-    pub fn send_message(&self, message: &String) -> Result<()> {
-        self.message_tx.send(message.clone())?;
-        Ok(())
-    }
 }
@@ -0,0 +1,214 @@
+use crate::{
+    elf::Symbols,
+    profiler::{is_call, is_return},
+};
+use std::collections::HashMap;
+
+#[derive(Clone)]
+struct Frame {
+    func: u32,
+    is_interrupt: bool,
+    // er7 before the return address was pushed
+    base_sp: u32,
+    // lowest er7 in the frame and its callees
+    min_sp: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Usage {
+    pub func: u32,
+    // bytes from the stack pointer before the call, including the return address
+    pub max_depth: u32,
+    pub calls: u64,
+}
+
+/// Tracks the peak stack depth of each function and interrupt handler.
+#[derive(Clone)]
+pub struct StackUsage {
+    initial_sp: u32,
+    frames: Vec<Frame>,
+    functions: HashMap<u32, Usage>,
+    interrupts: HashMap<u32, Usage>,
+    min_sp: u32,
+    // call chain at `min_sp`
+    peak_chain: Vec<u32>,
+}
+
+impl StackUsage {
+    pub fn new(entry_addr: u32, initial_sp: u32) -> Self {
+        StackUsage {
+            initial_sp,
+            frames: vec![Frame {
+                func: entry_addr,
+                is_interrupt: false,
+                base_sp: initial_sp,
+                min_sp: initial_sp,
+            }],
+            functions: HashMap::new(),
+            interrupts: HashMap::new(),
+            min_sp: initial_sp,
+            peak_chain: vec![entry_addr],
+        }
+    }
+
+    /// Called after an instruction is executed.
+    /// `pc` and `sp` are the values after the execution.
+    pub fn on_instruction(&mut self, opcode: u16, pc: u32, sp: u32) {
+        if is_call(opcode) {
+            self.enter(pc, sp, false);
+        } else if is_return(opcode) {
+            self.leave(sp);
+        }
+        self.update(sp);
+    }
+
+    pub fn on_interrupt(&mut self, pc: u32, sp: u32) {
+        self.enter(pc, sp, true);
+        self.update(sp);
+    }
+
+    fn update(&mut self, sp: u32) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.min_sp = frame.min_sp.min(sp);
+        }
+        if sp < self.min_sp {
+            self.min_sp = sp;
+            self.peak_chain = self.frames.iter().map(|f| f.func).collect();
+        }
+    }
+
+    fn enter(&mut self, func: u32, sp: u32, is_interrupt: bool) {
+        self.frames.push(Frame {
+            func,
+            is_interrupt,
+            base_sp: sp.wrapping_add(4),
+            min_sp: sp,
+        });
+    }
+
+    fn leave(&mut self, sp: u32) {
+        while self.frames.len() > 1 && self.frames.last().is_some_and(|f| f.base_sp <= sp) {
+            self.pop();
+        }
+    }
+
+    fn pop(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        let usages = if frame.is_interrupt {
+            &mut self.interrupts
+        } else {
+            &mut self.functions
+        };
+        let usage = usages.entry(frame.func).or_insert(Usage {
+            func: frame.func,
+            max_depth: 0,
+            calls: 0,
+        });
+        usage.max_depth = usage.max_depth.max(frame.base_sp.saturating_sub(frame.min_sp));
+        usage.calls += 1;
+
+        // Interrupts use the stack of the interrupted function, but are reported separately
+        if let (Some(parent), false) = (self.frames.last_mut(), frame.is_interrupt) {
+            parent.min_sp = parent.min_sp.min(frame.min_sp);
+        }
+    }
+
+    fn sorted(usages: &HashMap<u32, Usage>) -> Vec<Usage> {
+        let mut usages: Vec<Usage> = usages.values().cloned().collect();
+        usages.sort_by(|a, b| b.max_depth.cmp(&a.max_depth).then(a.func.cmp(&b.func)));
+        usages
+    }
+
+    /// Closes the frames still on the stack and returns the usages of functions and interrupt handlers.
+    pub fn finish(&mut self) -> (Vec<Usage>, Vec<Usage>) {
+        while !self.frames.is_empty() {
+            self.pop();
+        }
+        (Self::sorted(&self.functions), Self::sorted(&self.interrupts))
+    }
+
+    pub fn report(&mut self, symbols: &Symbols) -> String {
+        let (functions, interrupts) = self.finish();
+        let name = |func: u32| match symbols.find_by_addr(func) {
+            Some(symbol) if symbol.addr == func => symbol.name.clone(),
+            _ => format!("0x{:x}", func),
+        };
+
+        let chain: Vec<String> = self.peak_chain.iter().map(|func| name(*func)).collect();
+        let mut report = format!(
+            "Max stack depth: {} bytes (sp: 0x{:x})\n  in {}\n",
+            self.initial_sp - self.min_sp,
+            self.min_sp,
+            chain.join(" > ")
+        );
+        for (title, usages) in [("function", functions), ("interrupt handler", interrupts)] {
+            if usages.is_empty() {
+                continue;
+            }
+            report += &format!("{:>8} {:>8}  {}\n", "bytes", "calls", title);
+            for usage in usages {
+                report += &format!("{:>8} {:>8}  {}\n", usage.max_depth, usage.calls, name(usage.func));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StackUsage, Usage};
+
+    const MAIN: u32 = 0x1000;
+    const FUNC: u32 = 0x2000;
+    const HANDLER: u32 = 0x3000;
+    const SP: u32 = 0xffff00;
+
+    #[test]
+    fn test_nested_calls() {
+        let mut usage = StackUsage::new(MAIN, SP);
+        // push er6
+        usage.on_instruction(0x6df6, MAIN + 2, SP - 4);
+        // jsr @func
+        usage.on_instruction(0x5e00, FUNC, SP - 8);
+        // sub.l #16,sp
+        usage.on_instruction(0x7a37, FUNC + 6, SP - 24);
+        // interrupt in func
+        usage.on_interrupt(HANDLER, SP - 28);
+        usage.on_instruction(0x6df0, HANDLER + 2, SP - 32);
+        usage.on_instruction(0x6d70, HANDLER + 4, SP - 28);
+        // rte
+        usage.on_instruction(0x5670, FUNC + 6, SP - 24);
+        // add.l #16,sp; rts
+        usage.on_instruction(0x7a17, FUNC + 12, SP - 8);
+        usage.on_instruction(0x5470, MAIN + 6, SP - 4);
+
+        let (functions, interrupts) = usage.finish();
+        assert_eq!(
+            functions,
+            vec![
+                Usage {
+                    func: MAIN,
+                    max_depth: 24,
+                    calls: 1,
+                },
+                Usage {
+                    func: FUNC,
+                    max_depth: 20,
+                    calls: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            interrupts,
+            vec![Usage {
+                func: HANDLER,
+                max_depth: 8,
+                calls: 1,
+            }]
+        );
+        assert_eq!(usage.min_sp, SP - 32);
+        assert_eq!(usage.peak_chain, vec![MAIN, FUNC, HANDLER]);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context as _, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub passed: bool,
+    pub message: String,
+    // pc of the trapa
+    pub pc: u32,
+    // (expected, actual) of assert_eq
+    pub values: Option<(u32, u32)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub pc: u32,
+    pub ccr: u8,
+    pub er: [u32; 8],
+    pub state_sum: usize,
+}
+
+/// Results reported by the program with the test control system calls.
+#[derive(Clone)]
+pub struct TestReport {
+    // summary file written at exit
+    path: Option<String>,
+    pub results: Vec<TestResult>,
+    pub snapshots: Vec<Snapshot>,
+    // exit code given by the terminate call
+    pub terminated: Option<u32>,
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl TestReport {
+    pub fn new(path: Option<String>) -> Self {
+        TestReport {
+            path,
+            results: Vec::new(),
+            snapshots: Vec::new(),
+            terminated: None,
+        }
+    }
+
+    pub fn is_used(&self) -> bool {
+        !self.results.is_empty() || !self.snapshots.is_empty() || self.terminated.is_some()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed).count()
+    }
+
+    /// Exit code of the emulator: 1 if a test failed, otherwise the code given by the terminate call.
+    pub fn process_exit_code(&self) -> i32 {
+        match (self.failed(), self.terminated) {
+            (0, Some(code)) => code as i32,
+            (0, None) => 0,
+            _ => 1,
+        }
+    }
+
+    pub fn to_json(&self, exit_code: Option<u32>, state_sum: usize) -> String {
+        let results: Vec<String> = self
+            .results
+            .iter()
+            .map(|r| {
+                let values = match r.values {
+                    Some((expected, actual)) => format!(",\"expected\":{},\"actual\":{}", expected, actual),
+                    None => String::new(),
+                };
+                format!(
+                    "{{\"passed\":{},\"message\":\"{}\",\"pc\":{}{}}}",
+                    r.passed,
+                    escape_json(&r.message),
+                    r.pc,
+                    values
+                )
+            })
+            .collect();
+        let snapshots: Vec<String> = self
+            .snapshots
+            .iter()
+            .map(|s| {
+                format!(
+                    "{{\"name\":\"{}\",\"pc\":{},\"ccr\":{},\"er\":[{}],\"state_sum\":{}}}",
+                    escape_json(&s.name),
+                    s.pc,
+                    s.ccr,
+                    s.er.map(|er| er.to_string()).join(","),
+                    s.state_sum
+                )
+            })
+            .collect();
+        format!(
+            "{{\"passed\":{},\"failed\":{},\"exit_code\":{},\"state_sum\":{},\"results\":[{}],\"snapshots\":[{}]}}",
+            self.results.len() - self.failed(),
+            self.failed(),
+            exit_code.map_or("null".to_string(), |code| code.to_string()),
+            state_sum,
+            results.join(","),
+            snapshots.join(",")
+        )
+    }
+
+    /// Logs the summary and writes it to the file as JSON.
+    pub fn finish(&self, exit_code: Option<u32>, state_sum: usize) -> Result<()> {
+        if !self.is_used() && self.path.is_none() {
+            return Ok(());
+        }
+        log::info!("Tests: {} passed, {} failed", self.results.len() - self.failed(), self.failed());
+        if let Some(path) = &self.path {
+            std::fs::write(path, self.to_json(exit_code, state_sum) + "\n")
+                .with_context(|| format!("failed to write the test summary [{}]", path))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, TestReport, TestResult};
+
+    #[test]
+    fn test_to_json() {
+        let mut report = TestReport::new(None);
+        assert_eq!(report.process_exit_code(), 0);
+        report.results.push(TestResult {
+            passed: true,
+            message: "init".to_string(),
+            pc: 0x100,
+            values: None,
+        });
+        report.results.push(TestResult {
+            passed: false,
+            message: "count \"a\"".to_string(),
+            pc: 0x200,
+            values: Some((1, 2)),
+        });
+        report.snapshots.push(Snapshot {
+            name: "s".to_string(),
+            pc: 0x300,
+            ccr: 0x80,
+            er: [0, 1, 2, 3, 4, 5, 6, 7],
+            state_sum: 10,
+        });
+        report.terminated = Some(3);
+        assert_eq!(
+            report.to_json(Some(3), 20),
+            "{\"passed\":1,\"failed\":1,\"exit_code\":3,\"state_sum\":20,\
+             \"results\":[{\"passed\":true,\"message\":\"init\",\"pc\":256},\
+             {\"passed\":false,\"message\":\"count \\\"a\\\"\",\"pc\":512,\"expected\":1,\"actual\":2}],\
+             \"snapshots\":[{\"name\":\"s\",\"pc\":768,\"ccr\":128,\"er\":[0,1,2,3,4,5,6,7],\"state_sum\":10}]}"
+        );
+        assert_eq!(report.process_exit_code(), 1);
+
+        report.results.pop();
+        assert_eq!(report.process_exit_code(), 3);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    config::EmulatorConfig,
+    cpu::Cpu,
+    loader::{self, LoadOptions},
+    mes2::{GuestStdin, StdinSource},
+};
+use anyhow::{bail, Context as _, Result};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{mpsc, Mutex},
+    time::{Duration, Instant},
+};
+
+mod expect;
+mod junit;
+
+pub use expect::Expectation;
+
+// limit of programs without max_states, so a hung program fails instead of blocking the run
+pub const DEFAULT_MAX_STATES: usize = 100_000_000;
+
+/// Result of one program.
+pub struct Outcome {
+    pub name: String,
+    // empty if passed
+    pub failures: Vec<String>,
+    pub state_sum: usize,
+    pub time: Duration,
+}
+
+impl Outcome {
+    pub fn is_passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Finds the elf files under the paths that have expectation sidecar files, in name order.
+pub fn discover(paths: &[PathBuf]) -> Result<Vec<(PathBuf, Expectation)>> {
+    let mut elf_paths = Vec::new();
+    for path in paths {
+        collect_elf_paths(path, &mut elf_paths)?;
+    }
+    elf_paths.sort();
+
+    let mut tests = Vec::new();
+    for elf_path in elf_paths {
+        match Expectation::load(&elf_path)? {
+            Some(expectation) => tests.push((elf_path, expectation)),
+            None => log::debug!("[test] skip {}, no expectation", elf_path.display()),
+        }
+    }
+    Ok(tests)
+}
+
+fn collect_elf_paths(path: &Path, elf_paths: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path).with_context(|| format!("failed to read [{}]", path.display()))? {
+            collect_elf_paths(&entry?.path(), elf_paths)?;
+        }
+    } else if path.extension().is_some_and(|ext| ext == "elf") {
+        elf_paths.push(path.to_path_buf());
+    } else if !path.exists() {
+        bail!("[{}] does not exist", path.display());
+    }
+    Ok(())
+}
+
+// Parses `ioport:<port>:<value>:<state>` messages of the bus
+fn parse_port_message(message: &str) -> Option<(u8, u8)> {
+    let mut list = message.strip_prefix("ioport:")?.split(':');
+    let port = u8::from_str_radix(list.next()?, 16).ok()?;
+    let value = u8::from_str_radix(list.next()?, 16).ok()?;
+    Some((port, value))
+}
+
+fn format_ports(ports: &[(u8, u8)]) -> String {
+    let ports: Vec<String> = ports.iter().map(|(port, value)| format!("{:x}:{:x}", port, value)).collect();
+    format!("[{}]", ports.join(", "))
+}
+
+/// Runs the program to the end and compares the results with the expectation.
+pub fn run_test(elf_path: &Path, expectation: &Expectation) -> Outcome {
+    let start = Instant::now();
+    let mut cpu = Cpu::new();
+    cpu.set_config(EmulatorConfig {
+        print_instruction: false,
+        deterministic: true,
+        ..Default::default()
+    });
+    let (message_tx, message_rx) = mpsc::channel();
+    cpu.bus.message_tx = Some(message_tx);
+    cpu.stdout = Some(Vec::new());
+    cpu.max_states = Some(expectation.max_states.unwrap_or(DEFAULT_MAX_STATES));
+
+    let mut failures = Vec::new();
+    let result = (|| -> Result<()> {
+        cpu.mes2.stdin = match &expectation.stdin {
+            Some(path) => {
+                let file = std::fs::File::open(path).with_context(|| format!("failed to open [{}]", path.display()))?;
+                GuestStdin::new(StdinSource::File(Rc::new(file)))
+            }
+            None => GuestStdin::new(StdinSource::Empty),
+        };
+        let load_options = LoadOptions { load_addr: 0, entry: None };
+        loader::load(&elf_path.to_string_lossy(), &mut cpu, String::new(), &load_options)?;
+        cpu.run()
+    })();
+    if let Err(e) = result {
+        failures.push(format!("error: {:#}", e));
+    }
+
+    if let Some(expected) = &expectation.stdout {
+        let actual = cpu.stdout.as_deref().unwrap_or_default();
+        if actual != expected.as_slice() {
+            failures.push(format!(
+                "stdout: expected {:?}, actual {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(actual)
+            ));
+        }
+    }
+    if let Some(expected) = expectation.exit_code {
+        if cpu.exit_code() != Some(expected) {
+            let actual = cpu.exit_code().map_or("none".to_string(), |code| code.to_string());
+            failures.push(format!("exit code: expected {}, actual {}", expected, actual));
+        }
+    }
+    if let Some(expected) = &expectation.ports {
+        let actual: Vec<(u8, u8)> = message_rx.try_iter().filter_map(|m| parse_port_message(&m)).collect();
+        if &actual != expected {
+            failures.push(format!(
+                "ports: expected {}, actual {}",
+                format_ports(expected),
+                format_ports(&actual)
+            ));
+        }
+    }
+
+    Outcome {
+        name: elf_path.display().to_string(),
+        failures,
+        state_sum: cpu.bus.cpu_state_sum,
+        time: start.elapsed(),
+    }
+}
+
+/// Runs the tests on `jobs` threads and returns the outcomes in the order of the tests.
+pub fn run_tests(tests: &[(PathBuf, Expectation)], jobs: usize) -> Vec<Outcome> {
+    let next = Mutex::new(0);
+    let outcomes = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let index = {
+                    let mut next = next.lock().unwrap();
+                    *next += 1;
+                    *next - 1
+                };
+                let Some((elf_path, expectation)) = tests.get(index) else {
+                    break;
+                };
+                let outcome = run_test(elf_path, expectation);
+                outcomes.lock().unwrap().push((index, outcome));
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Runs every test found under the paths, prints the results and writes JUnit XML if `junit` is given.
+/// Fails if a test failed.
+pub fn run(paths: &[PathBuf], jobs: usize, junit: Option<&str>) -> Result<()> {
+    let tests = discover(paths)?;
+    let outcomes = run_tests(&tests, jobs);
+
+    for outcome in &outcomes {
+        if outcome.is_passed() {
+            println!(
+                "PASS {} ({} states, {:.3}s)",
+                outcome.name,
+                outcome.state_sum,
+                outcome.time.as_secs_f64()
+            );
+        } else {
+            println!("FAIL {}", outcome.name);
+            for failure in &outcome.failures {
+                println!("    {}", failure);
+            }
+        }
+    }
+    let failed = outcomes.iter().filter(|o| !o.is_passed()).count();
+    println!("{} tests: {} passed, {} failed", outcomes.len(), outcomes.len() - failed, failed);
+
+    if let Some(path) = junit {
+        std::fs::write(path, junit::to_junit(&outcomes)).with_context(|| format!("failed to write [{}]", path))?;
+    }
+    if failed > 0 {
+        bail!("{} of {} tests failed", failed, outcomes.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover, parse_port_message, run_test, Expectation};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_parse_port_message() {
+        assert_eq!(parse_port_message("ioport:b:fe:1234"), Some((0xb, 0xfe)));
+        assert_eq!(parse_port_message("stdout:ioport"), None);
+    }
+
+    #[test]
+    fn test_run_test() {
+        let tests = discover(&[PathBuf::from("example")]).unwrap();
+        assert!(tests.iter().any(|(path, _)| path == Path::new("example/printf.elf")));
+
+        let mut expectation = Expectation {
+            stdout: Some(b"Hello World\n".to_vec()),
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        let outcome = run_test(Path::new("example/printf.elf"), &expectation);
+        assert!(outcome.is_passed(), "{:?}", outcome.failures);
+        assert_eq!(outcome.state_sum, 24960);
+
+        expectation.exit_code = Some(1);
+        expectation.max_states = Some(1000);
+        let outcome = run_test(Path::new("example/printf.elf"), &expectation);
+        assert_eq!(outcome.failures.len(), 3);
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{bail, Context as _, Result};
+use std::path::{Path, PathBuf};
+
+/// Expected results of a program, read from the sidecar files next to it.
+/// `<name>.expect` has `key = value` lines, `<name>.stdout` is the exact output and `<name>.stdin` is the input.
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectation {
+    pub stdout: Option<Vec<u8>>,
+    pub stdin: Option<PathBuf>,
+    pub exit_code: Option<u32>,
+    // (port, value) written in order
+    pub ports: Option<Vec<(u8, u8)>>,
+    pub max_states: Option<usize>,
+}
+
+fn parse_port(value: &str) -> Result<(u8, u8)> {
+    let Some((port, value)) = value.split_once(':') else {
+        bail!("port must be <port>:<value> in hex");
+    };
+    Ok((u8::from_str_radix(port.trim(), 16)?, u8::from_str_radix(value.trim(), 16)?))
+}
+
+impl Expectation {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut expectation = Expectation::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("line {}: expected <key> = <value>", i + 1);
+            };
+            let value = value.trim();
+            let context = || format!("line {}: invalid {}", i + 1, key.trim());
+            match key.trim() {
+                "exit_code" => expectation.exit_code = Some(value.parse().with_context(context)?),
+                "max_states" => expectation.max_states = Some(value.parse().with_context(context)?),
+                "port" => expectation
+                    .ports
+                    .get_or_insert_with(Vec::new)
+                    .push(parse_port(value).with_context(context)?),
+                key => bail!("line {}: unknown key [{}]", i + 1, key),
+            }
+        }
+        Ok(expectation)
+    }
+
+    /// Reads the sidecar files of the program, or returns None if it has no `.expect` nor `.stdout`.
+    pub fn load(elf_path: &Path) -> Result<Option<Self>> {
+        let expect_path = elf_path.with_extension("expect");
+        let stdout_path = elf_path.with_extension("stdout");
+        if !expect_path.exists() && !stdout_path.exists() {
+            return Ok(None);
+        }
+
+        let mut expectation = match std::fs::read_to_string(&expect_path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("{}", expect_path.display()))?,
+            Err(_) => Expectation::default(),
+        };
+        if stdout_path.exists() {
+            expectation.stdout = Some(std::fs::read(&stdout_path).with_context(|| format!("{}", stdout_path.display()))?);
+        }
+        let stdin_path = elf_path.with_extension("stdin");
+        if stdin_path.exists() {
+            expectation.stdin = Some(stdin_path);
+        }
+        Ok(Some(expectation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expectation;
+
+    #[test]
+    fn test_parse() {
+        let expectation = Expectation::parse("# LED\nexit_code = 3\nmax_states = 1000\nport = b:ff\nport = b : fe\n").unwrap();
+        assert_eq!(
+            expectation,
+            Expectation {
+                exit_code: Some(3),
+                max_states: Some(1000),
+                ports: Some(vec![(0xb, 0xff), (0xb, 0xfe)]),
+                ..Default::default()
+            }
+        );
+        assert!(Expectation::parse("port = b\n").is_err());
+        assert!(Expectation::parse("timeout = 1\n").is_err());
+    }
+}
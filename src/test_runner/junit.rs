@@ -0,0 +1,80 @@
+use super::Outcome;
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\n' | '\t' => escaped.push(c),
+            c if (c as u32) < 0x20 => escaped += &format!("&#x{:x};", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the outcomes as a JUnit XML test suite.
+pub fn to_junit(outcomes: &[Outcome]) -> String {
+    let failures = outcomes.iter().filter(|o| !o.is_passed()).count();
+    let time: f64 = outcomes.iter().map(|o| o.time.as_secs_f64()).sum();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!(
+        "<testsuite name=\"h8-3069f\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        outcomes.len(),
+        failures,
+        time
+    );
+    for outcome in outcomes {
+        let attributes = format!("name=\"{}\" time=\"{:.3}\"", escape_xml(&outcome.name), outcome.time.as_secs_f64());
+        if outcome.is_passed() {
+            xml += &format!("  <testcase {}/>\n", attributes);
+        } else {
+            xml += &format!(
+                "  <testcase {}>\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                attributes,
+                escape_xml(&outcome.failures[0]),
+                escape_xml(&outcome.failures.join("\n"))
+            );
+        }
+    }
+    xml += "</testsuite>\n";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_junit;
+    use crate::test_runner::Outcome;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_junit() {
+        let outcomes = vec![
+            Outcome {
+                name: "example/a.elf".to_string(),
+                failures: vec![],
+                state_sum: 10,
+                time: Duration::from_millis(5),
+            },
+            Outcome {
+                name: "example/b.elf".to_string(),
+                failures: vec!["stdout <differs>".to_string(), "exit code: expected 0, actual 1".to_string()],
+                state_sum: 20,
+                time: Duration::from_millis(10),
+            },
+        ];
+        assert_eq!(
+            to_junit(&outcomes),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"h8-3069f\" tests=\"2\" failures=\"1\" time=\"0.015\">\n  \
+             <testcase name=\"example/a.elf\" time=\"0.005\"/>\n  \
+             <testcase name=\"example/b.elf\" time=\"0.010\">\n    \
+             <failure message=\"stdout &lt;differs&gt;\">stdout &lt;differs&gt;\nexit code: expected 0, actual 1</failure>\n  \
+             </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+}
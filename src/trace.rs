@@ -0,0 +1,424 @@
+use anyhow::{bail, Context as _, Result};
+use std::{collections::VecDeque, fs::File, io::Write, rc::Rc};
+
+pub mod dump;
+
+// File layout (big endian):
+//   header: magic "H8TRACE\0", version: u16, start_state: u64
+//   records: kind: u8, then
+//     Instruction: pc: u24, state: u8, ccr: u8, word count: u8, words: u16 * n,
+//                  changed register mask: u8, registers: u32 * popcount(mask),
+//                  access count: u8, accesses: (flags: u8, addr: u24, bytes: u8 * len)
+//                  flags: bit 7 = write, bit 0..6 = len
+//     Interrupt: vector: u8, pc: u24
+pub const TRACE_MAGIC: &[u8; 8] = b"H8TRACE\0";
+pub const TRACE_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 18;
+
+const KIND_INSTRUCTION: u8 = 0;
+const KIND_INTERRUPT: u8 = 1;
+
+const ACCESS_WRITE: u8 = 0x80;
+const MAX_ACCESS_LEN: usize = 0x7f;
+
+// Flush the streaming buffer to the file every 64KiB
+const FLUSH_SIZE: usize = 0x10000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryAccess {
+    pub addr: u32,
+    pub is_write: bool,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceRecord {
+    Instruction {
+        pc: u32,
+        state: u8,
+        ccr: u8,
+        words: Vec<u16>,
+        registers: Vec<(u8, u32)>,
+        accesses: Vec<MemoryAccess>,
+    },
+    Interrupt {
+        vector: u8,
+        pc: u32,
+    },
+}
+
+impl TraceRecord {
+    pub fn state(&self) -> u8 {
+        match self {
+            TraceRecord::Instruction { state, .. } => *state,
+            TraceRecord::Interrupt { .. } => 0,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            TraceRecord::Instruction {
+                pc,
+                state,
+                ccr,
+                words,
+                registers,
+                accesses,
+            } => {
+                buf.push(KIND_INSTRUCTION);
+                buf.extend_from_slice(&pc.to_be_bytes()[1..]);
+                buf.push(*state);
+                buf.push(*ccr);
+                buf.push(words.len() as u8);
+                for word in words {
+                    buf.extend_from_slice(&word.to_be_bytes());
+                }
+                let mask = registers.iter().fold(0u8, |mask, (i, _)| mask | (1 << i));
+                buf.push(mask);
+                for (_, value) in registers {
+                    buf.extend_from_slice(&value.to_be_bytes());
+                }
+                buf.push(accesses.len() as u8);
+                for access in accesses {
+                    let flags = if access.is_write { ACCESS_WRITE } else { 0 };
+                    buf.push(flags | access.bytes.len() as u8);
+                    buf.extend_from_slice(&access.addr.to_be_bytes()[1..]);
+                    buf.extend_from_slice(&access.bytes);
+                }
+            }
+            TraceRecord::Interrupt { vector, pc } => {
+                buf.push(KIND_INTERRUPT);
+                buf.push(*vector);
+                buf.extend_from_slice(&pc.to_be_bytes()[1..]);
+            }
+        }
+    }
+
+    /// Decodes a record and returns it with the number of bytes consumed.
+    pub fn decode(raw: &[u8]) -> Result<(TraceRecord, usize)> {
+        let mut reader = Reader { raw, pos: 0 };
+        let record = match reader.u8()? {
+            KIND_INSTRUCTION => {
+                let pc = reader.u24()?;
+                let state = reader.u8()?;
+                let ccr = reader.u8()?;
+                let word_count = reader.u8()?;
+                let mut words = Vec::with_capacity(word_count as usize);
+                for _ in 0..word_count {
+                    words.push(reader.u16()?);
+                }
+                let mask = reader.u8()?;
+                let mut registers = Vec::new();
+                for i in 0..8 {
+                    if mask & (1 << i) != 0 {
+                        registers.push((i, reader.u32()?));
+                    }
+                }
+                let access_count = reader.u8()?;
+                let mut accesses = Vec::with_capacity(access_count as usize);
+                for _ in 0..access_count {
+                    let flags = reader.u8()?;
+                    let addr = reader.u24()?;
+                    let bytes = reader.bytes((flags & !ACCESS_WRITE) as usize)?.to_vec();
+                    accesses.push(MemoryAccess {
+                        addr,
+                        is_write: flags & ACCESS_WRITE != 0,
+                        bytes,
+                    });
+                }
+                TraceRecord::Instruction {
+                    pc,
+                    state,
+                    ccr,
+                    words,
+                    registers,
+                    accesses,
+                }
+            }
+            KIND_INTERRUPT => {
+                let vector = reader.u8()?;
+                let pc = reader.u24()?;
+                TraceRecord::Interrupt { vector, pc }
+            }
+            kind => bail!("Invalid trace record kind [{}]", kind),
+        };
+        Ok((record, reader.pos))
+    }
+}
+
+struct Reader<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.raw.len() {
+            bail!("Unexpected end of trace");
+        }
+        let bytes = &self.raw[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Result<u32> {
+        let b = self.bytes(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+pub fn encode_header(start_state: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(TRACE_MAGIC);
+    header.extend_from_slice(&TRACE_VERSION.to_be_bytes());
+    header.extend_from_slice(&start_state.to_be_bytes());
+    header
+}
+
+/// Returns start_state and the records part of the trace.
+pub fn decode_header(raw: &[u8]) -> Result<(u64, &[u8])> {
+    if raw.len() < HEADER_SIZE || &raw[..8] != TRACE_MAGIC {
+        bail!("Not a trace file");
+    }
+    let version = u16::from_be_bytes([raw[8], raw[9]]);
+    if version != TRACE_VERSION {
+        bail!("Unsupported trace version [{}]", version);
+    }
+    let start_state = u64::from_be_bytes(raw[10..18].try_into()?);
+    Ok((start_state, &raw[HEADER_SIZE..]))
+}
+
+/// Merges byte accesses into runs of consecutive addresses.
+pub fn group_accesses(log: &[(u32, u8, bool)]) -> Vec<MemoryAccess> {
+    let mut accesses: Vec<MemoryAccess> = Vec::new();
+    for &(addr, value, is_write) in log {
+        if let Some(last) = accesses.last_mut() {
+            if last.is_write == is_write && last.addr + last.bytes.len() as u32 == addr && last.bytes.len() < MAX_ACCESS_LEN {
+                last.bytes.push(value);
+                continue;
+            }
+        }
+        accesses.push(MemoryAccess {
+            addr,
+            is_write,
+            bytes: vec![value],
+        });
+    }
+    accesses.truncate(u8::MAX as usize);
+    accesses
+}
+
+#[derive(Clone)]
+enum TraceOutput {
+    Stream {
+        // kept open while tracing, the clones of the cpu append to the same file
+        file: Rc<File>,
+        buf: Vec<u8>,
+    },
+    Ring {
+        records: VecDeque<Vec<u8>>,
+        capacity: usize,
+        dropped_state: u64,
+    },
+}
+
+/// Records executed instructions to a trace file.
+#[derive(Clone)]
+pub struct Tracer {
+    path: String,
+    output: TraceOutput,
+    pub(crate) words: Vec<u16>,
+    pub(crate) registers: [u32; 8],
+}
+
+impl Tracer {
+    /// `ring_size`: keep only the last n records and write them in `finish`
+    pub fn new(path: &str, ring_size: Option<usize>) -> Result<Self> {
+        let output = match ring_size {
+            Some(0) => bail!("the trace ring needs at least 1 record"),
+            Some(capacity) => TraceOutput::Ring {
+                records: VecDeque::with_capacity(capacity),
+                capacity,
+                dropped_state: 0,
+            },
+            None => TraceOutput::Stream {
+                file: Rc::new(File::create(path).with_context(|| format!("failed to create trace file [{}]", path))?),
+                buf: encode_header(0),
+            },
+        };
+        Ok(Tracer {
+            path: path.to_string(),
+            output,
+            words: Vec::new(),
+            registers: [0; 8],
+        })
+    }
+
+    /// Called before an instruction is executed.
+    pub fn begin(&mut self, registers: &[u32; 8]) {
+        self.words.clear();
+        self.registers = *registers;
+    }
+
+    pub fn record(&mut self, record: TraceRecord) -> Result<()> {
+        match &mut self.output {
+            TraceOutput::Stream { buf, .. } => {
+                record.encode(buf);
+                if buf.len() >= FLUSH_SIZE {
+                    self.flush()?;
+                }
+            }
+            TraceOutput::Ring {
+                records,
+                capacity,
+                dropped_state,
+            } => {
+                if records.len() == *capacity {
+                    if let Some(raw) = records.pop_front() {
+                        *dropped_state += TraceRecord::decode(&raw)?.0.state() as u64;
+                    }
+                }
+                let mut raw = Vec::new();
+                record.encode(&mut raw);
+                records.push_back(raw);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let TraceOutput::Stream { file, buf } = &mut self.output {
+            file.as_ref()
+                .write_all(buf)
+                .with_context(|| format!("failed to write trace file [{}]", self.path))?;
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining records to the trace file.
+    pub fn finish(&mut self) -> Result<()> {
+        match &self.output {
+            TraceOutput::Stream { .. } => self.flush()?,
+            TraceOutput::Ring {
+                records, dropped_state, ..
+            } => {
+                let mut file = File::create(&self.path).with_context(|| format!("failed to create trace file [{}]", self.path))?;
+                file.write_all(&encode_header(*dropped_state))?;
+                for raw in records {
+                    file.write_all(raw)?;
+                }
+            }
+        }
+        log::info!("Wrote trace [{}]", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_header, encode_header, group_accesses, MemoryAccess, TraceRecord, Tracer, FLUSH_SIZE};
+
+    #[test]
+    fn test_encode_decode_record() {
+        let records = vec![
+            TraceRecord::Instruction {
+                pc: 0x416918,
+                state: 12,
+                ccr: 0x84,
+                words: vec![0x0100, 0x6df6],
+                registers: vec![(7, 0x417fd8)],
+                accesses: vec![MemoryAccess {
+                    addr: 0x417fd8,
+                    is_write: true,
+                    bytes: vec![0, 0, 0, 0],
+                }],
+            },
+            TraceRecord::Interrupt { vector: 36, pc: 0x416a00 },
+        ];
+
+        let mut buf = Vec::new();
+        for record in &records {
+            record.encode(&mut buf);
+        }
+
+        let (first, len) = TraceRecord::decode(&buf).unwrap();
+        assert_eq!(first, records[0]);
+        let (second, len2) = TraceRecord::decode(&buf[len..]).unwrap();
+        assert_eq!(second, records[1]);
+        assert_eq!(len + len2, buf.len());
+    }
+
+    #[test]
+    fn test_decode_truncated_record() {
+        let mut buf = Vec::new();
+        TraceRecord::Interrupt { vector: 36, pc: 0x416a00 }.encode(&mut buf);
+        assert!(TraceRecord::decode(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let mut raw = encode_header(1234);
+        raw.push(0xff);
+        let (start_state, records) = decode_header(&raw).unwrap();
+        assert_eq!(start_state, 1234);
+        assert_eq!(records, &[0xff]);
+        assert!(decode_header(b"H8TRACE").is_err());
+    }
+
+    #[test]
+    fn test_tracer_stream() {
+        let path = std::env::temp_dir().join(format!("trace_test_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(Tracer::new(path, Some(0)).is_err());
+
+        // flushed several times to the open file
+        let mut tracer = Tracer::new(path, None).unwrap();
+        let record = TraceRecord::Interrupt { vector: 36, pc: 0x416a00 };
+        let count = FLUSH_SIZE;
+        for _ in 0..count {
+            tracer.record(record.clone()).unwrap();
+        }
+        tracer.finish().unwrap();
+
+        let raw = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let (_, mut records) = decode_header(&raw).unwrap();
+        let mut decoded = 0;
+        while !records.is_empty() {
+            let (_, len) = TraceRecord::decode(records).unwrap();
+            records = &records[len..];
+            decoded += 1;
+        }
+        assert_eq!(decoded, count);
+    }
+
+    #[test]
+    fn test_group_accesses() {
+        let log = vec![
+            (0xffbf20, 0x01, false),
+            (0xffbf21, 0x02, false),
+            (0xffbf22, 0x03, true),
+            (0xffbf30, 0x04, true),
+        ];
+        let accesses = group_accesses(&log);
+        assert_eq!(accesses.len(), 3);
+        assert_eq!(accesses[0].bytes, vec![0x01, 0x02]);
+        assert_eq!(accesses[1].addr, 0xffbf22);
+        assert!(accesses[2].is_write);
+    }
+}
@@ -0,0 +1,89 @@
+use super::{decode_header, TraceRecord};
+use crate::elf::Symbols;
+use anyhow::{bail, Context as _, Result};
+use std::{io::Write, ops::Range};
+
+pub struct DumpFilter {
+    pub range: Option<Range<u32>>,
+    pub function: Option<String>,
+}
+
+impl DumpFilter {
+    fn to_range(&self, symbols: &Symbols) -> Result<Option<Range<u32>>> {
+        if let Some(name) = &self.function {
+            let Some(symbol) = symbols.find_by_name(name).or_else(|| symbols.find_by_name(&format!("_{}", name))) else {
+                bail!("Symbol not found [{}]", name);
+            };
+            return Ok(Some(symbol.addr..symbol.addr + symbol.size.max(1)));
+        }
+        Ok(self.range.clone())
+    }
+}
+
+pub fn dump(trace_path: &str, symbols: &Symbols, filter: &DumpFilter) -> Result<()> {
+    let raw = std::fs::read(trace_path).with_context(|| format!("failed to read trace [{}]", trace_path))?;
+    let (mut state_sum, mut records) = decode_header(&raw)?;
+    let range = filter.to_range(symbols)?;
+    let mut out = std::io::stdout().lock();
+    let mut last_location = None;
+
+    while !records.is_empty() {
+        let (record, len) = TraceRecord::decode(records)?;
+        records = &records[len..];
+
+        let pc = match &record {
+            TraceRecord::Instruction { pc, .. } => *pc,
+            TraceRecord::Interrupt { pc, .. } => *pc,
+        };
+        if range.as_ref().is_none_or(|range| range.contains(&pc)) {
+            // Print the source location when it changes, like `objdump -l`
+            let location = symbols.format_location(pc);
+            if let Some(location) = location.as_ref().filter(|l| last_location.as_ref() != Some(*l)) {
+                if writeln!(out, "{}:", location).is_err() {
+                    return Ok(());
+                }
+            }
+            last_location = location;
+            if writeln!(out, "{:>12}  {}", state_sum, format_record(&record, symbols)).is_err() {
+                // e.g. closed pipe
+                return Ok(());
+            }
+        }
+        state_sum += record.state() as u64;
+    }
+    Ok(())
+}
+
+fn format_record(record: &TraceRecord, symbols: &Symbols) -> String {
+    match record {
+        TraceRecord::Instruction {
+            pc,
+            state,
+            ccr,
+            words,
+            registers,
+            accesses,
+        } => {
+            let words = words.iter().map(|w| format!("{:04x}", w)).collect::<Vec<String>>().join(" ");
+            let mut line = format!(
+                "{:06x} {:<24} {:<24} {:>3} CCR={:02x}",
+                pc,
+                symbols.format_addr(*pc),
+                words,
+                state,
+                ccr
+            );
+            for (i, value) in registers {
+                line += &format!(" ER{}={:08x}", i, value);
+            }
+            for access in accesses {
+                let bytes = access.bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+                line += &format!(" {}[{:06x}]={}", if access.is_write { "W" } else { "R" }, access.addr, bytes);
+            }
+            line
+        }
+        TraceRecord::Interrupt { vector, pc } => {
+            format!("{:06x} {:<24} interrupt vector {}", pc, symbols.format_addr(*pc), vector)
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use koge29_h8_3069f_emulator::{cpu::Cpu, elf};
+
+#[test]
+fn test_call_loaded_function() {
+    let mut cpu = Cpu::new();
+    elf::load("example/printf.elf", &mut cpu, String::new()).unwrap();
+    cpu.init_registers().unwrap();
+    let hello = cpu.push_data(b"hello\0").unwrap();
+    let (len, _) = cpu.call_function("strlen", &[hello]).unwrap();
+    assert_eq!(len & 0xffff, 5);
+}